@@ -1,30 +1,49 @@
 use cosmwasm_std::{
     attr, ensure_eq, entry_point, from_binary, instantiate2_address, to_binary, Addr, Attribute,
-    Binary, CodeInfoResponse, Coin, Deps, DepsMut, Empty, Env, Event, HexBinary,
-    Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannelCloseMsg, IbcChannelConnectMsg,
+    BankMsg, Binary, CodeInfoResponse, Coin, CosmosMsg, Deps, DepsMut, Empty, Env, Event,
+    HexBinary, Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannelCloseMsg, IbcChannelConnectMsg,
     IbcChannelOpenMsg, IbcChannelOpenResponse, IbcMsg, IbcPacketAckMsg, IbcPacketReceiveMsg,
     IbcPacketTimeoutMsg, IbcReceiveResponse, MessageInfo, Never, Order, QueryRequest,
-    QueryResponse, Response, StdError, StdResult, SystemError, SystemResult, Timestamp, WasmMsg,
-    WasmQuery,
+    QueryResponse, Response, StdError, StdResult, SystemError, SystemResult, Timestamp, Uint128,
+    WasmMsg, WasmQuery,
 };
 use cw_storage_plus::Bound;
 use nois_protocol::{
-    check_order, check_version, InPacket, InPacketAck, OutPacket, OutPacketAck, StdAck,
-    BEACON_PRICE_PACKET_LIFETIME, IBC_APP_VERSION, WELCOME_PACKET_LIFETIME,
+    check_order, check_version, AckErrorCode, InPacket, InPacketAck, OutPacket, OutPacketAck,
+    StdAck, BEACON_PRICE_PACKET_LIFETIME, IBC_APP_VERSION, MAX_REQUEST_BEACON_BATCH_SIZE,
+    WELCOME_PACKET_LIFETIME,
 };
 use sha2::{Digest, Sha256};
 
 use crate::error::ContractError;
+use crate::fee_split::{legacy_payment_msg, split_payment, validate_fee_split};
 use crate::job_id::validate_origin;
 use crate::msg::{
     ConfigResponse, CustomerResponse, CustomersResponse, DrandJobStatsResponse, ExecuteMsg,
-    InstantiateMsg, QueriedCustomer, QueryMsg,
+    InstantiateMsg, ProcessJobsRewardResponse, QueriedCustomer, QueryMsg,
+    UnprocessedDrandRoundsResponse,
 };
 use crate::request_router::{NewDrand, RequestRouter, RoutingReceipt};
 use crate::state::{
-    get_processed_drand_jobs, unprocessed_drand_jobs_len, Config, Customer, CONFIG, CUSTOMERS,
+    backfill_unprocessed_drand_rounds, default_fee_split, get_processed_drand_jobs,
+    unprocessed_drand_jobs_len, Config, Customer, FeeShare, PriceBroadcast, CLOSED_CHANNELS,
+    CONFIG, CUSTOMERS, PRICE_BROADCAST, PROCESS_JOBS_REWARD, PROCESS_JOBS_REWARD_BUDGET,
+    UNPROCESSED_DRAND_ROUNDS,
 };
 
+/// When the balance of a payment contract covers fewer beacons than this after a request,
+/// a low balance event is emitted.
+const LOW_BALANCE_BEACONS: u32 = 100;
+
+/// The default and maximum number of customers `ExecuteMsg::BroadcastPrice` sends the price to
+const BROADCAST_PRICE_DEFAULT_LIMIT: u32 = 50;
+const BROADCAST_PRICE_MAX_LIMIT: u32 = 100;
+
+/// The minimum number of blocks a round must have had unprocessed jobs for before
+/// `ExecuteMsg::ProcessJobs` pays a reward for it. This leaves the jobs to the regular
+/// submissions first and makes queueing jobs just to collect the reward unattractive.
+const PROCESS_JOBS_REWARD_MIN_AGE: u64 = 10;
+
 #[entry_point]
 pub fn instantiate(
     deps: DepsMut,
@@ -52,16 +71,18 @@ pub fn instantiate(
         payment_code_id,
         payment_initial_funds,
         sink,
+        fee_split: default_fee_split(),
     };
     CONFIG.save(deps.storage, &config)?;
     Ok(Response::default())
 }
 
-// This no-op migrate implementation allows us to upgrade within the 0.7 series.
-// No state changes expected.
+// Adds rounds with jobs queued before the `UNPROCESSED_DRAND_ROUNDS` index existed to the index.
+// Running this again is a no-op.
 #[entry_point]
-pub fn migrate(_deps: DepsMut, _env: Env, _msg: Empty) -> StdResult<Response> {
-    Ok(Response::default())
+pub fn migrate(deps: DepsMut, env: Env, _msg: Empty) -> StdResult<Response> {
+    let backfilled = backfill_unprocessed_drand_rounds(deps.storage, env.block.height)?;
+    Ok(Response::new().add_attribute("backfilled_unprocessed_rounds", backfilled.to_string()))
 }
 
 #[entry_point]
@@ -82,6 +103,7 @@ pub fn execute(
             price,
             drand_addr,
             payment_initial_funds,
+            fee_split,
         } => execute_set_config(
             deps,
             info,
@@ -90,7 +112,22 @@ pub fn execute(
             price,
             drand_addr,
             payment_initial_funds,
+            fee_split,
         ),
+        ExecuteMsg::ProcessJobs { round, max } => execute_process_jobs(deps, env, info, round, max),
+        ExecuteMsg::BroadcastPrice { limit } => execute_broadcast_price(deps, env, limit),
+        ExecuteMsg::MigratePaymentContracts {
+            code_id,
+            start_after,
+            limit,
+        } => execute_migrate_payment_contracts(deps, info, code_id, start_after, limit),
+        ExecuteMsg::SetCustomerPrice { channel_id, price } => {
+            execute_set_customer_price(deps, env, info, channel_id, price)
+        }
+        ExecuteMsg::SetProcessJobsReward { reward } => {
+            execute_set_process_jobs_reward(deps, info, reward)
+        }
+        ExecuteMsg::FundProcessJobsReward {} => execute_fund_process_jobs_reward(deps, info),
     }
 }
 
@@ -99,6 +136,15 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
     let response = match msg {
         QueryMsg::Config {} => to_binary(&query_config(deps)?)?,
         QueryMsg::DrandJobStats { round } => to_binary(&query_drand_job_stats(deps, round)?)?,
+        QueryMsg::UnprocessedDrandRounds { start_after, limit } => {
+            to_binary(&query_unprocessed_drand_rounds(deps, start_after, limit)?)?
+        }
+        QueryMsg::ProcessJobsReward {} => to_binary(&ProcessJobsRewardResponse {
+            reward: PROCESS_JOBS_REWARD.may_load(deps.storage)?,
+            budget: PROCESS_JOBS_REWARD_BUDGET
+                .may_load(deps.storage)?
+                .unwrap_or_default(),
+        })?,
         QueryMsg::Customer { channel_id } => to_binary(&query_customer(deps, channel_id)?)?,
         QueryMsg::Customers { start_after, limit } => {
             to_binary(&query_customers(deps, start_after, limit)?)?
@@ -123,6 +169,22 @@ fn query_drand_job_stats(deps: Deps, round: u64) -> StdResult<DrandJobStatsRespo
     })
 }
 
+fn query_unprocessed_drand_rounds(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<UnprocessedDrandRoundsResponse> {
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.map(Bound::exclusive);
+
+    let rounds = UNPROCESSED_DRAND_ROUNDS
+        .keys(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|round| query_drand_job_stats(deps, round?))
+        .collect::<StdResult<_>>()?;
+    Ok(UnprocessedDrandRoundsResponse { rounds })
+}
+
 fn query_customer(deps: Deps, channel_id: String) -> StdResult<CustomerResponse> {
     let customer = CUSTOMERS.may_load(deps.storage, &channel_id)?;
     Ok(CustomerResponse {
@@ -209,6 +271,8 @@ pub fn ibc_channel_connect(
     let customer = Customer {
         payment: address,
         requested_beacons: 0,
+        price: None,
+        payment_code_id: Some(config.payment_code_id),
     };
     CUSTOMERS.save(deps.storage, &chan_id, &customer)?;
 
@@ -218,7 +282,7 @@ pub fn ibc_channel_connect(
         vec![]
     };
     let instantiate_payment = WasmMsg::Instantiate2 {
-        admin: Some(env.contract.address.into()), // Only gateway can update the contracts it created
+        admin: Some(env.contract.address.to_string()), // Only gateway can update the contracts it created
         code_id: config.payment_code_id,
         label: format!("For {chan_id}"),
         msg: to_binary(&nois_payment::msg::InstantiateMsg {
@@ -236,19 +300,7 @@ pub fn ibc_channel_connect(
         })?,
         timeout: env.block.time.plus_seconds(WELCOME_PACKET_LIFETIME).into(),
     };
-    let beacon_price = IbcMsg::SendPacket {
-        channel_id: chan_id.clone(),
-        data: to_binary(&OutPacket::PushBeaconPrice {
-            timestamp: env.block.time,
-            amount: config.price.amount,
-            denom: config.price.denom,
-        })?,
-        timeout: env
-            .block
-            .time
-            .plus_seconds(BEACON_PRICE_PACKET_LIFETIME)
-            .into(),
-    };
+    let beacon_price = push_beacon_price_msg(&env, chan_id.clone(), config.price)?;
 
     Ok(IbcBasicResponse::new()
         .add_message(instantiate_payment)
@@ -261,13 +313,14 @@ pub fn ibc_channel_connect(
 
 #[entry_point]
 pub fn ibc_channel_close(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     msg: IbcChannelCloseMsg,
 ) -> StdResult<IbcBasicResponse> {
     let channel = msg.channel();
-    // get contract address and remove lookup
+    // The customer is kept but no packets can be sent to it anymore
     let channel_id = channel.endpoint.channel_id.as_str();
+    CLOSED_CHANNELS.save(deps.storage, channel_id, &Empty {})?;
 
     Ok(IbcBasicResponse::new()
         .add_attribute("action", "ibc_close")
@@ -287,23 +340,49 @@ pub fn ibc_packet_receive(
     let channel_id = packet.dest.channel_id;
 
     // put this in a closure so we can convert all error responses into acknowledgements
+    let channel = channel_id.clone();
     (|| {
         let op: InPacket = from_binary(&packet.data)?;
         match op {
             InPacket::RequestBeacon { after, origin } => {
                 receive_request_beacon(deps, env, channel_id, relayer, after, origin)
             }
-            InPacket::PullBeaconPrice {} => receive_pull_beacon_price(deps, env),
+            InPacket::RequestBeaconBatch { after, origins } => {
+                receive_request_beacon_batch(deps, env, channel_id, relayer, after, origins)
+            }
+            InPacket::PullBeaconPrice {} => receive_pull_beacon_price(deps, env, channel_id),
             _ => Err(ContractError::UnsupportedPacketType),
         }
     })()
     .or_else(|e| {
         // we try to capture all app-level errors and convert them into
         // acknowledgement packets that contain an error code.
-        let acknowledgement = StdAck::error(format!("Error processing packet: {e}"));
-        Ok(IbcReceiveResponse::new()
+        let message = format!("Error processing packet: {e}");
+        let acknowledgement = match e {
+            ContractError::InsufficientPaymentBalance { .. } => {
+                StdAck::error_with_code(AckErrorCode::InsufficientPaymentBalance, message)
+            }
+            _ => StdAck::error(message),
+        };
+        let mut res = IbcReceiveResponse::new()
             .set_ack(acknowledgement)
-            .add_event(Event::new("ibc").add_attribute("packet", "receive")))
+            .add_event(Event::new("ibc").add_attribute("packet", "receive"));
+        // Allow operators to notify customers about rejected requests
+        if let ContractError::InsufficientPaymentBalance {
+            payment,
+            balance,
+            required,
+        } = e
+        {
+            res = res.add_event(
+                Event::new("nois-payment-balance-insufficient")
+                    .add_attribute("channel_id", channel)
+                    .add_attribute("payment", payment)
+                    .add_attribute("balance", balance.to_string())
+                    .add_attribute("required", required.to_string()),
+            );
+        }
+        Ok(res)
     })
 }
 
@@ -316,6 +395,7 @@ fn receive_request_beacon(
     origin: Binary,
 ) -> Result<IbcReceiveResponse, ContractError> {
     validate_origin(&origin)?;
+    let low_balance = check_payment_balance(deps.as_ref(), &channel_id, 1)?;
 
     let router = RequestRouter::new();
     let RoutingReceipt {
@@ -324,38 +404,141 @@ fn receive_request_beacon(
     } = router.route(deps.branch(), env, channel_id.clone(), after, origin)?;
 
     // Pay time
-    let mut customer = CUSTOMERS.load(deps.storage, &channel_id)?;
-    customer.requested_beacons += 1;
-    CUSTOMERS.save(deps.storage, &channel_id, &customer)?;
+    msgs.push(pay_for_beacons(deps, &channel_id, relayer, 1)?);
+
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_messages(msgs)
+        .add_events(low_balance)
+        .add_attribute("action", "receive_request_beacon"))
+}
+
+/// Routes every origin of the batch like a single beacon request. Since all jobs
+/// share the same `after` value, they also share the same acknowledgement.
+fn receive_request_beacon_batch(
+    mut deps: DepsMut,
+    env: Env,
+    channel_id: String,
+    relayer: Addr,
+    after: Timestamp,
+    origins: Vec<Binary>,
+) -> Result<IbcReceiveResponse, ContractError> {
+    if origins.is_empty() || origins.len() > MAX_REQUEST_BEACON_BATCH_SIZE {
+        return Err(ContractError::InvalidBatchSize);
+    }
+    for origin in &origins {
+        validate_origin(origin)?;
+    }
+
+    let count = origins.len() as u32;
+    let low_balance = check_payment_balance(deps.as_ref(), &channel_id, count)?;
+    let router = RequestRouter::new();
+    let mut acknowledgement = None;
+    let mut msgs = Vec::<CosmosMsg>::new();
+    for origin in origins {
+        let receipt = router.route(
+            deps.branch(),
+            env.clone(),
+            channel_id.clone(),
+            after,
+            origin,
+        )?;
+        acknowledgement.get_or_insert(receipt.acknowledgement);
+        msgs.extend(receipt.msgs);
+    }
+
+    // Pay time
+    msgs.push(pay_for_beacons(deps, &channel_id, relayer, count)?);
+
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement.expect("origins must not be empty"))
+        .add_messages(msgs)
+        .add_events(low_balance)
+        .add_attribute("action", "receive_request_beacon_batch")
+        .add_attribute("count", count.to_string()))
+}
+
+/// Ensures the customer's payment contract can pay for `count` beacons. Returns an event
+/// if the remaining balance covers less than [`LOW_BALANCE_BEACONS`] beacons afterwards.
+///
+/// The balance is checked when the request packet arrives. A payment relayed after the
+/// request, e.g. the ICS-20 transfer sent along with it, does not count. Such requests are
+/// rejected with [`AckErrorCode::InsufficientPaymentBalance`] and never retried by the
+/// gateway. The proxy can match the code and keeps the payment for later requests.
+fn check_payment_balance(
+    deps: Deps,
+    channel_id: &str,
+    count: u32,
+) -> Result<Option<Event>, ContractError> {
+    let customer = CUSTOMERS.load(deps.storage, channel_id)?;
+    let Coin { amount, denom } = customer.beacon_price(&CONFIG.load(deps.storage)?);
+
+    let required = amount * Uint128::from(count);
+    let balance = deps.querier.query_balance(&customer.payment, &denom)?;
+    if balance.amount < required {
+        return Err(ContractError::InsufficientPaymentBalance {
+            payment: customer.payment.into(),
+            balance,
+            required: Coin::new(required.u128(), denom),
+        });
+    }
+
+    let remaining = balance.amount - required;
+    if remaining >= amount * Uint128::from(LOW_BALANCE_BEACONS) {
+        return Ok(None);
+    }
+    Ok(Some(
+        Event::new("nois-payment-balance-low")
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("payment", customer.payment)
+            .add_attribute("balance", Coin::new(remaining.u128(), denom).to_string()),
+    ))
+}
+
+/// Updates the customer's request counter and creates the message to pay for
+/// `count` beacons from the customer's payment contract.
+fn pay_for_beacons(
+    deps: DepsMut,
+    channel_id: &str,
+    relayer: Addr,
+    count: u32,
+) -> Result<CosmosMsg, ContractError> {
+    let mut customer = CUSTOMERS.load(deps.storage, channel_id)?;
+    customer.requested_beacons += count as u64;
+    CUSTOMERS.save(deps.storage, channel_id, &customer)?;
 
     let config = CONFIG.load(deps.storage)?;
 
-    let Coin { amount, denom } = config.price;
-    let amount_burn = amount.mul_floor((50u128, 100)); // 50%
-    let amount_relayer = amount.mul_floor((5u128, 100)); // 5%
-    let amount_rest = amount - amount_burn - amount_relayer; // 45%
+    let Coin { amount, denom } = customer.beacon_price(&config);
+    let total = Coin {
+        amount: amount * Uint128::from(count),
+        denom,
+    };
+    let msg = match customer.payment_code_id {
+        Some(_) => to_binary(&nois_payment::msg::ExecuteMsg::Pay {
+            payments: split_payment(total, &config.fee_split, &relayer),
+        })?,
+        // Not yet migrated via `ExecuteMsg::MigratePaymentContracts`
+        None => to_binary(&legacy_payment_msg(total, &relayer))?,
+    };
 
     let msg = WasmMsg::Execute {
         contract_addr: customer.payment.into(),
-        msg: to_binary(&nois_payment::msg::ExecuteMsg::Pay {
-            burn: Coin::new(amount_burn.u128(), &denom),
-            relayer: (relayer.into(), Coin::new(amount_relayer.u128(), &denom)),
-            community_pool: Coin::new(amount_rest.u128(), denom),
-        })?,
+        msg,
         funds: vec![],
     };
-    msgs.push(msg.into());
-
-    Ok(IbcReceiveResponse::new()
-        .set_ack(acknowledgement)
-        .add_messages(msgs)
-        .add_attribute("action", "receive_request_beacon"))
+    Ok(msg.into())
 }
 
-fn receive_pull_beacon_price(deps: DepsMut, env: Env) -> Result<IbcReceiveResponse, ContractError> {
+fn receive_pull_beacon_price(
+    deps: DepsMut,
+    env: Env,
+    channel_id: String,
+) -> Result<IbcReceiveResponse, ContractError> {
     let config = CONFIG.load(deps.storage)?;
+    let customer = CUSTOMERS.load(deps.storage, &channel_id)?;
 
-    let Coin { amount, denom } = config.price;
+    let Coin { amount, denom } = customer.beacon_price(&config);
     let ack = StdAck::success(&InPacketAck::PullBeaconPrice {
         timestamp: env.block.time,
         amount,
@@ -423,9 +606,11 @@ fn execute_add_verified_round(
     let NewDrand {
         msgs,
         jobs_processed,
+        jobs_dropped,
         jobs_left,
     } = router.new_drand(deps, env, round, &randomness, is_verifying_tx)?;
     attributes.push(Attribute::new("jobs_processed", jobs_processed.to_string()));
+    attributes.push(Attribute::new("jobs_dropped", jobs_dropped.to_string()));
     attributes.push(Attribute::new("jobs_left", jobs_left.to_string()));
 
     Ok(Response::new()
@@ -433,11 +618,111 @@ fn execute_add_verified_round(
         .add_attributes(attributes))
 }
 
+fn execute_process_jobs(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    round: u64,
+    max: u32,
+) -> Result<Response, ContractError> {
+    // Load before processing since the round is removed from the index once all jobs are done
+    let queued_since = UNPROCESSED_DRAND_ROUNDS.may_load(deps.storage, round)?;
+    let height = env.block.height;
+
+    let router = RequestRouter::new();
+    let NewDrand {
+        msgs,
+        jobs_processed,
+        jobs_dropped,
+        jobs_left,
+    } = router
+        .process_jobs(deps.branch(), env, round, max)?
+        .ok_or(ContractError::RoundNotAdded { round })?;
+
+    let mut res = Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "process_jobs")
+        .add_attribute("jobs_processed", jobs_processed.to_string())
+        .add_attribute("jobs_dropped", jobs_dropped.to_string())
+        .add_attribute("jobs_left", jobs_left.to_string());
+
+    // Pay the reward as far as the reward budget allows
+    let reward_due =
+        matches!(queued_since, Some(since) if height >= since + PROCESS_JOBS_REWARD_MIN_AGE);
+    if let (true, Some(reward)) = (reward_due, PROCESS_JOBS_REWARD.may_load(deps.storage)?) {
+        let budget = PROCESS_JOBS_REWARD_BUDGET
+            .may_load(deps.storage)?
+            .unwrap_or_default();
+        let amount = std::cmp::min(reward.amount * Uint128::from(jobs_processed), budget);
+        if !amount.is_zero() {
+            PROCESS_JOBS_REWARD_BUDGET.save(deps.storage, &(budget - amount))?;
+            let reward = Coin::new(amount.u128(), reward.denom);
+            res = res
+                .add_attribute("reward", reward.to_string())
+                .add_message(BankMsg::Send {
+                    to_address: info.sender.into(),
+                    amount: vec![reward],
+                });
+        }
+    }
+    Ok(res)
+}
+
+fn execute_set_process_jobs_reward(
+    deps: DepsMut,
+    info: MessageInfo,
+    reward: Option<Coin>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+
+    // The budget is kept in the reward denom. When the denom changes or the reward is
+    // removed, the remaining budget goes back to the gateway's general balance.
+    let previous_denom = PROCESS_JOBS_REWARD.may_load(deps.storage)?.map(|r| r.denom);
+    if previous_denom != reward.as_ref().map(|r| r.denom.clone()) {
+        PROCESS_JOBS_REWARD_BUDGET.remove(deps.storage);
+    }
+    match &reward {
+        Some(reward) => PROCESS_JOBS_REWARD.save(deps.storage, reward)?,
+        None => PROCESS_JOBS_REWARD.remove(deps.storage),
+    }
+    Ok(Response::new()
+        .add_attribute("action", "set_process_jobs_reward")
+        .add_attribute("reward", reward.map(|r| r.to_string()).unwrap_or_default()))
+}
+
+fn execute_fund_process_jobs_reward(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let reward = PROCESS_JOBS_REWARD
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoProcessJobsReward)?;
+    let amount = match info.funds.as_slice() {
+        [coin] if coin.denom == reward.denom && !coin.amount.is_zero() => coin.amount,
+        _ => {
+            return Err(ContractError::InvalidProcessJobsRewardFunds {
+                denom: reward.denom,
+            })
+        }
+    };
+
+    let budget = PROCESS_JOBS_REWARD_BUDGET
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + amount;
+    PROCESS_JOBS_REWARD_BUDGET.save(deps.storage, &budget)?;
+    Ok(Response::new()
+        .add_attribute("action", "fund_process_jobs_reward")
+        .add_attribute("budget", Coin::new(budget.u128(), reward.denom).to_string()))
+}
+
 /// In order not to fall in the chicken egg problem where you need
 /// to instantiate two or more contracts that need to be aware of each other
 /// in a context where the contract addresses generration is not known
 /// in advance, we set the contract address at a later stage after the
 /// instantation and make sure it is immutable once set
+#[allow(clippy::too_many_arguments)]
 fn execute_set_config(
     deps: DepsMut,
     info: MessageInfo,
@@ -446,6 +731,7 @@ fn execute_set_config(
     price: Option<Coin>,
     drand: Option<String>,
     payment_initial_funds: Option<Coin>,
+    fee_split: Option<Vec<FeeShare>>,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
 
@@ -460,26 +746,194 @@ fn execute_set_config(
         Some(dr) => Some(deps.api.addr_validate(&dr)?),
         None => config.drand,
     };
+    let price_changed = price.as_ref().is_some_and(|p| *p != config.price);
     let price = price.unwrap_or(config.price);
     let payment_initial_funds = match payment_initial_funds {
         Some(pif) => Some(pif),
         None => config.payment_initial_funds,
     };
+    let fee_split = match fee_split {
+        Some(fs) => {
+            validate_fee_split(deps.api, &fs)?;
+            fs
+        }
+        None => config.fee_split,
+    };
 
     let new_config = Config {
         manager,
         drand,
         price,
-        payment_code_id: config.payment_code_id, // Use a separate message for this in order to migrate all existing contracts too
+        payment_code_id: config.payment_code_id, // Use `ExecuteMsg::MigratePaymentContracts` in order to migrate all existing contracts too
         payment_initial_funds,
         sink: config.sink, // Make updatable?
+        fee_split,
     };
 
     CONFIG.save(deps.storage, &new_config)?;
 
+    if price_changed {
+        // The new price is sent to the customers via `ExecuteMsg::BroadcastPrice`.
+        // A broadcast in progress starts over such that all customers get the latest price.
+        PRICE_BROADCAST.save(
+            deps.storage,
+            &PriceBroadcast {
+                next_start_after: None,
+            },
+        )?;
+    }
     Ok(Response::default())
 }
 
+fn execute_broadcast_price(
+    deps: DepsMut,
+    env: Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let PriceBroadcast { next_start_after } = PRICE_BROADCAST
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoPriceBroadcastPending)?;
+    let limit = limit
+        .unwrap_or(BROADCAST_PRICE_DEFAULT_LIMIT)
+        .min(BROADCAST_PRICE_MAX_LIMIT) as usize;
+
+    let low_bound = next_start_after.as_deref().map(Bound::exclusive);
+    // Load one more to find out if there are more customers
+    let customers = CUSTOMERS
+        .range(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit + 1)
+        .collect::<StdResult<Vec<_>>>()?;
+    let has_more = customers.len() > limit;
+
+    let mut msgs = Vec::<IbcMsg>::with_capacity(limit);
+    let mut last = None;
+    for (channel_id, customer) in customers.into_iter().take(limit) {
+        // Sending to a closed channel would fail the whole page
+        if !CLOSED_CHANNELS.has(deps.storage, &channel_id) {
+            let price = customer.beacon_price(&config);
+            msgs.push(push_beacon_price_msg(&env, channel_id.clone(), price)?);
+        }
+        last = Some(channel_id);
+    }
+
+    let mut res = Response::new()
+        .add_attribute("action", "broadcast_price")
+        .add_attribute("count", msgs.len().to_string())
+        .add_messages(msgs);
+    match (has_more, last) {
+        (true, Some(last)) => {
+            PRICE_BROADCAST.save(
+                deps.storage,
+                &PriceBroadcast {
+                    next_start_after: Some(last.clone()),
+                },
+            )?;
+            res = res.add_attribute("next_start_after", last);
+        }
+        _ => PRICE_BROADCAST.remove(deps.storage),
+    }
+    Ok(res)
+}
+
+fn execute_migrate_payment_contracts(
+    deps: DepsMut,
+    info: MessageInfo,
+    code_id: u64,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+    ensure_code_id_exists(deps.as_ref(), code_id)?;
+
+    // New payment contracts are created with the new code
+    config.payment_code_id = code_id;
+    CONFIG.save(deps.storage, &config)?;
+
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.as_deref().map(Bound::exclusive);
+    // Load one more to find out if there are more customers
+    let customers = CUSTOMERS
+        .range(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit + 1)
+        .collect::<StdResult<Vec<_>>>()?;
+    let has_more = customers.len() > limit;
+
+    let mut msgs = Vec::<WasmMsg>::with_capacity(limit);
+    let mut last = None;
+    for (channel_id, mut customer) in customers.into_iter().take(limit) {
+        customer.payment_code_id = Some(code_id);
+        CUSTOMERS.save(deps.storage, &channel_id, &customer)?;
+        msgs.push(WasmMsg::Migrate {
+            contract_addr: customer.payment.into(),
+            new_code_id: code_id,
+            msg: to_binary(&Empty {})?,
+        });
+        last = Some(channel_id);
+    }
+
+    let mut res = Response::new()
+        .add_attribute("action", "migrate_payment_contracts")
+        .add_attribute("code_id", code_id.to_string())
+        .add_attribute("count", msgs.len().to_string())
+        .add_messages(msgs);
+    if let (true, Some(last)) = (has_more, last) {
+        res = res.add_attribute("next_start_after", last);
+    }
+    Ok(res)
+}
+
+fn execute_set_customer_price(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    price: Option<Coin>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(info.sender, config.manager, ContractError::Unauthorized);
+
+    let mut customer = CUSTOMERS
+        .may_load(deps.storage, &channel_id)?
+        .ok_or_else(|| ContractError::CustomerNotFound {
+            channel_id: channel_id.clone(),
+        })?;
+    customer.price = price;
+    CUSTOMERS.save(deps.storage, &channel_id, &customer)?;
+
+    let price = customer.beacon_price(&config);
+    let mut res = Response::new();
+    if !CLOSED_CHANNELS.has(deps.storage, &channel_id) {
+        res = res.add_message(push_beacon_price_msg(
+            &env,
+            channel_id.clone(),
+            price.clone(),
+        )?);
+    }
+    Ok(res
+        .add_attribute("action", "set_customer_price")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("price", price.to_string()))
+}
+
+/// Creates the packet that informs the proxy on the other end of the channel about its beacon price
+fn push_beacon_price_msg(env: &Env, channel_id: String, price: Coin) -> StdResult<IbcMsg> {
+    Ok(IbcMsg::SendPacket {
+        channel_id,
+        data: to_binary(&OutPacket::PushBeaconPrice {
+            timestamp: env.block.time,
+            amount: price.amount,
+            denom: price.denom,
+        })?,
+        timeout: env
+            .block
+            .time
+            .plus_seconds(BEACON_PRICE_PACKET_LIFETIME)
+            .into(),
+    })
+}
+
 fn ensure_code_id_exists(deps: Deps, code_id: u64) -> Result<(), ContractError> {
     let query = to_binary(&QueryRequest::<Empty>::Wasm(WasmQuery::CodeInfo {
         code_id,
@@ -507,6 +961,7 @@ mod tests {
     use crate::msg::ExecuteMsg;
 
     use super::*;
+    use crate::fee_split::LegacyPaymentExecuteMsg;
     use cosmwasm_std::testing::{
         self, mock_env, mock_ibc_channel_close_init, mock_ibc_channel_connect_confirm,
         mock_ibc_channel_open_try, mock_ibc_packet_ack, mock_ibc_packet_recv, mock_info, MockApi,
@@ -519,6 +974,11 @@ mod tests {
     };
     use nois_protocol::{APP_ORDER, BAD_APP_ORDER};
 
+    use crate::state::{
+        unprocessed_drand_jobs_dequeue, unprocessed_drand_jobs_enqueue, FeeDestination, Job,
+    };
+    use cosmwasm_std::Storage;
+
     const CREATOR: &str = "creator";
     const MANAGER: &str = "boss";
     const MANAGER2: &str = "boss2";
@@ -714,6 +1174,9 @@ mod tests {
 
     fn mock_dependencies() -> OwnedDeps<MockStorage, MockApi, MockQuerier, Empty> {
         let mut deps = testing::mock_dependencies();
+        // Funds of the payment contracts created in tests
+        deps.querier
+            .update_balance("some payment address", vec![coin(1_000, "unois")]);
         deps.querier
             .update_wasm(Box::from(|request: &WasmQuery| -> QuerierResult {
                 match request {
@@ -792,6 +1255,7 @@ mod tests {
                 payment_code_id: PAYMENT,
                 payment_initial_funds: None,
                 sink: Addr::unchecked(SINK),
+                fee_split: default_fee_split(),
             }
         );
 
@@ -818,6 +1282,7 @@ mod tests {
                 payment_code_id: PAYMENT,
                 payment_initial_funds: payment_initial(),
                 sink: Addr::unchecked(SINK),
+                fee_split: default_fee_split(),
             }
         );
     }
@@ -866,6 +1331,7 @@ mod tests {
             price: Some(Coin::new(123, "unois")),
             drand_addr: Some("somewhere".to_string()),
             payment_initial_funds: Some(Coin::new(500, "unois")),
+            fee_split: None,
         };
 
         // Fails for incorrect manager
@@ -895,8 +1361,58 @@ mod tests {
                 payment_code_id: PAYMENT,
                 payment_initial_funds: Some(Coin::new(500, "unois")),
                 sink: Addr::unchecked(SINK),
+                fee_split: default_fee_split(),
             }
-        )
+        );
+
+        // Fee split must sum up to 100 %
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            price: None,
+            drand_addr: None,
+            payment_initial_funds: None,
+            fee_split: Some(vec![FeeShare {
+                destination: FeeDestination::Burn {},
+                percent: 99,
+            }]),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(MANAGER2, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFeeSplit));
+
+        let fee_split = vec![
+            FeeShare {
+                destination: FeeDestination::Burn {},
+                percent: 80,
+            },
+            FeeShare {
+                destination: FeeDestination::Relayer {},
+                percent: 20,
+            },
+        ];
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            price: None,
+            drand_addr: None,
+            payment_initial_funds: None,
+            fee_split: Some(fee_split.clone()),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(MANAGER2, &[]), msg).unwrap();
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.fee_split, fee_split);
+    }
+
+    #[test]
+    fn config_without_fee_split_loads_default() {
+        let mut deps = mock_dependencies();
+        // Config as stored before the fee split was added
+        deps.storage.set(
+            b"config",
+            br#"{"drand":null,"manager":"boss","price":{"denom":"unois","amount":"1"},"payment_code_id":1,"payment_initial_funds":null,"sink":"sink"}"#,
+        );
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.fee_split, default_fee_split());
     }
 
     #[test]
@@ -930,6 +1446,7 @@ mod tests {
             price: None,
             drand_addr: Some(DRAND.to_string()),
             payment_initial_funds: None,
+            fee_split: None,
         };
         let _res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
 
@@ -965,9 +1482,12 @@ mod tests {
             price: None,
             drand_addr: Some(DRAND.to_string()),
             payment_initial_funds: None,
+            fee_split: None,
         };
         let _res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
 
+        connect(deps.as_mut(), "foo");
+
         // Create one job
         let msg = mock_ibc_packet_recv(
             "foo",
@@ -1103,57 +1623,320 @@ mod tests {
         assert_eq!(jobs_left, "0");
     }
 
-    //
-    // Query tests
-    //
-
     #[test]
-    fn query_job_stats_works() {
-        let mut deps = mock_dependencies();
-
-        let info = mock_info("creator", &[]);
-        let msg = InstantiateMsg {
-            manager: MANAGER.to_string(),
-            price: Coin::new(1, "unois"),
-            payment_code_id: PAYMENT,
-            payment_initial_funds: payment_initial(),
-            sink: SINK.to_string(),
-        };
-        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-
+    fn process_jobs_works() {
+        let mut deps = setup();
         const DRAND: &str = "drand_verifier_7";
-
-        // Set drand contract
         let msg = ExecuteMsg::SetConfig {
             manager: None,
             price: None,
             drand_addr: Some(DRAND.to_string()),
             payment_initial_funds: None,
+            fee_split: None,
         };
-        let _res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        connect(deps.as_mut(), "foo");
 
-        fn job_stats(deps: Deps, round: u64) -> DrandJobStatsResponse {
-            from_binary(&query(deps, mock_env(), QueryMsg::DrandJobStats { round }).unwrap())
-                .unwrap()
+        // Create 6 jobs
+        for i in 0..6 {
+            let msg = mock_ibc_packet_recv(
+                "foo",
+                &InPacket::RequestBeacon {
+                    after: AFTER1,
+                    origin: origin(i),
+                },
+            )
+            .unwrap();
+            ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
         }
-
-        // No jobs by default
+        let msg = QueryMsg::UnprocessedDrandRounds {
+            start_after: None,
+            limit: None,
+        };
+        let UnprocessedDrandRoundsResponse { rounds } =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
         assert_eq!(
-            job_stats(deps.as_ref(), ROUND1),
-            DrandJobStatsResponse {
+            rounds,
+            vec![DrandJobStatsResponse {
                 round: ROUND1,
+                unprocessed: 6,
                 processed: 0,
-                unprocessed: 0,
-            }
+            }]
         );
 
-        // Create one job
-        let msg = mock_ibc_packet_recv(
-            "foo",
-            &InPacket::RequestBeacon {
-                after: AFTER1,
-                origin: origin(1),
-            },
+        // Round must be added first
+        let msg = ExecuteMsg::ProcessJobs {
+            round: ROUND1,
+            max: 10,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RoundNotAdded { round: ROUND1 }
+        ));
+
+        // The submission processes 2 jobs
+        let add_round = make_add_verified_round_msg(ROUND1, true);
+        execute(deps.as_mut(), mock_env(), mock_info(DRAND, &[]), add_round).unwrap();
+
+        // Only the manager can set the reward
+        let set_reward = ExecuteMsg::SetProcessJobsReward {
+            reward: Some(coin(100, "unois")),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            set_reward.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(MANAGER, &[]),
+            set_reward,
+        )
+        .unwrap();
+
+        // The budget must be funded in the reward denom
+        let fund = ExecuteMsg::FundProcessJobsReward {};
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[coin(250, "uatom")]),
+            fund.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidProcessJobsRewardFunds { .. }
+        ));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[coin(250, "unois")]),
+            fund,
+        )
+        .unwrap();
+        // Funds for other purposes are not used for rewards
+        deps.querier
+            .update_balance(testing::MOCK_CONTRACT_ADDR, vec![coin(10250, "unois")]);
+
+        // Anyone can process the rest. No reward for jobs queued only recently.
+        let msg = ExecuteMsg::ProcessJobs {
+            round: ROUND1,
+            max: 1,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(first_attr(&res.attributes, "jobs_processed").unwrap(), "1");
+        assert_eq!(first_attr(&res.attributes, "jobs_left").unwrap(), "3");
+        assert_eq!(first_attr(&res.attributes, "reward"), None);
+        assert_eq!(res.messages.len(), 1);
+
+        let mut env = mock_env();
+        env.block.height += PROCESS_JOBS_REWARD_MIN_AGE;
+        let msg = ExecuteMsg::ProcessJobs {
+            round: ROUND1,
+            max: 2,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(first_attr(&res.attributes, "jobs_processed").unwrap(), "2");
+        assert_eq!(first_attr(&res.attributes, "jobs_left").unwrap(), "1");
+        assert_eq!(res.messages.len(), 3);
+        assert_eq!(
+            res.messages[2].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: vec![coin(200, "unois")],
+            })
+        );
+
+        // Reward is limited by the budget
+        let msg = ExecuteMsg::ProcessJobs {
+            round: ROUND1,
+            max: 10,
+        };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(first_attr(&res.attributes, "jobs_processed").unwrap(), "1");
+        assert_eq!(first_attr(&res.attributes, "jobs_left").unwrap(), "0");
+        assert_eq!(first_attr(&res.attributes, "reward").unwrap(), "50unois");
+        let ProcessJobsRewardResponse { reward, budget } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::ProcessJobsReward {}).unwrap())
+                .unwrap();
+        assert_eq!(reward, Some(coin(100, "unois")));
+        assert_eq!(budget, Uint128::zero());
+
+        // The round is done
+        let msg = QueryMsg::UnprocessedDrandRounds {
+            start_after: None,
+            limit: None,
+        };
+        let UnprocessedDrandRoundsResponse { rounds } =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(rounds, vec![]);
+    }
+
+    #[test]
+    fn jobs_of_closed_channels_are_dropped() {
+        let mut deps = setup();
+        const DRAND: &str = "drand_verifier_7";
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            price: None,
+            drand_addr: Some(DRAND.to_string()),
+            payment_initial_funds: None,
+            fee_split: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        connect(deps.as_mut(), "foo");
+        connect(deps.as_mut(), "bar");
+
+        for channel_id in ["bar", "bar", "foo", "bar", "foo"] {
+            let msg = mock_ibc_packet_recv(
+                channel_id,
+                &InPacket::RequestBeacon {
+                    after: AFTER1,
+                    origin: origin(1),
+                },
+            )
+            .unwrap();
+            ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        }
+        let msg = mock_ibc_channel_close_init("bar", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+
+        fn channels(res: &Response) -> Vec<String> {
+            res.messages
+                .iter()
+                .map(|m| match &m.msg {
+                    CosmosMsg::Ibc(IbcMsg::SendPacket { channel_id, .. }) => channel_id.clone(),
+                    msg => panic!("Unexpected message: {msg:?}"),
+                })
+                .collect()
+        }
+
+        // Dropped jobs count towards the limit of the submission
+        let add_round = make_add_verified_round_msg(ROUND1, true);
+        let res = execute(deps.as_mut(), mock_env(), mock_info(DRAND, &[]), add_round).unwrap();
+        assert_eq!(first_attr(&res.attributes, "jobs_processed").unwrap(), "0");
+        assert_eq!(first_attr(&res.attributes, "jobs_dropped").unwrap(), "2");
+        assert_eq!(first_attr(&res.attributes, "jobs_left").unwrap(), "3");
+        assert_eq!(channels(&res), Vec::<String>::new());
+
+        let msg = ExecuteMsg::ProcessJobs {
+            round: ROUND1,
+            max: 10,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(first_attr(&res.attributes, "jobs_processed").unwrap(), "2");
+        assert_eq!(first_attr(&res.attributes, "jobs_dropped").unwrap(), "1");
+        assert_eq!(first_attr(&res.attributes, "jobs_left").unwrap(), "0");
+        assert_eq!(channels(&res), vec!["foo", "foo"]);
+    }
+
+    #[test]
+    fn migrate_backfills_unprocessed_drand_rounds() {
+        let mut deps = setup();
+
+        let job = Job {
+            source_id: "drand:abc:1".to_string(),
+            channel: "foo".to_string(),
+            origin: Binary::from([1, 2, 3]),
+        };
+        for round in [10, 10, 20, 30, 1234567890] {
+            unprocessed_drand_jobs_enqueue(&mut deps.storage, round, &job, 1).unwrap();
+        }
+        // Round 30 is fully processed
+        unprocessed_drand_jobs_dequeue(&mut deps.storage, 30).unwrap();
+        // Queued before the index existed
+        for round in [10, 20, 1234567890] {
+            UNPROCESSED_DRAND_ROUNDS.remove(&mut deps.storage, round);
+        }
+
+        let res = migrate(deps.as_mut(), mock_env(), Empty {}).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "backfilled_unprocessed_rounds").unwrap(),
+            "3"
+        );
+        let height = mock_env().block.height;
+        let rounds = UNPROCESSED_DRAND_ROUNDS
+            .range(&deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            rounds,
+            vec![(10, height), (20, height), (1234567890, height)]
+        );
+
+        // Running it again changes nothing
+        let res = migrate(deps.as_mut(), mock_env(), Empty {}).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "backfilled_unprocessed_rounds").unwrap(),
+            "0"
+        );
+    }
+
+    //
+    // Query tests
+    //
+
+    #[test]
+    fn query_job_stats_works() {
+        let mut deps = mock_dependencies();
+
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            manager: MANAGER.to_string(),
+            price: Coin::new(1, "unois"),
+            payment_code_id: PAYMENT,
+            payment_initial_funds: payment_initial(),
+            sink: SINK.to_string(),
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        const DRAND: &str = "drand_verifier_7";
+
+        // Set drand contract
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            price: None,
+            drand_addr: Some(DRAND.to_string()),
+            payment_initial_funds: None,
+            fee_split: None,
+        };
+        let _res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+
+        fn job_stats(deps: Deps, round: u64) -> DrandJobStatsResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::DrandJobStats { round }).unwrap())
+                .unwrap()
+        }
+
+        // No jobs by default
+        assert_eq!(
+            job_stats(deps.as_ref(), ROUND1),
+            DrandJobStatsResponse {
+                round: ROUND1,
+                processed: 0,
+                unprocessed: 0,
+            }
+        );
+
+        connect(deps.as_mut(), "foo");
+
+        // Create one job
+        let msg = mock_ibc_packet_recv(
+            "foo",
+            &InPacket::RequestBeacon {
+                after: AFTER1,
+                origin: origin(1),
+            },
         )
         .unwrap();
         ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
@@ -1340,6 +2123,540 @@ mod tests {
         assert_eq!(first_attr(&attributes, "error").unwrap(), "kaputt");
     }
 
+    #[test]
+    fn receive_request_beacon_checks_payment_balance() {
+        let mut deps = setup();
+        connect(deps.as_mut(), "foo");
+        let request = |deps: DepsMut| {
+            let msg = mock_ibc_packet_recv(
+                "foo",
+                &InPacket::RequestBeacon {
+                    after: AFTER1,
+                    origin: origin(1),
+                },
+            )
+            .unwrap();
+            ibc_packet_receive(deps, mock_env(), msg).unwrap()
+        };
+
+        // Empty payment contract
+        deps.querier.update_balance("some payment address", vec![]);
+        let res = request(deps.as_mut());
+        let ack: StdAck = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(
+            ack,
+            StdAck::error_with_code(AckErrorCode::InsufficientPaymentBalance, "Error processing packet: Insufficient balance in payment contract some payment address: 0unois available, 1unois required")
+        );
+        assert_eq!(
+            ack.clone().unwrap_err(),
+            "nois-ack-code:insufficient_payment_balance: Error processing packet: Insufficient balance in payment contract some payment address: 0unois available, 1unois required"
+        );
+        assert_eq!(
+            ack.error_code(),
+            Some(AckErrorCode::InsufficientPaymentBalance)
+        );
+        // Plain errors never carry a code, whatever their message is
+        let plain = StdAck::error("insufficient_payment_balance: something else");
+        assert_eq!(plain.error_code(), None);
+        let plain = StdAck::error("nois-ack-code:insufficient_payment_balance: something else");
+        assert_eq!(plain.error_code(), None);
+        assert_eq!(
+            plain.unwrap_err(),
+            "error: nois-ack-code:insufficient_payment_balance: something else"
+        );
+        assert_eq!(res.messages.len(), 0);
+        assert_eq!(
+            res.events[1],
+            Event::new("nois-payment-balance-insufficient")
+                .add_attribute("channel_id", "foo")
+                .add_attribute("payment", "some payment address")
+                .add_attribute("balance", "0unois")
+                .add_attribute("required", "1unois")
+        );
+        assert_eq!(
+            unprocessed_drand_jobs_len(deps.as_ref().storage, ROUND1).unwrap(),
+            0
+        );
+
+        // A payment arriving after the request does not bring back the rejected job.
+        // The proxy has to send a new request.
+        deps.querier
+            .update_balance("some payment address", vec![coin(50, "unois")]);
+        assert_eq!(
+            unprocessed_drand_jobs_len(deps.as_ref().storage, ROUND1).unwrap(),
+            0
+        );
+
+        // Low balance
+        let res = request(deps.as_mut());
+        let ack: StdAck = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, StdAck::Result(_)));
+        assert_eq!(ack.error_code(), None);
+        assert_eq!(
+            unprocessed_drand_jobs_len(deps.as_ref().storage, ROUND1).unwrap(),
+            1
+        );
+        assert_eq!(
+            res.events,
+            vec![Event::new("nois-payment-balance-low")
+                .add_attribute("channel_id", "foo")
+                .add_attribute("payment", "some payment address")
+                .add_attribute("balance", "49unois")]
+        );
+
+        // Enough balance
+        deps.querier
+            .update_balance("some payment address", vec![coin(101, "unois")]);
+        let res = request(deps.as_mut());
+        assert_eq!(res.events, vec![]);
+    }
+
+    #[test]
+    fn broadcast_price_works() {
+        let mut deps = setup();
+        connect(deps.as_mut(), "channel-1");
+        connect(deps.as_mut(), "channel-2");
+        connect(deps.as_mut(), "channel-3");
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(MANAGER, &[]),
+            ExecuteMsg::SetCustomerPrice {
+                channel_id: "channel-2".to_string(),
+                price: Some(coin(0, "unois")),
+            },
+        )
+        .unwrap();
+
+        fn pushed_prices(res: &Response) -> Vec<(String, Uint128)> {
+            res.messages
+                .iter()
+                .map(|m| match &m.msg {
+                    CosmosMsg::Ibc(IbcMsg::SendPacket {
+                        channel_id, data, ..
+                    }) => match from_binary(data).unwrap() {
+                        OutPacket::PushBeaconPrice { amount, .. } => (channel_id.clone(), amount),
+                        packet => panic!("Unexpected packet: {packet:?}"),
+                    },
+                    msg => panic!("Unexpected message: {msg:?}"),
+                })
+                .collect()
+        }
+
+        // Nothing to broadcast
+        let broadcast = ExecuteMsg::BroadcastPrice { limit: Some(2) };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            broadcast.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NoPriceBroadcastPending));
+
+        // Changing the price marks a broadcast as pending
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            price: Some(coin(5, "unois")),
+            drand_addr: None,
+            payment_initial_funds: None,
+            fee_split: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        // Anyone can broadcast in pages
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            broadcast.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            pushed_prices(&res),
+            vec![
+                ("channel-1".to_string(), Uint128::new(5)),
+                ("channel-2".to_string(), Uint128::new(0)),
+            ]
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "next_start_after").unwrap(),
+            "channel-2"
+        );
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            broadcast.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            pushed_prices(&res),
+            vec![("channel-3".to_string(), Uint128::new(5))]
+        );
+        assert_eq!(first_attr(&res.attributes, "next_start_after"), None);
+
+        // Done
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            broadcast.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NoPriceBroadcastPending));
+
+        // Setting the same price does not start a broadcast
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            price: Some(coin(5, "unois")),
+            drand_addr: None,
+            payment_initial_funds: None,
+            fee_split: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            broadcast.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NoPriceBroadcastPending));
+
+        // Closed channels are skipped
+        let msg = mock_ibc_channel_close_init("channel-1", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            price: Some(coin(6, "unois")),
+            drand_addr: None,
+            payment_initial_funds: None,
+            fee_split: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::BroadcastPrice { limit: None },
+        )
+        .unwrap();
+        assert_eq!(
+            pushed_prices(&res),
+            vec![
+                ("channel-2".to_string(), Uint128::new(0)),
+                ("channel-3".to_string(), Uint128::new(6)),
+            ]
+        );
+        assert_eq!(first_attr(&res.attributes, "next_start_after"), None);
+    }
+
+    #[test]
+    fn migrate_payment_contracts_works() {
+        let mut deps = setup();
+        connect(deps.as_mut(), "channel-1");
+        connect(deps.as_mut(), "channel-2");
+        connect(deps.as_mut(), "channel-3");
+
+        let msg = ExecuteMsg::MigratePaymentContracts {
+            code_id: PAYMENT,
+            start_after: None,
+            limit: Some(2),
+        };
+
+        // Only manager
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("someone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+
+        // Code must exist
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(MANAGER, &[]),
+            ExecuteMsg::MigratePaymentContracts {
+                code_id: 654321,
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::CodeIdDoesNotExist { code_id: 654321 }
+        ));
+
+        fn migrated(res: &Response) -> Vec<String> {
+            res.messages
+                .iter()
+                .map(|m| match &m.msg {
+                    CosmosMsg::Wasm(WasmMsg::Migrate {
+                        contract_addr,
+                        new_code_id,
+                        ..
+                    }) => {
+                        assert_eq!(*new_code_id, PAYMENT);
+                        contract_addr.clone()
+                    }
+                    msg => panic!("Unexpected message: {msg:?}"),
+                })
+                .collect()
+        }
+        let payment = |deps: &OwnedDeps<_, _, _>, channel_id: &str| {
+            CUSTOMERS
+                .load(&deps.storage, channel_id)
+                .unwrap()
+                .payment
+                .to_string()
+        };
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        assert_eq!(
+            migrated(&res),
+            vec![payment(&deps, "channel-1"), payment(&deps, "channel-2")]
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "next_start_after").unwrap(),
+            "channel-2"
+        );
+
+        let msg = ExecuteMsg::MigratePaymentContracts {
+            code_id: PAYMENT,
+            start_after: Some("channel-2".to_string()),
+            limit: Some(2),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        assert_eq!(migrated(&res), vec![payment(&deps, "channel-3")]);
+        assert_eq!(first_attr(&res.attributes, "next_start_after"), None);
+    }
+
+    #[test]
+    fn unmigrated_payment_contracts_get_legacy_pay_msg() {
+        let mut deps = setup();
+        connect(deps.as_mut(), "foo");
+
+        // Customer created before the payment code ID was tracked
+        let mut customer = CUSTOMERS.load(&deps.storage, "foo").unwrap();
+        assert_eq!(customer.payment_code_id, Some(PAYMENT));
+        customer.payment_code_id = None;
+        customer.price = Some(coin(100, "unois"));
+        CUSTOMERS.save(&mut deps.storage, "foo", &customer).unwrap();
+
+        fn pay_msg(deps: DepsMut) -> Binary {
+            let msg = mock_ibc_packet_recv(
+                "foo",
+                &InPacket::RequestBeacon {
+                    after: AFTER1,
+                    origin: origin(1),
+                },
+            )
+            .unwrap();
+            let res = ibc_packet_receive(deps, mock_env(), msg).unwrap();
+            res.messages
+                .iter()
+                .find_map(|m| match &m.msg {
+                    CosmosMsg::Wasm(WasmMsg::Execute {
+                        contract_addr, msg, ..
+                    }) if contract_addr == "some payment address" => Some(msg.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        }
+
+        let msg: LegacyPaymentExecuteMsg = from_binary(&pay_msg(deps.as_mut())).unwrap();
+        assert_eq!(
+            msg,
+            LegacyPaymentExecuteMsg::Pay {
+                burn: coin(50, "unois"),
+                community_pool: coin(45, "unois"),
+                relayer: ("relayer".to_string(), coin(5, "unois")),
+            }
+        );
+
+        let msg = ExecuteMsg::MigratePaymentContracts {
+            code_id: PAYMENT,
+            start_after: None,
+            limit: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        let customer = CUSTOMERS.load(&deps.storage, "foo").unwrap();
+        assert_eq!(customer.payment_code_id, Some(PAYMENT));
+
+        let msg: nois_payment::msg::ExecuteMsg = from_binary(&pay_msg(deps.as_mut())).unwrap();
+        assert!(matches!(msg, nois_payment::msg::ExecuteMsg::Pay { .. }));
+    }
+
+    #[test]
+    fn set_customer_price_works() {
+        let mut deps = setup();
+        connect(deps.as_mut(), "foo");
+
+        let msg = ExecuteMsg::SetCustomerPrice {
+            channel_id: "foo".to_string(),
+            price: Some(coin(0, "unois")),
+        };
+
+        // Only manager
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("someone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+
+        // Unknown channel
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(MANAGER, &[]),
+            ExecuteMsg::SetCustomerPrice {
+                channel_id: "bar".to_string(),
+                price: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::CustomerNotFound { .. }));
+
+        // Sponsored customer gets the new price pushed
+        let res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let CosmosMsg::Ibc(IbcMsg::SendPacket {
+            channel_id, data, ..
+        }) = &res.messages[0].msg
+        else {
+            panic!("Unexpected message: {:?}", res.messages[0].msg);
+        };
+        assert_eq!(channel_id, "foo");
+        let packet: OutPacket = from_binary(data).unwrap();
+        assert!(matches!(
+            packet,
+            OutPacket::PushBeaconPrice { amount, .. } if amount.is_zero()
+        ));
+        let CustomerResponse { customer } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Customer {
+                    channel_id: "foo".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(customer.unwrap().price, Some(coin(0, "unois")));
+
+        // Pulling the price returns the customer's price
+        let msg = mock_ibc_packet_recv("foo", &InPacket::PullBeaconPrice {}).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let StdAck::Result(data) = from_binary(&res.acknowledgement).unwrap() else {
+            panic!("Expected success ack");
+        };
+        let ack: InPacketAck = from_binary(&data).unwrap();
+        assert!(matches!(
+            ack,
+            InPacketAck::PullBeaconPrice { amount, .. } if amount.is_zero()
+        ));
+
+        // Requests of a sponsored customer do not require a payment balance
+        deps.querier.update_balance("some payment address", vec![]);
+        let msg = mock_ibc_packet_recv(
+            "foo",
+            &InPacket::RequestBeacon {
+                after: AFTER1,
+                origin: origin(1),
+            },
+        )
+        .unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: StdAck = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, StdAck::Result(_)));
+    }
+
+    #[test]
+    fn receive_request_beacon_batch_works() {
+        let mut deps = setup();
+
+        const DRAND: &str = "drand_verifier_7";
+        const CHANNEL_ID: &str = "the-channel";
+
+        connect(deps.as_mut(), CHANNEL_ID);
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            price: None,
+            drand_addr: Some(DRAND.to_string()),
+            payment_initial_funds: None,
+            fee_split: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
+
+        // Batch is queued
+        let msg = mock_ibc_packet_recv(
+            CHANNEL_ID,
+            &InPacket::RequestBeaconBatch {
+                after: AFTER1,
+                origins: vec![origin(1), origin(2), origin(3)],
+            },
+        )
+        .unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: InPacketAck = from_binary::<StdAck>(&res.acknowledgement)
+            .unwrap()
+            .unwrap_into();
+        assert!(matches!(ack, InPacketAck::RequestQueued { .. }));
+        // Only the payment
+        assert_eq!(res.messages.len(), 1);
+        let customer = CUSTOMERS.load(&deps.storage, CHANNEL_ID).unwrap();
+        assert_eq!(customer.requested_beacons, 3);
+
+        // All jobs get processed
+        let msg = make_add_verified_round_msg(ROUND1, false);
+        let res = execute(deps.as_mut(), mock_env(), mock_info(DRAND, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 3);
+
+        // Batch for existing round is processed immediately
+        let msg = mock_ibc_packet_recv(
+            CHANNEL_ID,
+            &InPacket::RequestBeaconBatch {
+                after: AFTER1,
+                origins: vec![origin(4), origin(5)],
+            },
+        )
+        .unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: InPacketAck = from_binary::<StdAck>(&res.acknowledgement)
+            .unwrap()
+            .unwrap_into();
+        assert!(matches!(ack, InPacketAck::RequestProcessed { .. }));
+        // Two deliveries and the payment
+        assert_eq!(res.messages.len(), 3);
+        let customer = CUSTOMERS.load(&deps.storage, CHANNEL_ID).unwrap();
+        assert_eq!(customer.requested_beacons, 5);
+
+        // Empty batch is rejected
+        let msg = mock_ibc_packet_recv(
+            CHANNEL_ID,
+            &InPacket::RequestBeaconBatch {
+                after: AFTER1,
+                origins: vec![],
+            },
+        )
+        .unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let err = from_binary::<StdAck>(&res.acknowledgement)
+            .unwrap()
+            .unwrap_err();
+        assert!(err.contains("Batch must contain"));
+    }
+
     #[test]
     fn query_customer_works() {
         let mut deps = setup();
@@ -1367,6 +2684,7 @@ mod tests {
                 channel_id: channel_id.to_string(),
                 payment: Addr::unchecked("some payment address"),
                 requested_beacons: 0,
+                price: None,
             })
         );
 
@@ -1401,6 +2719,7 @@ mod tests {
             price: None,
             drand_addr: Some(DRAND.to_string()),
             payment_initial_funds: None,
+            fee_split: None,
         };
         let _res = execute(deps.as_mut(), mock_env(), mock_info(MANAGER, &[]), msg).unwrap();
 
@@ -1513,6 +2832,7 @@ mod tests {
                 channel_id: channel_id.to_string(),
                 payment: Addr::unchecked("some payment address"),
                 requested_beacons: 0,
+                price: None,
             }]
         );
 
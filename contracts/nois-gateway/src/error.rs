@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Coin, StdError};
 
 use nois_protocol::ChannelError;
 
@@ -14,16 +14,44 @@ pub enum ContractError {
     Unauthorized,
 
     // Payment
+    #[error("Invalid fee split. The percentages must sum up to 100 and addresses must be valid.")]
+    InvalidFeeSplit,
+
     #[error("Code ID does not exist: {code_id}")]
     CodeIdDoesNotExist { code_id: u64 },
 
+    #[error("No customer found for channel {channel_id}")]
+    CustomerNotFound { channel_id: String },
+
     // Jobs
     #[error("Origin data exceeds length limit.")]
     OriginTooLong,
 
+    #[error("Batch must contain between 1 and the maximum number of origins.")]
+    InvalidBatchSize,
+
+    #[error("Insufficient balance in payment contract {payment}: {balance} available, {required} required")]
+    InsufficientPaymentBalance {
+        payment: String,
+        balance: Coin,
+        required: Coin,
+    },
+
     #[error("Sender is unauthorized to add verified round.")]
     UnauthorizedAddVerifiedRound,
 
+    #[error("Round {round} was not yet added. Its jobs cannot be processed.")]
+    RoundNotAdded { round: u64 },
+
+    #[error("No price change is waiting to be broadcast.")]
+    NoPriceBroadcastPending,
+
+    #[error("No reward for processing jobs is set.")]
+    NoProcessJobsReward,
+
+    #[error("Funds for the process jobs reward must be a single non-zero coin of denom {denom}.")]
+    InvalidProcessJobsRewardFunds { denom: String },
+
     // IBC
     #[error("The nois-gateway contract must be on chain B of the connection. Try swapping A and B in the channel creation.")]
     MustBeChainB,
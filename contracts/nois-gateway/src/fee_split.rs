@@ -0,0 +1,201 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Api, Coin};
+use nois_payment::msg::{Payment, PaymentDestination};
+
+use crate::error::ContractError;
+use crate::state::{FeeDestination, FeeShare};
+
+/// Checks that the percentages sum up to 100 and all addresses are valid
+pub fn validate_fee_split(api: &dyn Api, fee_split: &[FeeShare]) -> Result<(), ContractError> {
+    let total: u64 = fee_split.iter().map(|share| share.percent as u64).sum();
+    if total != 100 {
+        return Err(ContractError::InvalidFeeSplit);
+    }
+    for share in fee_split {
+        if let FeeDestination::Address { address } = &share.destination {
+            api.addr_validate(address)
+                .map_err(|_| ContractError::InvalidFeeSplit)?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits the total amount into the payments instructed to the payment contract.
+///
+/// Amounts are rounded down. The remainder caused by rounding goes to the last destination.
+pub fn split_payment(total: Coin, fee_split: &[FeeShare], relayer: &Addr) -> Vec<Payment> {
+    let mut remaining = total.amount;
+    fee_split
+        .iter()
+        .enumerate()
+        .map(|(i, share)| {
+            let amount = if i + 1 == fee_split.len() {
+                remaining
+            } else {
+                total.amount.mul_floor((share.percent as u128, 100u128))
+            };
+            remaining -= amount;
+            let destination = match &share.destination {
+                FeeDestination::Burn {} => PaymentDestination::Burn {},
+                FeeDestination::CommunityPool {} => PaymentDestination::CommunityPool {},
+                FeeDestination::Relayer {} => PaymentDestination::Address {
+                    address: relayer.to_string(),
+                },
+                FeeDestination::Address { address } => PaymentDestination::Address {
+                    address: address.clone(),
+                },
+            };
+            Payment {
+                destination,
+                amount: Coin {
+                    amount,
+                    denom: total.denom.clone(),
+                },
+            }
+        })
+        .collect()
+}
+
+/// The `ExecuteMsg` of payment contracts created before the fee split became configurable
+#[cw_serde]
+pub enum LegacyPaymentExecuteMsg {
+    Pay {
+        burn: Coin,
+        community_pool: Coin,
+        relayer: (String, Coin),
+    },
+}
+
+/// Creates the payment message understood by payment contracts which were not migrated yet.
+///
+/// Those contracts only support the fixed split of 50% burn, 5% relayer and 45% community pool.
+pub fn legacy_payment_msg(total: Coin, relayer: &Addr) -> LegacyPaymentExecuteMsg {
+    let Coin { amount, denom } = total;
+    let amount_burn = amount.mul_floor((50u128, 100)); // 50%
+    let amount_relayer = amount.mul_floor((5u128, 100)); // 5%
+    let amount_rest = amount - amount_burn - amount_relayer; // 45%
+    LegacyPaymentExecuteMsg::Pay {
+        burn: Coin::new(amount_burn.u128(), &denom),
+        relayer: (relayer.into(), Coin::new(amount_relayer.u128(), &denom)),
+        community_pool: Coin::new(amount_rest.u128(), denom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::default_fee_split;
+    use cosmwasm_std::{coin, testing::MockApi};
+
+    #[test]
+    fn validate_fee_split_works() {
+        let api = MockApi::default();
+        validate_fee_split(&api, &default_fee_split()).unwrap();
+        validate_fee_split(
+            &api,
+            &[FeeShare {
+                destination: FeeDestination::Address {
+                    address: "treasury".to_string(),
+                },
+                percent: 100,
+            }],
+        )
+        .unwrap();
+
+        // Empty
+        let err = validate_fee_split(&api, &[]).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFeeSplit));
+        // Sum too low
+        let mut split = default_fee_split();
+        split[0].percent = 49;
+        let err = validate_fee_split(&api, &split).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFeeSplit));
+        // Sum too high
+        split[0].percent = 51;
+        let err = validate_fee_split(&api, &split).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFeeSplit));
+        // Invalid address
+        let err = validate_fee_split(
+            &api,
+            &[FeeShare {
+                destination: FeeDestination::Address {
+                    address: "x".to_string(),
+                },
+                percent: 100,
+            }],
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFeeSplit));
+    }
+
+    #[test]
+    fn split_payment_works() {
+        let relayer = Addr::unchecked("relayer");
+
+        let payments = split_payment(coin(1_000_000, "unois"), &default_fee_split(), &relayer);
+        assert_eq!(
+            payments,
+            vec![
+                Payment {
+                    destination: PaymentDestination::Burn {},
+                    amount: coin(500_000, "unois"),
+                },
+                Payment {
+                    destination: PaymentDestination::Address {
+                        address: "relayer".to_string(),
+                    },
+                    amount: coin(50_000, "unois"),
+                },
+                Payment {
+                    destination: PaymentDestination::CommunityPool {},
+                    amount: coin(450_000, "unois"),
+                },
+            ]
+        );
+
+        // Rounding remainder goes to the last destination
+        let split = vec![
+            FeeShare {
+                destination: FeeDestination::Address {
+                    address: "treasury".to_string(),
+                },
+                percent: 33,
+            },
+            FeeShare {
+                destination: FeeDestination::Burn {},
+                percent: 67,
+            },
+        ];
+        let payments = split_payment(coin(10, "unois"), &split, &relayer);
+        assert_eq!(
+            payments,
+            vec![
+                Payment {
+                    destination: PaymentDestination::Address {
+                        address: "treasury".to_string(),
+                    },
+                    amount: coin(3, "unois"),
+                },
+                Payment {
+                    destination: PaymentDestination::Burn {},
+                    amount: coin(7, "unois"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_payment_msg_works() {
+        let relayer = Addr::unchecked("relayer");
+
+        let msg = legacy_payment_msg(coin(1_000_001, "unois"), &relayer);
+        assert_eq!(
+            msg,
+            LegacyPaymentExecuteMsg::Pay {
+                burn: coin(500_000, "unois"),
+                community_pool: coin(450_001, "unois"),
+                relayer: ("relayer".to_string(), coin(50_000, "unois")),
+            }
+        );
+    }
+}
@@ -1,4 +1,5 @@
 mod drand_archive;
+mod fee_split;
 mod job_id;
 mod request_router;
 
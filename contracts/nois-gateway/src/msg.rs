@@ -1,7 +1,7 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Coin, HexBinary};
+use cosmwasm_std::{Addr, Coin, HexBinary, Uint128};
 
-use crate::state::{Config, Customer};
+use crate::state::{Config, Customer, FeeShare};
 
 #[cw_serde]
 pub struct InstantiateMsg {
@@ -34,7 +34,42 @@ pub enum ExecuteMsg {
         /// See https://twitter.com/simon_warta/status/1643354582494642177 for why.
         /// To deactivate it later on, send Some(Coin::new(0, "unois")) here.
         payment_initial_funds: Option<Coin>,
+        /// Sets how the payment for beacons is split between the destinations
+        fee_split: Option<Vec<FeeShare>>,
     },
+    /// Delivers up to `max` jobs that are left in the queue of a round which was already added.
+    /// This is needed when more jobs were queued than the submissions of the round processed.
+    /// Anyone can call this. The caller receives the reward per processed job if one is set
+    /// and the round has had unprocessed jobs for at least 10 blocks.
+    /// Jobs of closed channels are removed from the queue without delivery.
+    ProcessJobs { round: u64, max: u32 },
+    /// Sends a changed beacon price to the proxies of the next up to `limit` customers
+    /// (default 50, max 100). Customers of closed channels are skipped.
+    /// Anyone can call this as long as not all customers received the new price.
+    /// The response contains the attribute `next_start_after` if more customers exist.
+    BroadcastPrice { limit: Option<u32> },
+    /// Sets the payment code ID and migrates the payment contracts of up to `limit` customers
+    /// starting after the channel `start_after` to it. Only the manager can call this.
+    /// The response contains the attribute `next_start_after` if more customers exist.
+    MigratePaymentContracts {
+        code_id: u64,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Sets the price for the customer of the given channel. If `price` is None,
+    /// the price in the config is used. Sends the new price to the proxy.
+    /// Use a zero amount to sponsor the customer's beacons.
+    SetCustomerPrice {
+        channel_id: String,
+        price: Option<Coin>,
+    },
+    /// Sets the reward per job processed via `ProcessJobs`. The reward is paid from the
+    /// reward budget. If `reward` is None, no reward is paid.
+    /// Changing the denom or removing the reward resets the budget.
+    SetProcessJobsReward { reward: Option<Coin> },
+    /// Adds the sent funds to the budget the `ProcessJobs` rewards are paid from.
+    /// The funds must be in the denom of the reward. Anyone can call this.
+    FundProcessJobsReward {},
 }
 
 #[cw_serde]
@@ -46,6 +81,16 @@ pub enum QueryMsg {
     /// Gets basic statistics about jobs in this drand round.
     #[returns(DrandJobStatsResponse)]
     DrandJobStats { round: u64 },
+    /// Lists the drand rounds that have unprocessed jobs, including rounds not yet added.
+    #[returns(UnprocessedDrandRoundsResponse)]
+    UnprocessedDrandRounds {
+        /// The round after which to start
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Gets the reward per job processed via `ExecuteMsg::ProcessJobs`
+    #[returns(ProcessJobsRewardResponse)]
+    ProcessJobsReward {},
     #[returns(CustomerResponse)]
     Customer { channel_id: String },
     #[returns(CustomersResponse)]
@@ -68,6 +113,19 @@ pub struct DrandJobStatsResponse {
     pub processed: u32,
 }
 
+#[cw_serde]
+pub struct UnprocessedDrandRoundsResponse {
+    /// Basic statistics of the rounds in ascending order
+    pub rounds: Vec<DrandJobStatsResponse>,
+}
+
+#[cw_serde]
+pub struct ProcessJobsRewardResponse {
+    pub reward: Option<Coin>,
+    /// The amount left for paying rewards, in the denom of `reward`
+    pub budget: Uint128,
+}
+
 #[cw_serde]
 pub struct QueriedCustomer {
     pub channel_id: String,
@@ -75,6 +133,8 @@ pub struct QueriedCustomer {
     pub payment: Addr,
     /// Number of beacons requested in total
     pub requested_beacons: u64,
+    /// The price override of this customer. If unset, the price in the config is used.
+    pub price: Option<Coin>,
 }
 
 impl QueriedCustomer {
@@ -83,6 +143,7 @@ impl QueriedCustomer {
             channel_id,
             payment: customer.payment,
             requested_beacons: customer.requested_beacons,
+            price: customer.price,
         }
     }
 }
@@ -10,7 +10,7 @@ use crate::{
     drand_archive::{archive_lookup, archive_store},
     state::{
         increment_processed_drand_jobs, unprocessed_drand_jobs_dequeue,
-        unprocessed_drand_jobs_enqueue, unprocessed_drand_jobs_len, Job,
+        unprocessed_drand_jobs_enqueue, unprocessed_drand_jobs_len, Job, CLOSED_CHANNELS,
     },
 };
 
@@ -22,6 +22,9 @@ use crate::{
 const MAX_JOBS_PER_SUBMISSION_WITH_VERIFICATION: u32 = 2;
 const MAX_JOBS_PER_SUBMISSION_WITHOUT_VERIFICATION: u32 = 14;
 
+/// The max number of jobs that are processed in one `ExecuteMsg::ProcessJobs`
+pub const MAX_JOBS_PER_PROCESS_JOBS: u32 = 30;
+
 pub struct RoutingReceipt {
     pub acknowledgement: StdAck,
     pub msgs: Vec<CosmosMsg>,
@@ -30,6 +33,8 @@ pub struct RoutingReceipt {
 pub struct NewDrand {
     pub msgs: Vec<CosmosMsg>,
     pub jobs_processed: u32,
+    /// Jobs of closed channels which were removed from the queue without delivery
+    pub jobs_dropped: u32,
     pub jobs_left: u32,
 }
 
@@ -81,7 +86,7 @@ impl RequestRouter {
             msgs.push(msg.into());
             StdAck::success(&InPacketAck::RequestProcessed { source_id })
         } else {
-            unprocessed_drand_jobs_enqueue(deps.storage, round, &job)?;
+            unprocessed_drand_jobs_enqueue(deps.storage, round, &job, env.block.height)?;
             StdAck::success(&InPacketAck::RequestQueued { source_id })
         };
 
@@ -107,10 +112,48 @@ impl RequestRouter {
             MAX_JOBS_PER_SUBMISSION_WITHOUT_VERIFICATION
         };
 
+        self.deliver_queued_jobs(deps, env, round, randomness, max_jobs_per_submission)
+    }
+
+    /// Delivers up to `max` jobs of a round that is already in the archive. This allows
+    /// delivering jobs that are left over after the last submission of the round.
+    ///
+    /// Returns None if the round is not in the archive.
+    pub fn process_jobs(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        round: u64,
+        max: u32,
+    ) -> StdResult<Option<NewDrand>> {
+        let Some(randomness) = archive_lookup(deps.storage, round) else {
+            return Ok(None);
+        };
+        let max = max.min(MAX_JOBS_PER_PROCESS_JOBS);
+        self.deliver_queued_jobs(deps, env, round, &randomness, max)
+            .map(Some)
+    }
+
+    fn deliver_queued_jobs(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        round: u64,
+        randomness: &HexBinary,
+        max: u32,
+    ) -> StdResult<NewDrand> {
         let mut msgs = Vec::<CosmosMsg>::new();
         let mut jobs_processed = 0;
-        // let max_jobs_per_submission
-        while let Some(job) = unprocessed_drand_jobs_dequeue(deps.storage, round)? {
+        let mut jobs_dropped = 0;
+        while jobs_processed + jobs_dropped < max {
+            let Some(job) = unprocessed_drand_jobs_dequeue(deps.storage, round)? else {
+                break;
+            };
+            // Sending packets to closed channels fails, which would block the whole round
+            if CLOSED_CHANNELS.has(deps.storage, &job.channel) {
+                jobs_dropped += 1;
+                continue;
+            }
             increment_processed_drand_jobs(deps.storage, round)?;
             let published = time_of_round(round);
             // Use IbcMsg::SendPacket to send packages to the proxies.
@@ -122,14 +165,12 @@ impl RequestRouter {
             )?;
             msgs.push(msg.into());
             jobs_processed += 1;
-            if jobs_processed >= max_jobs_per_submission {
-                break;
-            }
         }
         let jobs_left = unprocessed_drand_jobs_len(deps.storage, round)?;
         Ok(NewDrand {
             msgs,
             jobs_processed,
+            jobs_dropped,
             jobs_left,
         })
     }
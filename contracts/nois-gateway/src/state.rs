@@ -1,5 +1,5 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Binary, Coin, StdResult, Storage};
+use cosmwasm_std::{Addr, Binary, Coin, Empty, Order, StdResult, Storage, Uint128};
 use cw_storage_plus::{Deque, Item, Map};
 
 #[cw_serde]
@@ -19,6 +19,48 @@ pub struct Config {
     pub payment_initial_funds: Option<Coin>,
     /// Address of the Nois sink
     pub sink: Addr,
+    /// How the payment for beacons is split. The percentages sum up to 100.
+    /// Configs stored before this field existed use the default split.
+    #[serde(default = "default_fee_split")]
+    pub fee_split: Vec<FeeShare>,
+}
+
+/// A destination of a share of the payment for beacons
+#[cw_serde]
+pub enum FeeDestination {
+    /// Burns the tokens via the Nois sink
+    Burn {},
+    /// Sends the tokens to the community pool
+    CommunityPool {},
+    /// Sends the tokens to the relayer of the beacon request packet
+    Relayer {},
+    /// Sends the tokens to the given address
+    Address { address: String },
+}
+
+#[cw_serde]
+pub struct FeeShare {
+    pub destination: FeeDestination,
+    pub percent: u32,
+}
+
+/// The split used before it became configurable:
+/// 50 % burn, 5 % relayer and 45 % community pool
+pub fn default_fee_split() -> Vec<FeeShare> {
+    vec![
+        FeeShare {
+            destination: FeeDestination::Burn {},
+            percent: 50,
+        },
+        FeeShare {
+            destination: FeeDestination::Relayer {},
+            percent: 5,
+        },
+        FeeShare {
+            destination: FeeDestination::CommunityPool {},
+            percent: 45,
+        },
+    ]
 }
 
 pub const CONFIG: Item<Config> = Item::new("config");
@@ -39,14 +81,24 @@ fn unprocessed_drand_jobs_key(round: u64) -> String {
     format!("drand_jobs_up_{:0>10}", round)
 }
 
+/// An index of the drand rounds that have unprocessed jobs. The value is the block height
+/// at which the round was added to the index.
+/// "ur" is short for unprocessed rounds.
+pub const UNPROCESSED_DRAND_ROUNDS: Map<u64, u64> = Map::new("drand_jobs_ur");
+
 /// Add an element to the unprocessed drand jobs queue of this round
 pub fn unprocessed_drand_jobs_enqueue(
     storage: &mut dyn Storage,
     round: u64,
     value: &Job,
+    height: u64,
 ) -> StdResult<()> {
     let prefix = unprocessed_drand_jobs_key(round);
-    Deque::new(&prefix).push_back(storage, value)
+    Deque::new(&prefix).push_back(storage, value)?;
+    if !UNPROCESSED_DRAND_ROUNDS.has(storage, round) {
+        UNPROCESSED_DRAND_ROUNDS.save(storage, round, &height)?;
+    }
+    Ok(())
 }
 
 /// Remove an element from the unprocessed drand jobs queue of this round
@@ -55,7 +107,12 @@ pub fn unprocessed_drand_jobs_dequeue(
     round: u64,
 ) -> StdResult<Option<Job>> {
     let prefix = unprocessed_drand_jobs_key(round);
-    Deque::new(&prefix).pop_front(storage)
+    let deque = Deque::new(&prefix);
+    let job = deque.pop_front(storage)?;
+    if deque.is_empty(storage)? {
+        UNPROCESSED_DRAND_ROUNDS.remove(storage, round);
+    }
+    Ok(job)
 }
 
 /// Gets the number of unprocessed drand jobs queue of this round
@@ -64,6 +121,61 @@ pub fn unprocessed_drand_jobs_len(storage: &dyn Storage, round: u64) -> StdResul
     Deque::<Job>::new(&prefix).len(storage)
 }
 
+/// The raw storage key prefix of the unprocessed drand jobs queue of this round.
+/// This is the length-prefixed namespace used by `Deque`.
+fn unprocessed_drand_jobs_raw_prefix(round: u64) -> Vec<u8> {
+    let namespace = unprocessed_drand_jobs_key(round);
+    let mut out = (namespace.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(namespace.as_bytes());
+    out
+}
+
+/// Adds all rounds with queued jobs to `UNPROCESSED_DRAND_ROUNDS`.
+///
+/// Jobs queued before the index existed are found by scanning the storage keys of the queues.
+/// The scan seeks to the next round after every hit, so its cost is linear in the number of
+/// rounds, not jobs. The rounds are added at the given block height. Returns the number of
+/// rounds added.
+pub fn backfill_unprocessed_drand_rounds(storage: &mut dyn Storage, height: u64) -> StdResult<u32> {
+    // All queue keys have the same length prefix, so the first key after the "_" separator
+    // is an upper bound for all of them.
+    let end = {
+        let mut end = unprocessed_drand_jobs_raw_prefix(0);
+        end.truncate(end.len() - 10);
+        *end.last_mut().unwrap() += 1;
+        end
+    };
+    let digits = end.len()..end.len() + 10;
+
+    let mut count = 0;
+    let mut next_round = 0;
+    loop {
+        let start = unprocessed_drand_jobs_raw_prefix(next_round);
+        let Some((key, _)) = storage
+            .range(Some(&start), Some(&end), Order::Ascending)
+            .next()
+        else {
+            break;
+        };
+        let round = key
+            .get(digits.clone())
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| d.parse::<u64>().ok());
+        let Some(round) = round else {
+            break;
+        };
+        // Empty queues keep their head and tail keys
+        if unprocessed_drand_jobs_len(storage, round)? > 0
+            && !UNPROCESSED_DRAND_ROUNDS.has(storage, round)
+        {
+            UNPROCESSED_DRAND_ROUNDS.save(storage, round, &height)?;
+            count += 1;
+        }
+        next_round = round + 1;
+    }
+    Ok(count)
+}
+
 /// A map from drand rounds to number of jobs.
 /// "pc" is short for processed count.
 const PROCESSED_DRAND_JOBS_COUNT: Map<u64, u32> = Map::new("drand_jobs_pc");
@@ -83,13 +195,48 @@ pub fn increment_processed_drand_jobs(storage: &mut dyn Storage, round: u64) ->
     Ok(())
 }
 
+/// The reward paid for every job processed via `ExecuteMsg::ProcessJobs`
+pub const PROCESS_JOBS_REWARD: Item<Coin> = Item::new("process_jobs_reward");
+
+/// The part of the gateway's balance reserved for `ExecuteMsg::ProcessJobs` rewards,
+/// in the denom of `PROCESS_JOBS_REWARD`. Rewards are only paid from this budget such that
+/// they never consume the funds used for `Config::payment_initial_funds`.
+pub const PROCESS_JOBS_REWARD_BUDGET: Item<Uint128> = Item::new("process_jobs_reward_budget");
+
 #[cw_serde]
 pub struct Customer {
     /// The payment contract address
     pub payment: Addr,
     /// Number of beacons requested in total
     pub requested_beacons: u64,
+    /// A price override for this customer. If unset, `Config::price` is used.
+    pub price: Option<Coin>,
+    /// The code ID the payment contract was created with or last migrated to by the gateway.
+    /// Unset for payment contracts created before this was tracked. Those run code which only
+    /// understands the legacy `Pay` message.
+    pub payment_code_id: Option<u64>,
+}
+
+impl Customer {
+    /// The price this customer pays for a beacon
+    pub fn beacon_price(&self, config: &Config) -> Coin {
+        self.price.clone().unwrap_or_else(|| config.price.clone())
+    }
 }
 
 /// A map from channel ID to customer information
 pub const CUSTOMERS: Map<&str, Customer> = Map::new("customers");
+
+/// The channels closed via `ibc_channel_close`. Their customers are kept,
+/// but no packets are sent to them anymore.
+pub const CLOSED_CHANNELS: Map<&str, Empty> = Map::new("closed_channels");
+
+/// A price change that was not yet sent to all customers
+#[cw_serde]
+pub struct PriceBroadcast {
+    /// The channel after which `ExecuteMsg::BroadcastPrice` continues
+    pub next_start_after: Option<String>,
+}
+
+/// Set when the price changed and unset once all customers were informed
+pub const PRICE_BROADCAST: Item<PriceBroadcast> = Item::new("price_broadcast");
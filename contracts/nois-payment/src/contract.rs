@@ -1,11 +1,14 @@
 use anything::Anything;
 use cosmwasm_std::{
-    ensure_eq, entry_point, to_binary, Addr, BankMsg, Coin, CosmosMsg, Deps, DepsMut, Env,
-    MessageInfo, QueryResponse, Response, StdResult, WasmMsg,
+    ensure_eq, entry_point, to_binary, Addr, Attribute, BankMsg, Coin, CosmosMsg, Deps, DepsMut,
+    Empty, Env, MessageInfo, QueryResponse, Response, StdResult, WasmMsg,
 };
 
 use crate::error::ContractError;
-use crate::msg::{ConfigResponse, ExecuteMsg, InstantiateMsg, NoisSinkExecuteMsg, QueryMsg};
+use crate::msg::{
+    ConfigResponse, ExecuteMsg, InstantiateMsg, NoisSinkExecuteMsg, Payment, PaymentDestination,
+    QueryMsg,
+};
 use crate::state::{Config, CONFIG};
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -32,6 +35,13 @@ pub fn instantiate(
         .add_attribute("nois_gateway", info.sender))
 }
 
+// The gateway is the admin of all payment contracts and migrates them via
+// its `ExecuteMsg::MigratePaymentContracts`. No state changes expected.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(_deps: DepsMut, _env: Env, _msg: Empty) -> StdResult<Response> {
+    Ok(Response::default())
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
@@ -40,11 +50,7 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::Pay {
-            burn,
-            community_pool,
-            relayer,
-        } => execute_pay(deps, info, env, burn, community_pool, relayer),
+        ExecuteMsg::Pay { payments } => execute_pay(deps, info, env, payments),
     }
 }
 
@@ -65,9 +71,7 @@ fn execute_pay(
     deps: DepsMut,
     info: MessageInfo,
     env: Env,
-    burn: Coin,
-    community_pool: Coin,
-    relayer: (String, Coin),
+    payments: Vec<Payment>,
 ) -> Result<Response, ContractError> {
     let funds = info.funds;
     let config = CONFIG.load(deps.storage).unwrap();
@@ -79,50 +83,62 @@ fn execute_pay(
     if !funds.is_empty() {
         return Err(ContractError::DontSendFunds);
     }
-    // Check relayer addr is valid
-    deps.api
-        .addr_validate(relayer.0.as_str())
-        .map_err(|_| ContractError::InvalidAddress)?;
 
-    let mut out_msgs: Vec<CosmosMsg> = Vec::with_capacity(3);
-
-    // Burn
-    if !burn.amount.is_zero() {
-        out_msgs.push(
-            WasmMsg::Execute {
-                contract_addr: config.sink.to_string(),
-                msg: to_binary(&NoisSinkExecuteMsg::Burn {})?,
-                funds: vec![burn.clone()],
+    let mut out_msgs: Vec<CosmosMsg> = Vec::with_capacity(payments.len());
+    let mut attributes: Vec<Attribute> = Vec::with_capacity(payments.len());
+
+    for Payment {
+        destination,
+        amount,
+    } in payments
+    {
+        match destination {
+            PaymentDestination::Burn {} => {
+                if !amount.amount.is_zero() {
+                    out_msgs.push(
+                        WasmMsg::Execute {
+                            contract_addr: config.sink.to_string(),
+                            msg: to_binary(&NoisSinkExecuteMsg::Burn {})?,
+                            funds: vec![amount.clone()],
+                        }
+                        .into(),
+                    );
+                }
+                attributes.push(Attribute::new("burnt", amount.to_string()));
             }
-            .into(),
-        );
-    }
-
-    // Send to relayer
-    if !relayer.1.amount.is_zero() {
-        out_msgs.push(
-            BankMsg::Send {
-                to_address: relayer.0.to_owned(),
-                amount: vec![relayer.1.clone()],
+            PaymentDestination::CommunityPool {} => {
+                if !amount.amount.is_zero() {
+                    out_msgs.push(CosmosMsg::Stargate {
+                        type_url: "/cosmos.distribution.v1beta1.MsgFundCommunityPool".to_string(),
+                        value: encode_msg_fund_community_pool(&amount, &env.contract.address)
+                            .into(),
+                    });
+                }
+                attributes.push(Attribute::new("sent_to_community_pool", amount.to_string()));
             }
-            .into(),
-        );
-    }
-
-    // Send to community pool
-    if !community_pool.amount.is_zero() {
-        out_msgs.push(CosmosMsg::Stargate {
-            type_url: "/cosmos.distribution.v1beta1.MsgFundCommunityPool".to_string(),
-            value: encode_msg_fund_community_pool(&community_pool, &env.contract.address).into(),
-        });
+            PaymentDestination::Address { address } => {
+                // Check addr is valid
+                deps.api
+                    .addr_validate(&address)
+                    .map_err(|_| ContractError::InvalidAddress)?;
+                if !amount.amount.is_zero() {
+                    out_msgs.push(
+                        BankMsg::Send {
+                            to_address: address.clone(),
+                            amount: vec![amount.clone()],
+                        }
+                        .into(),
+                    );
+                }
+                attributes.push(Attribute::new("sent_to_address", address));
+                attributes.push(Attribute::new("sent", amount.to_string()));
+            }
+        }
     }
 
     Ok(Response::new()
         .add_messages(out_msgs)
-        .add_attribute("burnt", burn.to_string())
-        .add_attribute("relayer_reward", relayer.1.to_string())
-        .add_attribute("relayer_address", relayer.0)
-        .add_attribute("sent_to_community_pool", community_pool.to_string()))
+        .add_attributes(attributes))
 }
 
 fn encode_msg_fund_community_pool(amount: &Coin, depositor: &Addr) -> Vec<u8> {
@@ -146,7 +162,7 @@ mod tests {
     use cosmwasm_std::{
         coins, from_binary,
         testing::{mock_dependencies, mock_env, mock_info},
-        Addr, Attribute, Binary, Uint128,
+        Addr, Binary,
     };
     use hex;
 
@@ -164,6 +180,25 @@ mod tests {
         })
     }
 
+    fn payments(burn: u128, relayer: u128, community_pool: u128) -> Vec<Payment> {
+        vec![
+            Payment {
+                destination: PaymentDestination::Burn {},
+                amount: Coin::new(burn, "unois"),
+            },
+            Payment {
+                destination: PaymentDestination::Address {
+                    address: "some-relayer".to_string(),
+                },
+                amount: Coin::new(relayer, "unois"),
+            },
+            Payment {
+                destination: PaymentDestination::CommunityPool {},
+                amount: Coin::new(community_pool, "unois"),
+            },
+        ]
+    }
+
     #[test]
     fn instantiate_works() {
         let mut deps = mock_dependencies();
@@ -195,21 +230,7 @@ mod tests {
 
         let info = mock_info(NOIS_GATEWAY, &coins(12345, "unoisx"));
         let msg = ExecuteMsg::Pay {
-            burn: Coin {
-                denom: "unois".to_string(),
-                amount: Uint128::new(500_000),
-            },
-            community_pool: Coin {
-                denom: "unois".to_string(),
-                amount: Uint128::new(450_000),
-            },
-            relayer: (
-                "some-relayer".to_string(),
-                Coin {
-                    denom: "unois".to_string(),
-                    amount: Uint128::new(50_000),
-                },
-            ),
+            payments: payments(500_000, 50_000, 450_000),
         };
 
         let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
@@ -227,21 +248,7 @@ mod tests {
 
         let info = mock_info("a-malicious-person", &[]);
         let msg = ExecuteMsg::Pay {
-            burn: Coin {
-                denom: "unois".to_string(),
-                amount: Uint128::new(500_000),
-            },
-            community_pool: Coin {
-                denom: "unois".to_string(),
-                amount: Uint128::new(450_000),
-            },
-            relayer: (
-                "some-relayer".to_string(),
-                Coin {
-                    denom: "unois".to_string(),
-                    amount: Uint128::new(50_000),
-                },
-            ),
+            payments: payments(500_000, 50_000, 450_000),
         };
 
         let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
@@ -259,21 +266,7 @@ mod tests {
 
         let info = mock_info(NOIS_GATEWAY, &[]);
         let msg = ExecuteMsg::Pay {
-            burn: Coin {
-                denom: "unois".to_string(),
-                amount: Uint128::new(500_000),
-            },
-            community_pool: Coin {
-                denom: "unois".to_string(),
-                amount: Uint128::new(450_000),
-            },
-            relayer: (
-                "some-relayer".to_string(),
-                Coin {
-                    denom: "unois".to_string(),
-                    amount: Uint128::new(50_000),
-                },
-            ),
+            payments: payments(500_000, 50_000, 450_000),
         };
 
         let response = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -302,7 +295,11 @@ mod tests {
             "500000unois"
         );
         assert_eq!(
-            first_attr(&response.attributes, "relayer_reward").unwrap(),
+            first_attr(&response.attributes, "sent_to_address").unwrap(),
+            "some-relayer"
+        );
+        assert_eq!(
+            first_attr(&response.attributes, "sent").unwrap(),
             "50000unois"
         );
         assert_eq!(
@@ -313,22 +310,30 @@ mod tests {
         // Zero amount is supported
         let info = mock_info(NOIS_GATEWAY, &[]);
         let msg = ExecuteMsg::Pay {
-            burn: Coin::new(0, "unois"),
-            community_pool: Coin::new(0, "unois"),
-            relayer: ("some-relayer".to_string(), Coin::new(0, "unois")),
+            payments: payments(0, 0, 0),
         };
         let response = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
         // 0 because sink does not like empty funds array and bank send does not like zero coins
         assert_eq!(response.messages.len(), 0);
         assert_eq!(first_attr(&response.attributes, "burnt").unwrap(), "0unois");
-        assert_eq!(
-            first_attr(&response.attributes, "relayer_reward").unwrap(),
-            "0unois"
-        );
+        assert_eq!(first_attr(&response.attributes, "sent").unwrap(), "0unois");
         assert_eq!(
             first_attr(&response.attributes, "sent_to_community_pool").unwrap(),
             "0unois"
         );
+
+        // Addresses are validated
+        let info = mock_info(NOIS_GATEWAY, &[]);
+        let msg = ExecuteMsg::Pay {
+            payments: vec![Payment {
+                destination: PaymentDestination::Address {
+                    address: "x".to_string(),
+                },
+                amount: Coin::new(50_000, "unois"),
+            }],
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidAddress));
     }
 
     #[test]
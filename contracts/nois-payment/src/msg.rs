@@ -12,11 +12,24 @@ pub struct InstantiateMsg {
 #[cw_serde]
 pub enum ExecuteMsg {
     /// Pays by distributing the funds according to what has been instructed by the gateway
-    Pay {
-        burn: Coin,
-        community_pool: Coin,
-        relayer: (String, Coin),
-    },
+    Pay { payments: Vec<Payment> },
+}
+
+/// Where a share of a payment is sent to
+#[cw_serde]
+pub enum PaymentDestination {
+    /// Burns the tokens via the Nois sink
+    Burn {},
+    /// Sends the tokens to the community pool
+    CommunityPool {},
+    /// Sends the tokens to the given address
+    Address { address: String },
+}
+
+#[cw_serde]
+pub struct Payment {
+    pub destination: PaymentDestination,
+    pub amount: Coin,
 }
 
 #[cw_serde]
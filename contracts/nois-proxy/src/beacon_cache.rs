@@ -0,0 +1,176 @@
+use cosmwasm_std::{Coin, HexBinary, Order, StdResult, Storage, Timestamp};
+use cw_storage_plus::Bound;
+use drand_common::{valid_round_after, DRAND_CHAIN_HASH};
+
+use crate::error::ContractError;
+use crate::state::{BeaconCacheSettings, CachedBeacon, BEACON_CACHE, BEACON_CACHE_SETTINGS};
+
+/// The maximum number of expired beacons removed from the cache when a new one is added
+const PRUNE_LIMIT: usize = 5;
+
+/// Checks that the discount is within the 0-100 % range
+pub fn validate_beacon_cache_settings(settings: &BeaconCacheSettings) -> Result<(), ContractError> {
+    if settings.discount_percent > 100 {
+        return Err(ContractError::InvalidBeaconCacheSettings);
+    }
+    Ok(())
+}
+
+/// Gets the drand round from a source ID like `drand:<chain hash>:<round>`.
+/// Returns None for other randomness sources.
+fn drand_round(source_id: &str) -> Option<u64> {
+    let mut parts = source_id.split(':');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("drand"), Some(DRAND_CHAIN_HASH), Some(round), None) => round.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Stores a delivered beacon in the cache if the cache is enabled.
+/// Expired beacons are removed along the way.
+pub fn cache_beacon(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    source_id: &str,
+    published: Timestamp,
+    randomness: &HexBinary,
+) -> StdResult<()> {
+    let Some(settings) = BEACON_CACHE_SETTINGS.may_load(storage)? else {
+        return Ok(());
+    };
+    let Some(round) = drand_round(source_id) else {
+        return Ok(());
+    };
+
+    // Rounds increase over time, so the oldest entries come first
+    let expired: Vec<u64> = BEACON_CACHE
+        .range(
+            storage,
+            None,
+            Some(Bound::exclusive(round)),
+            Order::Ascending,
+        )
+        .take(PRUNE_LIMIT)
+        .filter_map(|item| match item {
+            Ok((round, beacon)) if beacon.expires <= now => Some(Ok(round)),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<StdResult<_>>()?;
+    for round in expired {
+        BEACON_CACHE.remove(storage, round);
+    }
+
+    BEACON_CACHE.save(
+        storage,
+        round,
+        &CachedBeacon {
+            source_id: source_id.to_string(),
+            published,
+            randomness: randomness.clone(),
+            expires: now.plus_seconds(settings.lifetime),
+        },
+    )
+}
+
+/// Finds the beacon the gateway would deliver for a request with the given `after`
+/// value in the cache
+pub fn find_cached_beacon(
+    storage: &dyn Storage,
+    after: Timestamp,
+    now: Timestamp,
+) -> StdResult<Option<CachedBeacon>> {
+    if BEACON_CACHE_SETTINGS.may_load(storage)?.is_none() {
+        return Ok(None);
+    }
+    let round = valid_round_after(after);
+    Ok(BEACON_CACHE
+        .may_load(storage, round)?
+        .filter(|beacon| beacon.expires > now))
+}
+
+/// Applies the cache discount to the prices
+pub fn cached_prices(storage: &dyn Storage, prices: Vec<Coin>) -> StdResult<Vec<Coin>> {
+    let discount_percent = BEACON_CACHE_SETTINGS
+        .may_load(storage)?
+        .map(|settings| settings.discount_percent)
+        .unwrap_or_default();
+    Ok(prices
+        .into_iter()
+        .map(|price| Coin {
+            amount: price
+                .amount
+                .multiply_ratio(100 - discount_percent as u128, 100u128),
+            denom: price.denom,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+    use drand_common::time_of_round;
+
+    #[test]
+    fn drand_round_works() {
+        let source_id = format!("drand:{DRAND_CHAIN_HASH}:72780");
+        assert_eq!(drand_round(&source_id), Some(72780));
+        assert_eq!(drand_round("drand:abcd:72780"), None);
+        assert_eq!(drand_round(&format!("drand:{DRAND_CHAIN_HASH}:foo")), None);
+        assert_eq!(drand_round(&format!("{source_id}:1")), None);
+        assert_eq!(drand_round("backend:123:456"), None);
+    }
+
+    #[test]
+    fn cache_beacon_works() {
+        let mut storage = MockStorage::new();
+        let now = Timestamp::from_seconds(1677900000);
+        let randomness = HexBinary::from([0xaa; 32]);
+        let source_id = format!("drand:{DRAND_CHAIN_HASH}:72780");
+        let published = time_of_round(72780);
+        let after = published.minus_seconds(5);
+
+        // Disabled
+        cache_beacon(&mut storage, now, &source_id, published, &randomness).unwrap();
+        BEACON_CACHE_SETTINGS
+            .save(
+                &mut storage,
+                &BeaconCacheSettings {
+                    lifetime: 60,
+                    discount_percent: 20,
+                },
+            )
+            .unwrap();
+        assert_eq!(find_cached_beacon(&storage, after, now).unwrap(), None);
+
+        cache_beacon(&mut storage, now, &source_id, published, &randomness).unwrap();
+        let beacon = find_cached_beacon(&storage, after, now).unwrap().unwrap();
+        assert_eq!(beacon.published, published);
+        assert_eq!(beacon.randomness, randomness);
+        // Other round
+        assert_eq!(find_cached_beacon(&storage, published, now).unwrap(), None);
+        // Expired
+        assert_eq!(
+            find_cached_beacon(&storage, after, now.plus_seconds(60)).unwrap(),
+            None
+        );
+
+        // Expired beacons are removed when new ones are added
+        let later = now.plus_seconds(60);
+        let source_id2 = format!("drand:{DRAND_CHAIN_HASH}:72790");
+        cache_beacon(
+            &mut storage,
+            later,
+            &source_id2,
+            time_of_round(72790),
+            &randomness,
+        )
+        .unwrap();
+        assert!(!BEACON_CACHE.has(&storage, 72780));
+        assert!(BEACON_CACHE.has(&storage, 72790));
+
+        let prices = cached_prices(&storage, vec![Coin::new(1000, "unoisx")]).unwrap();
+        assert_eq!(prices, vec![Coin::new(800, "unoisx")]);
+    }
+}
@@ -0,0 +1,163 @@
+use cosmwasm_std::{Order, StdResult, Storage};
+
+use crate::error::ContractError;
+use crate::state::{
+    GatewayChannel, CONFIG, FIRST_CHANNEL_CONNECTED, GATEWAY_CHANNEL, GATEWAY_CHANNELS,
+};
+
+/// Stores a newly connected channel. The first channel ever connected becomes the
+/// primary channel. All further channels need to be approved by the manager, also
+/// after the primary channel closed without an approved backup.
+pub fn add_channel(storage: &mut dyn Storage, channel_id: &str) -> Result<(), ContractError> {
+    if GATEWAY_CHANNELS.has(storage, channel_id) {
+        return Err(ContractError::ChannelAlreadySet);
+    }
+    GATEWAY_CHANNELS.save(storage, channel_id, &GatewayChannel::default())?;
+    if !FIRST_CHANNEL_CONNECTED
+        .may_load(storage)?
+        .unwrap_or_default()
+    {
+        FIRST_CHANNEL_CONNECTED.save(storage, &true)?;
+        set_primary_channel(storage, channel_id)?;
+    }
+    Ok(())
+}
+
+/// Approves the channel and makes it the primary channel. The gateway is paid
+/// through the payment contract of this channel if it is known already.
+pub fn set_primary_channel(
+    storage: &mut dyn Storage,
+    channel_id: &str,
+) -> Result<(), ContractError> {
+    let mut channel = GATEWAY_CHANNELS
+        .may_load(storage, channel_id)?
+        .ok_or(ContractError::UnknownChannel)?;
+    channel.approved = true;
+    GATEWAY_CHANNELS.save(storage, channel_id, &channel)?;
+    GATEWAY_CHANNEL.save(storage, &channel_id.to_string())?;
+
+    if let Some(payment) = channel.payment {
+        CONFIG.update(storage, |mut config| -> StdResult<_> {
+            config.payment = Some(payment);
+            Ok(config)
+        })?;
+    }
+    Ok(())
+}
+
+/// Removes a closed channel. If this was the primary channel, the approved channel with
+/// the fewest timeouts takes over. Returns the new primary channel if it changed.
+pub fn remove_channel(
+    storage: &mut dyn Storage,
+    channel_id: &str,
+) -> Result<Option<String>, ContractError> {
+    GATEWAY_CHANNELS.remove(storage, channel_id);
+    if GATEWAY_CHANNEL.may_load(storage)?.as_deref() != Some(channel_id) {
+        return Ok(None);
+    }
+
+    let mut backup: Option<(String, GatewayChannel)> = None;
+    for item in GATEWAY_CHANNELS.range(storage, None, None, Order::Ascending) {
+        let (id, channel) = item?;
+        let better = match &backup {
+            Some((_, current)) => channel.timeouts < current.timeouts,
+            None => true,
+        };
+        if channel.approved && better {
+            backup = Some((id, channel));
+        }
+    }
+    match backup {
+        Some((id, _)) => {
+            set_primary_channel(storage, &id)?;
+            Ok(Some(id))
+        }
+        None => {
+            GATEWAY_CHANNEL.remove(storage);
+            Ok(None)
+        }
+    }
+}
+
+/// Errors if packets from the channel must not be processed
+pub fn check_channel_approved(
+    storage: &dyn Storage,
+    channel_id: &str,
+) -> Result<(), ContractError> {
+    match GATEWAY_CHANNELS.may_load(storage, channel_id)? {
+        Some(channel) if channel.approved => Ok(()),
+        _ => Err(ContractError::UnapprovedChannel {
+            channel_id: channel_id.to_string(),
+        }),
+    }
+}
+
+/// Applies the update to the channel if it is known
+pub fn update_channel(
+    storage: &mut dyn Storage,
+    channel_id: &str,
+    update: impl FnOnce(&mut GatewayChannel),
+) -> StdResult<()> {
+    if let Some(mut channel) = GATEWAY_CHANNELS.may_load(storage, channel_id)? {
+        update(&mut channel);
+        GATEWAY_CHANNELS.save(storage, channel_id, &channel)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::mock_dependencies;
+
+    #[test]
+    fn add_and_remove_channel_works() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+
+        add_channel(s, "channel-1").unwrap();
+        add_channel(s, "channel-2").unwrap();
+        add_channel(s, "channel-3").unwrap();
+        let err = add_channel(s, "channel-2").unwrap_err();
+        assert!(matches!(err, ContractError::ChannelAlreadySet));
+        assert_eq!(GATEWAY_CHANNEL.load(s).unwrap(), "channel-1");
+        check_channel_approved(s, "channel-1").unwrap();
+        let err = check_channel_approved(s, "channel-2").unwrap_err();
+        assert!(matches!(err, ContractError::UnapprovedChannel { .. }));
+        let err = check_channel_approved(s, "channel-4").unwrap_err();
+        assert!(matches!(err, ContractError::UnapprovedChannel { .. }));
+
+        // Approved backups with fewer timeouts are preferred
+        for id in ["channel-2", "channel-3"] {
+            update_channel(s, id, |channel| channel.approved = true).unwrap();
+        }
+        update_channel(s, "channel-2", |channel| channel.timeouts = 2).unwrap();
+        assert_eq!(remove_channel(s, "channel-2").unwrap(), None);
+        // Updates of removed channels are ignored
+        update_channel(s, "channel-2", |channel| channel.timeouts = 2).unwrap();
+        assert!(!GATEWAY_CHANNELS.has(s, "channel-2"));
+
+        add_channel(s, "channel-2").unwrap();
+        update_channel(s, "channel-2", |channel| channel.approved = true).unwrap();
+        update_channel(s, "channel-3", |channel| channel.timeouts = 1).unwrap();
+        assert_eq!(
+            remove_channel(s, "channel-1").unwrap(),
+            Some("channel-2".to_string())
+        );
+        assert_eq!(GATEWAY_CHANNEL.load(s).unwrap(), "channel-2");
+
+        // No approved backup left
+        update_channel(s, "channel-3", |channel| channel.approved = false).unwrap();
+        assert_eq!(remove_channel(s, "channel-2").unwrap(), None);
+        assert_eq!(GATEWAY_CHANNEL.may_load(s).unwrap(), None);
+
+        // New channels are not approved automatically anymore
+        add_channel(s, "channel-4").unwrap();
+        assert_eq!(GATEWAY_CHANNEL.may_load(s).unwrap(), None);
+        let err = check_channel_approved(s, "channel-4").unwrap_err();
+        assert!(matches!(err, ContractError::UnapprovedChannel { .. }));
+        set_primary_channel(s, "channel-4").unwrap();
+        assert_eq!(GATEWAY_CHANNEL.load(s).unwrap(), "channel-4");
+        check_channel_approved(s, "channel-4").unwrap();
+    }
+}
@@ -1,30 +1,87 @@
 use anything::Anything;
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
 use cosmwasm_std::{
     attr, ensure_eq, from_binary, from_slice, to_binary, Addr, Attribute, BankMsg, Binary, Coin,
-    CosmosMsg, Deps, DepsMut, Env, Event, HexBinary, Ibc3ChannelOpenResponse, IbcBasicResponse,
-    IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcMsg, IbcPacketAckMsg,
-    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, MessageInfo, Never,
-    QueryResponse, Reply, Response, StdError, StdResult, Storage, SubMsg, SubMsgResult, Timestamp,
-    Uint128, WasmMsg,
+    CosmosMsg, Deps, DepsMut, Empty, Env, Event, HexBinary, Ibc3ChannelOpenResponse,
+    IbcBasicResponse, IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcMsg,
+    IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, MessageInfo,
+    Never, Order, QuerierWrapper, QueryResponse, Reply, Response, StdError, StdResult, Storage,
+    SubMsg, SubMsgResult, Timestamp, Uint128, WasmMsg,
 };
-#[cfg(not(feature = "library"))]
-use cosmwasm_std::{entry_point, Empty};
+use cw2::{get_contract_version, set_contract_version, ContractVersion};
+use cw20::Cw20ReceiveMsg;
+use cw_storage_plus::{Bound, Map};
 use nois::{NoisCallback, ReceiverExecuteMsg};
 use nois_protocol::{
-    check_order, check_version, InPacket, InPacketAck, OutPacket, OutPacketAck, StdAck,
+    check_order, check_version, AckErrorCode, InPacket, InPacketAck, OutPacket, OutPacketAck,
+    StdAck, MAX_REQUEST_BEACON_BATCH_SIZE, PULL_BEACON_PRICE_PACKET_LIFETIME,
     REQUEST_BEACON_PACKET_LIFETIME, TRANSFER_PACKET_LIFETIME,
 };
 
+use crate::beacon_cache::{
+    cache_beacon, cached_prices, find_cached_beacon, validate_beacon_cache_settings,
+};
+use crate::channels::{
+    add_channel, check_channel_approved, remove_channel, set_primary_channel, update_channel,
+};
+use crate::credits::{add_credit, find_credit_payment, record_credit_usage, remove_credit};
 use crate::error::ContractError;
-use crate::jobs::{validate_job_id, validate_payment};
+use crate::escrow::{add_escrow, remove_escrow, withdrawable};
+use crate::gas_limits::{
+    callback_gas_limit_for, job_callback_gas_limit, validate_gas_bounds, validate_gas_limit,
+};
+use crate::jobs::{surplus, validate_job_id, validate_payment};
+use crate::migrations::{migrate_v0_13, parse_version};
 use crate::msg::{
-    ConfigResponse, ExecuteMsg, GatewayChannelResponse, InstantiateMsg, PriceResponse,
-    PricesResponse, QueryMsg, RequestBeaconOrigin, SudoMsg,
+    AddressListResponse, BeaconCacheResponse, BeaconPriceResponse, CallbackGasBoundsResponse,
+    CallbackGasLimitResponse, ConfigResponse, CreditHistoryResponse, CreditResponse,
+    DappStatsResponse, DebtResponse, DiscountsResponse, ErrorReceiverExecuteMsg, ExecuteMsg,
+    FailedCallbacksResponse, GatewayChannelResponse, GatewayChannelsResponse, GlobalStatsResponse,
+    InstantiateMsg, JobResponse, JobsResponse, MigrateMsg, NoisErrorCallback, NoisTimeoutCallback,
+    PriceOverridesResponse, PriceResponse, PricesResponse, QueriedCreditUsage, QueriedDappStats,
+    QueriedGatewayChannel, QueriedJob, QueriedPriceOverride, QueriedSubscription, QueryMsg,
+    ReceiveMsg, RequestBeaconOrigin, SubscriptionsResponse, SudoMsg, TopUpResponse,
 };
+use crate::prices::{effective_prices, record_requests, validate_discounts};
 use crate::publish_time::{calculate_after, AfterMode};
-use crate::state::{Config, OperationalMode, CONFIG, GATEWAY_CHANNEL};
+use crate::rate_limits::{check_rate_limits, validate_rate_limits};
+use crate::settlement::{
+    accrue_debt, clear_gateway_prepaid, is_settlement_due, load_settlement, return_gateway_payment,
+    reverse_gateway_debt, take_debt, use_gateway_prepaid,
+};
+use crate::state::{
+    create_job, AccessMode, AutoTopUp, BeaconCacheSettings, CallbackGasBounds, Config, CreditUsage,
+    CreditUsageKind, Delivery, DiscountTier, Discounts, GasTier, IbcDenom, Job, OperationalMode,
+    RateLimits, Subscription, TopUp, ALLOWLIST, BEACON_CACHE_SETTINGS, CALLBACK_DELIVERIES,
+    CALLBACK_GAS_BOUNDS, CALLBACK_GAS_LIMITS, CONFIG, CREDITS, CREDIT_HISTORY, DAPP_STATS,
+    DENYLIST, DISCOUNTS, FAILED_DELIVERIES, GATEWAY_CHANNEL, GATEWAY_CHANNELS, GLOBAL_STATS, JOBS,
+    JOBS_BY_SENDER, LAST_BEACON_PRICE_REQUEST, OUTGOING_REQUEST, PENDING_REQUESTS, PRICE_OVERRIDES,
+    REQUESTS_SINCE_TOP_UP, SETTLEMENT, SUBSCRIPTIONS, SUBSCRIPTION_REQUESTS, TOP_UP,
+};
+use crate::stats::{
+    count_delivery, count_failed_callback, count_payment, count_refund, count_requests,
+};
+use crate::tokens::{cw20_contract, cw20_denom, query_balance, send_msgs};
+
+const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub const CALLBACK_ID: u64 = 456;
+pub const SEND_BEACON_REQUEST_ID: u64 = 457;
+pub const ERROR_CALLBACK_ID: u64 = 458;
+pub const CONTINUE_SUBSCRIPTION_ID: u64 = 459;
+
+/// The minimal age in seconds of the stored beacon price before it can be refreshed
+pub const BEACON_PRICE_REFRESH_INTERVAL: u64 = 3600;
+
+/// The number of times a failed callback can be retried
+pub const MAX_CALLBACK_RETRIES: u32 = 3;
+/// The time in seconds after delivery during which a failed callback can be retried
+pub const CALLBACK_RETRY_PERIOD: u64 = 7 * 24 * 3600;
+/// The number of a dapp's oldest failed deliveries that are checked for expiry
+/// whenever one of its callbacks is executed
+const FAILED_DELIVERIES_PRUNE_LIMIT: usize = 10;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -39,6 +96,7 @@ pub fn instantiate(
         test_mode,
         callback_gas_limit,
         mode,
+        access_mode,
     } = msg;
     let manager = match manager {
         Some(ma) => Some(deps.api.addr_validate(&ma)?),
@@ -54,19 +112,77 @@ pub fn instantiate(
         nois_beacon_price: Uint128::zero(),
         nois_beacon_price_updated: Timestamp::from_seconds(0),
         mode,
+        access_mode: access_mode.unwrap_or(AccessMode::Open {}),
+        rate_limits: RateLimits::default(),
     };
     CONFIG.save(deps.storage, &config)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new()
         .add_attribute("action", "instantiate")
         .add_attribute("test_mode", test_mode.to_string()))
 }
 
-// This no-op migrate implementation allows us to upgrade within the 0.7 series.
-// No state changes expected.
+/// Migrates the state of contracts created by this or an older version of nois-proxy.
+///
+/// Contracts without cw2 version info were created by 0.13 or earlier.
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(_deps: DepsMut, _env: Env, _msg: Empty) -> StdResult<Response> {
-    Ok(Response::default())
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let MigrateMsg {
+        access_mode,
+        rate_limits,
+    } = msg;
+    if let Some(rate_limits) = &rate_limits {
+        validate_rate_limits(rate_limits)?;
+    }
+    let new_version = parse_version(CONTRACT_VERSION)?;
+
+    let previous_version = match get_contract_version(deps.storage) {
+        Ok(ContractVersion { contract, version }) => {
+            if contract != CONTRACT_NAME {
+                return Err(ContractError::CannotMigrateFromContract {
+                    previous_contract: contract,
+                });
+            }
+            let previous_version = parse_version(&version)?;
+            if previous_version > new_version {
+                return Err(ContractError::CannotDowngrade {
+                    previous_version: version,
+                    new_version: CONTRACT_VERSION.to_string(),
+                });
+            }
+            Some(previous_version)
+        }
+        Err(_) => None,
+    };
+
+    if previous_version.is_none() {
+        migrate_v0_13(deps.storage, AccessMode::Open {}, RateLimits::default())?;
+    }
+
+    if access_mode.is_some() || rate_limits.is_some() {
+        CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+            if let Some(access_mode) = access_mode {
+                config.access_mode = access_mode;
+            }
+            if let Some(rate_limits) = rate_limits {
+                config.rate_limits = rate_limits;
+            }
+            Ok(config)
+        })?;
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute(
+            "previous_version",
+            previous_version
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+        )
+        .add_attribute("new_version", CONTRACT_VERSION))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -76,16 +192,27 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    // Native funds must not impersonate CW20 tokens
+    if info
+        .funds
+        .iter()
+        .any(|coin| cw20_contract(&coin.denom).is_some())
+    {
+        return Err(ContractError::Cw20AsNativeFunds);
+    }
     match msg {
         ExecuteMsg::GetNextRandomness { job_id } => {
             execute_get_next_randomness(deps, env, info, job_id)
         }
+        ExecuteMsg::Receive(wrapper) => execute_receive(deps, env, info, wrapper),
         ExecuteMsg::SetConfig {
             manager,
             prices,
             payment,
             nois_beacon_price,
             mode,
+            access_mode,
+            rate_limits,
         } => execute_set_config(
             deps,
             info,
@@ -95,10 +222,66 @@ pub fn execute(
             payment,
             nois_beacon_price,
             mode,
+            access_mode,
+            rate_limits,
         ),
+        ExecuteMsg::SetPriceOverride { address, prices } => {
+            execute_set_price_override(deps, info, address, prices)
+        }
+        ExecuteMsg::SetBeaconCache { settings } => execute_set_beacon_cache(deps, info, settings),
+        ExecuteMsg::SetPrimaryGatewayChannel { channel_id } => {
+            execute_set_primary_gateway_channel(deps, info, channel_id)
+        }
+        ExecuteMsg::ApproveGatewayChannel {
+            channel_id,
+            approved,
+        } => execute_approve_gateway_channel(deps, info, channel_id, approved),
+        ExecuteMsg::SetTopUp { unois_denom, auto } => {
+            execute_set_top_up(deps, info, unois_denom, auto)
+        }
+        ExecuteMsg::TopUpPayment { amount } => execute_top_up_payment(deps, env, info, amount),
+        ExecuteMsg::SetDiscounts { period, tiers } => {
+            execute_set_discounts(deps, info, period, tiers)
+        }
+        ExecuteMsg::SetCallbackGasLimit { gas_limit, dapp } => {
+            execute_set_callback_gas_limit(deps, info, gas_limit, dapp)
+        }
+        ExecuteMsg::SetCallbackGasBounds { min, max, tiers } => {
+            execute_set_callback_gas_bounds(deps, info, min, max, tiers)
+        }
+        ExecuteMsg::UpdateAllowlist { add, remove } => {
+            execute_update_address_list(deps, info, ALLOWLIST, add, remove)
+        }
+        ExecuteMsg::UpdateDenylist { add, remove } => {
+            execute_update_address_list(deps, info, DENYLIST, add, remove)
+        }
         ExecuteMsg::GetRandomnessAfter { after, job_id } => {
             execute_get_randomness_after(deps, env, info, after, job_id)
         }
+        ExecuteMsg::GetRandomnessBatch { after, job_ids } => {
+            execute_get_randomness_batch(deps, env, info, after, job_ids)
+        }
+        ExecuteMsg::RefreshBeaconPrice {} => execute_refresh_beacon_price(deps, env),
+        ExecuteMsg::Settle {} => execute_settle(deps, env),
+        ExecuteMsg::RetryCallback {
+            request_id,
+            gas_limit,
+            sender,
+        } => execute_retry_callback(deps, env, info, request_id, gas_limit, sender),
+        ExecuteMsg::Subscribe {
+            interval,
+            count,
+            job_id_prefix,
+        } => execute_subscribe(deps, env, info, interval, count, job_id_prefix),
+        ExecuteMsg::Unsubscribe { job_id_prefix } => execute_unsubscribe(deps, info, job_id_prefix),
+        ExecuteMsg::ContinueSubscription {
+            owner,
+            job_id_prefix,
+        } => execute_continue_subscription(deps, env, owner, job_id_prefix),
+        ExecuteMsg::Deposit { beneficiary } => execute_deposit(deps, env, info, beneficiary),
+        ExecuteMsg::WithdrawCredit { denom, amount } => {
+            execute_withdraw_credit(deps, env, info, denom, amount)
+        }
         ExecuteMsg::Withdraw {
             denom,
             amount,
@@ -107,6 +290,35 @@ pub fn execute(
     }
 }
 
+fn execute_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    // The CW20 contract only forwards tokens. Native funds would be kept unaccounted.
+    if !info.funds.is_empty() {
+        return Err(ContractError::NativeFundsWithCw20);
+    }
+
+    // The request is made by the sender of the tokens, paying with the tokens of the calling contract
+    let info = MessageInfo {
+        sender: deps.api.addr_validate(&wrapper.sender)?,
+        funds: vec![Coin {
+            denom: cw20_denom(&info.sender),
+            amount: wrapper.amount,
+        }],
+    };
+    match from_binary(&wrapper.msg)? {
+        ReceiveMsg::GetNextRandomness { job_id } => {
+            execute_get_next_randomness(deps, env, info, job_id)
+        }
+        ReceiveMsg::GetRandomnessAfter { after, job_id } => {
+            execute_get_randomness_after(deps, env, info, after, job_id)
+        }
+    }
+}
+
 fn execute_get_next_randomness(
     deps: DepsMut,
     env: Env,
@@ -128,7 +340,7 @@ fn execute_get_next_randomness(
         info,
         config,
         after,
-        job_id,
+        vec![job_id],
         "execute_get_next_randomness",
     )
 }
@@ -147,55 +359,302 @@ fn execute_get_randomness_after(
         info,
         config,
         after,
-        job_id,
+        vec![job_id],
         "execute_get_randomness_after",
     )
 }
 
+fn execute_get_randomness_batch(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    after: Timestamp,
+    job_ids: Vec<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    execute_get_randomness_impl(
+        deps,
+        env,
+        info,
+        config,
+        after,
+        job_ids,
+        "execute_get_randomness_batch",
+    )
+}
+
 pub fn execute_get_randomness_impl(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     config: Config,
     after: Timestamp,
-    job_id: String,
+    job_ids: Vec<String>,
     action: &str,
 ) -> Result<Response, ContractError> {
-    validate_job_id(&job_id)?;
-    validate_payment(&config.prices, &info.funds)?;
+    if job_ids.is_empty() || job_ids.len() > MAX_REQUEST_BEACON_BATCH_SIZE {
+        return Err(ContractError::InvalidBatchSize {
+            max: MAX_REQUEST_BEACON_BATCH_SIZE,
+        });
+    }
+    for job_id in &job_ids {
+        validate_job_id(job_id)?;
+    }
+    check_access(deps.storage, &config.access_mode, &info.sender)?;
 
-    let packet = InPacket::RequestBeacon {
-        after,
-        origin: to_binary(&RequestBeaconOrigin {
-            sender: info.sender.into(),
+    let count = job_ids.len() as u32;
+    check_rate_limits(
+        deps.storage,
+        &config.rate_limits,
+        &info.sender,
+        &env.block,
+        count,
+    )?;
+    let prices = effective_prices(deps.storage, &config.prices, &info.sender, env.block.time)?;
+    record_requests(deps.storage, &info.sender, env.block.time, count)?;
+
+    // Single requests for a cached beacon are answered without an IBC round trip
+    let cached = if count == 1 {
+        find_cached_beacon(deps.storage, after, env.block.time)?
+    } else {
+        None
+    };
+    let prices = match cached {
+        Some(_) => cached_prices(deps.storage, prices)?,
+        None => prices,
+    };
+
+    // All jobs are paid at once
+    let total_prices: Vec<Coin> = prices
+        .into_iter()
+        .map(|price| Coin {
+            amount: price.amount * Uint128::from(count),
+            denom: price.denom,
+        })
+        .collect();
+
+    // Requests without funds are paid from the prepaid credit of the sender
+    let paid_with_credit = info.funds.is_empty();
+    let (charged, refund) = if paid_with_credit {
+        let charged = find_credit_payment(deps.storage, &info.sender, &total_prices)?;
+        remove_credit(deps.storage, &info.sender, &charged)?;
+        (charged, vec![])
+    } else {
+        let charged = validate_payment(&total_prices, &info.funds)?;
+        let refund = surplus(&info.funds, &charged);
+        (charged, refund)
+    };
+    count_requests(deps.storage, &info.sender, count)?;
+    count_payment(deps.storage, &info.sender, &charged)?;
+
+    // The share of a single job. This is exact since the total is a multiple of count.
+    let charged_per_job = Coin {
+        amount: charged.amount / Uint128::from(count),
+        denom: charged.denom.clone(),
+    };
+
+    // The gas limit the price was based on
+    let gas_limit = callback_gas_limit_for(deps.storage, config.callback_gas_limit, &info.sender)?;
+
+    let mut request_ids = Vec::with_capacity(job_ids.len());
+    let mut origins = Vec::with_capacity(job_ids.len());
+    for job_id in job_ids {
+        let request_id = create_job(
+            deps.storage,
+            &Job {
+                sender: info.sender.clone(),
+                job_id: job_id.clone(),
+                after,
+                funds: vec![charged_per_job.clone()],
+                paid_with_credit,
+                source_id: cached.as_ref().map(|beacon| beacon.source_id.clone()),
+                request_height: env.block.height,
+                delivery_height: cached.as_ref().map(|_| env.block.height),
+                callback_success: None,
+                error: None,
+                gateway_payment: None,
+                gateway_transfer: None,
+                callback_gas_limit: Some(gas_limit),
+            },
+        )?;
+        if paid_with_credit {
+            record_credit_usage(
+                deps.storage,
+                &info.sender,
+                &CreditUsage {
+                    time: env.block.time,
+                    amount: charged_per_job.clone(),
+                    kind: CreditUsageKind::Payment { request_id },
+                },
+            )?;
+        }
+        origins.push(to_binary(&RequestBeaconOrigin {
+            sender: info.sender.to_string(),
+            job_id,
+            request_id: Some(request_id),
+        })?);
+        request_ids.push(request_id);
+    }
+
+    let mut msgs: Vec<CosmosMsg> = Vec::with_capacity(2);
+
+    // Send back everything that was not charged
+    msgs.extend(send_msgs(&info.sender, &refund)?);
+
+    if let Some(beacon) = cached {
+        let request_id = request_ids[0];
+        let Job { sender, job_id, .. } = JOBS.load(deps.storage, request_id)?;
+        let delivery = Delivery {
+            sender,
             job_id,
-        })?,
+            published: beacon.published,
+            randomness: beacon.randomness,
+            request_id: Some(request_id),
+            retries: 0,
+            expires: env.block.time.plus_seconds(CALLBACK_RETRY_PERIOD),
+        };
+        let callback = callback_msg(&delivery, gas_limit)?;
+        CALLBACK_DELIVERIES.push_back(deps.storage, &delivery)?;
+        count_delivery(deps.storage, &delivery.sender)?;
+
+        return Ok(Response::new()
+            .add_messages(msgs)
+            .add_submessage(callback)
+            .add_attribute("action", action)
+            .add_attribute("request_id", request_id.to_string())
+            .add_attribute("cached", "true")
+            .add_attribute("charged", charged.to_string())
+            .add_attribute("refund", coins_to_string(&refund)));
+    }
+
+    let (payment_msgs, request_msg) =
+        beacon_request_msgs(deps, &env, config, after, request_ids.clone(), origins)?;
+    msgs.extend(payment_msgs);
+
+    let res = Response::new()
+        .add_messages(msgs)
+        .add_submessage(request_msg)
+        .add_attribute("action", action)
+        .add_attributes(
+            request_ids
+                .iter()
+                .map(|request_id| attr("request_id", request_id.to_string())),
+        )
+        .add_attribute("charged", charged.to_string())
+        .add_attribute("refund", coins_to_string(&refund));
+    Ok(res)
+}
+
+/// Stores the unois paid to the gateway for the given jobs, split evenly between them.
+/// `amount` includes prepaid unois and `transfer` is the part that was not prepaid.
+fn record_gateway_payment(
+    storage: &mut dyn Storage,
+    request_ids: &[u64],
+    amount: Uint128,
+    transfer: Uint128,
+) -> StdResult<()> {
+    let payments = split_evenly(amount, request_ids.len());
+    let transfers = split_evenly(transfer, request_ids.len());
+    for ((request_id, payment), transfer) in request_ids.iter().zip(payments).zip(transfers) {
+        JOBS.update(storage, *request_id, |job| -> StdResult<_> {
+            let mut job = job.ok_or_else(|| StdError::not_found("Job"))?;
+            job.gateway_payment = Some(payment);
+            job.gateway_transfer = Some(transfer);
+            Ok(job)
+        })?;
+    }
+    Ok(())
+}
+
+/// Splits the amount into `count` equal shares. The last share gets the remainder
+/// such that the total is preserved.
+fn split_evenly(amount: Uint128, count: usize) -> Vec<Uint128> {
+    let per_share = amount
+        .checked_div(Uint128::from(count as u64))
+        .unwrap_or_default();
+    let mut remaining = amount;
+    (0..count)
+        .map(|i| {
+            let share = if i + 1 == count { remaining } else { per_share };
+            remaining -= share;
+            share
+        })
+        .collect()
+}
+
+/// Creates the beacon request packet for the given jobs along with the payment
+/// of the gateway, depending on the operational mode.
+fn beacon_request_msgs(
+    deps: DepsMut,
+    env: &Env,
+    config: Config,
+    after: Timestamp,
+    request_ids: Vec<u64>,
+    mut origins: Vec<Binary>,
+) -> Result<(Vec<CosmosMsg>, SubMsg), ContractError> {
+    let count = request_ids.len() as u32;
+    OUTGOING_REQUEST.save(deps.storage, &request_ids)?;
+
+    // Single requests use the original packet type such that they can be
+    // processed by gateways without batch support.
+    let packet = if origins.len() == 1 {
+        InPacket::RequestBeacon {
+            after,
+            origin: origins.remove(0),
+        }
+    } else {
+        InPacket::RequestBeaconBatch { after, origins }
     };
     let channel_id = get_gateway_channel(deps.storage)?;
 
-    let mut msgs: Vec<CosmosMsg> = Vec::with_capacity(2);
+    let mut msgs: Vec<CosmosMsg> = Vec::with_capacity(1);
 
     // Add payment frist such that (at least in integration tests) the funds arrive in time
-    if let OperationalMode::IbcPay { unois_denom } = config.mode {
-        if let Some(payment_contract) = config.payment {
-            if !config.nois_beacon_price.is_zero() {
-                msgs.push(
-                    IbcMsg::Transfer {
-                        channel_id: unois_denom.ics20_channel,
-                        to_address: payment_contract,
-                        amount: Coin {
-                            amount: config.nois_beacon_price,
-                            denom: unois_denom.denom,
-                        },
-                        timeout: env.block.time.plus_seconds(TRANSFER_PACKET_LIFETIME).into(),
-                    }
-                    .into(),
-                );
+    if let Some(payment_contract) = config.payment {
+        let amount = config.nois_beacon_price * Uint128::from(count);
+        match config.mode {
+            OperationalMode::IbcPay { unois_denom } if !amount.is_zero() => {
+                let transfer = use_gateway_prepaid(deps.storage, amount)?;
+                if !transfer.is_zero() {
+                    msgs.push(unois_transfer(env, unois_denom, payment_contract, transfer));
+                }
+                record_gateway_payment(deps.storage, &request_ids, amount, transfer)?;
+            }
+            OperationalMode::IbcPayBatched {
+                unois_denom,
+                threshold,
+                interval,
+            } if !amount.is_zero() => {
+                let unpaid = use_gateway_prepaid(deps.storage, amount)?;
+                // The gateway charges the payment contract for every beacon, so the first
+                // settlement prefunds it with `threshold`. This covers the debt accrued
+                // until the next settlement.
+                let prefund = if SETTLEMENT.may_load(deps.storage)?.is_some() {
+                    Uint128::zero()
+                } else {
+                    threshold
+                };
+                let settlement = accrue_debt(deps.storage, env.block.time, unpaid + prefund)?;
+                record_gateway_payment(deps.storage, &request_ids, amount, unpaid)?;
+                if is_settlement_due(&settlement, env.block.time, threshold, interval) {
+                    let debt = take_debt(deps.storage, env.block.time)?;
+                    msgs.push(unois_transfer(env, unois_denom, payment_contract, debt));
+                }
             }
+            OperationalMode::Funded {} => {
+                if let Some(msg) =
+                    auto_top_up(deps.storage, &deps.querier, env, payment_contract, count)?
+                {
+                    msgs.push(msg);
+                }
+            }
+            _ => {}
         }
     }
 
-    msgs.push(
+    // We need the reply to learn the packet sequence, such that the acknowledgement
+    // can be mapped to this request.
+    let request_msg = SubMsg::reply_on_success(
         IbcMsg::SendPacket {
             channel_id,
             data: to_binary(&packet)?,
@@ -204,740 +663,5224 @@ pub fn execute_get_randomness_impl(
                 .time
                 .plus_seconds(REQUEST_BEACON_PACKET_LIFETIME)
                 .into(),
-        }
-        .into(),
+        },
+        SEND_BEACON_REQUEST_ID,
     );
 
-    let res = Response::new()
-        .add_messages(msgs)
-        .add_attribute("action", action);
-    Ok(res)
+    Ok((msgs, request_msg))
 }
 
-#[allow(clippy::too_many_arguments)]
-fn execute_set_config(
+/// Creates the ICS-20 transfer of IBCed unois to the payment contract on the Nois chain
+fn unois_transfer(
+    env: &Env,
+    unois_denom: IbcDenom,
+    payment_contract: String,
+    amount: Uint128,
+) -> CosmosMsg {
+    IbcMsg::Transfer {
+        channel_id: unois_denom.ics20_channel,
+        to_address: payment_contract,
+        amount: Coin {
+            amount,
+            denom: unois_denom.denom,
+        },
+        timeout: env.block.time.plus_seconds(TRANSFER_PACKET_LIFETIME).into(),
+    }
+    .into()
+}
+
+/// Counts the beacon requests and creates a top-up of the payment contract once
+/// `AutoTopUp::every_requests` is reached. If the proxy's balance is insufficient,
+/// the top-up is postponed to the next request.
+fn auto_top_up(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    env: &Env,
+    payment_contract: String,
+    count: u32,
+) -> StdResult<Option<CosmosMsg>> {
+    let (unois_denom, auto) = match TOP_UP.may_load(storage)? {
+        Some(TopUp {
+            unois_denom,
+            auto: Some(auto),
+        }) => (unois_denom, auto),
+        _ => return Ok(None),
+    };
+
+    let requests = REQUESTS_SINCE_TOP_UP.may_load(storage)?.unwrap_or_default() + count;
+    if requests >= auto.every_requests {
+        let balance = querier.query_balance(&env.contract.address, &unois_denom.denom)?;
+        if balance.amount >= auto.amount {
+            REQUESTS_SINCE_TOP_UP.save(storage, &0)?;
+            return Ok(Some(unois_transfer(
+                env,
+                unois_denom,
+                payment_contract,
+                auto.amount,
+            )));
+        }
+    }
+    REQUESTS_SINCE_TOP_UP.save(storage, &requests)?;
+    Ok(None)
+}
+
+fn execute_set_primary_gateway_channel(
     deps: DepsMut,
     info: MessageInfo,
-    env: Env,
-    manager: Option<String>,
-    prices: Option<Vec<Coin>>,
-    payment: Option<String>,
-    nois_beacon_price: Option<Uint128>,
-    mode: Option<OperationalMode>,
+    channel_id: String,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-
-    // if manager set, check the calling address is the authorised multisig otherwise error unauthorised
     ensure_eq!(
-        &info.sender,                                                // &Addr
-        config.manager.as_ref().ok_or(ContractError::Unauthorized)?, // &Addr
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
         ContractError::Unauthorized
     );
 
-    set_config_unchecked(deps, env, manager, prices, payment, nois_beacon_price, mode)
+    set_primary_gateway_channel_unchecked(deps, channel_id)
 }
 
-fn execute_withdraw(
+fn set_primary_gateway_channel_unchecked(
+    deps: DepsMut,
+    channel_id: String,
+) -> Result<Response, ContractError> {
+    set_primary_channel(deps.storage, &channel_id)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_primary_gateway_channel")
+        .add_attribute("channel_id", channel_id))
+}
+
+fn execute_approve_gateway_channel(
     deps: DepsMut,
-    env: Env,
     info: MessageInfo,
-    denom: String,
-    amount: Option<Uint128>,
-    address: String,
+    channel_id: String,
+    approved: bool,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-
-    // if manager set, check the calling address is the authorised multisig otherwise error unauthorised
     ensure_eq!(
-        &info.sender,                                                // &Addr
-        config.manager.as_ref().ok_or(ContractError::Unauthorized)?, // &Addr
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
         ContractError::Unauthorized
     );
 
-    withdraw_unchecked(deps, env, denom, amount, address)
+    approve_gateway_channel_unchecked(deps, channel_id, approved)
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-#[allow(unused)]
-pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
-    match msg {
-        #[cfg(feature = "governance_owned")]
-        SudoMsg::Withdraw {
-            denom,
-            amount,
-            address,
-        } => withdraw_unchecked(deps, env, denom, amount, address),
-        #[cfg(feature = "governance_owned")]
-        SudoMsg::WithdrawToCommunityPool { denom, amount } => {
-            withdraw_community_pool_unchecked(deps, env, denom, amount)
-        }
-        #[cfg(feature = "governance_owned")]
-        SudoMsg::SetConfig {
-            manager,
-            prices,
-            payment,
-            nois_beacon_price,
-            mode,
-        } => set_config_unchecked(deps, env, manager, prices, payment, nois_beacon_price, mode),
+fn approve_gateway_channel_unchecked(
+    deps: DepsMut,
+    channel_id: String,
+    approved: bool,
+) -> Result<Response, ContractError> {
+    let mut channel = GATEWAY_CHANNELS
+        .may_load(deps.storage, &channel_id)?
+        .ok_or(ContractError::UnknownChannel)?;
+    if !approved && GATEWAY_CHANNEL.may_load(deps.storage)?.as_ref() == Some(&channel_id) {
+        return Err(ContractError::CannotRevokePrimaryChannel);
     }
+    channel.approved = approved;
+    GATEWAY_CHANNELS.save(deps.storage, &channel_id, &channel)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "approve_gateway_channel")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("approved", approved.to_string()))
 }
 
-fn withdraw_unchecked(
+fn execute_set_beacon_cache(
     deps: DepsMut,
-    env: Env,
-    denom: String,
-    amount: Option<Uint128>,
-    address: String,
+    info: MessageInfo,
+    settings: Option<BeaconCacheSettings>,
 ) -> Result<Response, ContractError> {
-    let address = deps.api.addr_validate(&address)?;
-    let amount: Coin = match amount {
-        Some(amount) => Coin { denom, amount },
-        None => deps.querier.query_balance(env.contract.address, denom)?,
-    };
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+        ContractError::Unauthorized
+    );
 
-    let msg = BankMsg::Send {
-        to_address: address.into(),
-        amount: vec![amount.clone()],
-    };
-    let res = Response::new()
-        .add_message(msg)
-        .add_attribute("action", "withdraw")
-        .add_attribute("amount", amount.to_string());
-    Ok(res)
+    set_beacon_cache_unchecked(deps, settings)
 }
 
-#[allow(unused)]
-fn withdraw_community_pool_unchecked(
+fn set_beacon_cache_unchecked(
     deps: DepsMut,
-    env: Env,
-    denom: String,
-    amount: Option<Uint128>,
+    settings: Option<BeaconCacheSettings>,
 ) -> Result<Response, ContractError> {
-    let amount: Coin = match amount {
-        Some(amount) => Coin { denom, amount },
-        None => deps
-            .querier
-            .query_balance(env.contract.address.clone(), denom)?,
-    };
-
-    let msg = CosmosMsg::Stargate {
-        type_url: "/cosmos.distribution.v1beta1.MsgFundCommunityPool".to_string(),
-        value: encode_msg_fund_community_pool(&amount, &env.contract.address).into(),
-    };
+    match &settings {
+        Some(settings) => {
+            validate_beacon_cache_settings(settings)?;
+            BEACON_CACHE_SETTINGS.save(deps.storage, settings)?;
+        }
+        None => BEACON_CACHE_SETTINGS.remove(deps.storage),
+    }
 
-    let res = Response::new()
-        .add_message(msg)
-        .add_attribute("action", "withdraw_community_pool")
-        .add_attribute("amount", amount.to_string());
-    Ok(res)
+    Ok(Response::new()
+        .add_attribute("action", "set_beacon_cache")
+        .add_attribute("enabled", settings.is_some().to_string()))
 }
 
-fn set_config_unchecked(
+fn execute_set_top_up(
     deps: DepsMut,
-    env: Env,
-    manager: Option<String>,
-    prices: Option<Vec<Coin>>,
-    payment: Option<String>,
-    nois_beacon_price: Option<Uint128>,
-    mode: Option<OperationalMode>,
+    info: MessageInfo,
+    unois_denom: IbcDenom,
+    auto: Option<AutoTopUp>,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+        ContractError::Unauthorized
+    );
 
-    let manager = match manager {
-        Some(ma) => Some(deps.api.addr_validate(&ma)?),
-        None => config.manager,
-    };
-    let prices = prices.unwrap_or(config.prices);
+    set_top_up_unchecked(deps, unois_denom, auto)
+}
 
-    let test_mode = config.test_mode;
-    let callback_gas_limit = config.callback_gas_limit;
-    let payment = match payment {
-        Some(pa) => Some(pa),
-        None => config.payment,
-    };
-    let (nois_beacon_price, nois_beacon_price_updated) = match nois_beacon_price {
-        Some(bp) => (bp, env.block.time),
-        None => (config.nois_beacon_price, config.nois_beacon_price_updated),
-    };
-    let mode = mode.unwrap_or(config.mode);
+fn set_top_up_unchecked(
+    deps: DepsMut,
+    unois_denom: IbcDenom,
+    auto: Option<AutoTopUp>,
+) -> Result<Response, ContractError> {
+    if let Some(auto) = &auto {
+        if auto.every_requests == 0 {
+            return Err(ContractError::InvalidAutoTopUp);
+        }
+    }
+    TOP_UP.save(deps.storage, &TopUp { unois_denom, auto })?;
+    REQUESTS_SINCE_TOP_UP.save(deps.storage, &0)?;
 
-    let new_config = Config {
-        manager,
-        prices,
-        test_mode,
-        callback_gas_limit,
-        payment,
-        nois_beacon_price,
-        nois_beacon_price_updated,
-        mode,
-    };
+    Ok(Response::new().add_attribute("action", "set_top_up"))
+}
 
-    CONFIG.save(deps.storage, &new_config)?;
+fn execute_top_up_payment(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+        ContractError::Unauthorized
+    );
 
-    Ok(Response::default())
+    top_up_payment_unchecked(deps, env, amount)
 }
 
-fn get_gateway_channel(storage: &dyn Storage) -> Result<String, ContractError> {
-    let data = GATEWAY_CHANNEL.may_load(storage)?;
-    match data {
-        Some(d) => Ok(d),
-        None => Err(ContractError::UnsetChannel),
-    }
-}
+fn top_up_payment_unchecked(
+    deps: DepsMut,
+    env: Env,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let TopUp { unois_denom, .. } = TOP_UP
+        .may_load(deps.storage)?
+        .ok_or(ContractError::TopUpNotConfigured)?;
+    let payment_contract = CONFIG
+        .load(deps.storage)?
+        .payment
+        .ok_or(ContractError::PaymentContractNotSet)?;
 
-#[allow(unused)]
-fn encode_msg_fund_community_pool(amount: &Coin, depositor: &Addr) -> Vec<u8> {
-    // Coin: https://github.com/cosmos/cosmos-sdk/blob/v0.45.15/proto/cosmos/base/v1beta1/coin.proto#L14-L19
-    // MsgFundCommunityPool: https://github.com/cosmos/cosmos-sdk/blob/v0.45.15/proto/cosmos/distribution/v1beta1/tx.proto#L69-L76
-    let coin = Anything::new()
-        .append_bytes(1, &amount.denom)
-        .append_bytes(2, amount.amount.to_string());
-    Anything::new()
-        .append_message(1, &coin)
-        .append_bytes(2, depositor.as_bytes())
-        .into_vec()
+    Ok(Response::new()
+        .add_message(unois_transfer(&env, unois_denom, payment_contract, amount))
+        .add_attribute("action", "top_up_payment")
+        .add_attribute("amount", amount.to_string()))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn reply(_deps: DepsMut, _env: Env, reply: Reply) -> StdResult<Response> {
-    match reply.id {
-        CALLBACK_ID => {
-            let mut attributes = vec![];
-            match reply.result {
-                SubMsgResult::Ok(_) => attributes.push(Attribute::new("success", "true")),
-                SubMsgResult::Err(err) => {
-                    attributes.push(Attribute::new("success", "false"));
-                    attributes.push(Attribute::new("log", err));
-                }
-            };
-            let callback_event = Event::new("nois-callback").add_attributes(attributes);
-            Ok(Response::new().add_event(callback_event))
-        }
-        _ => Err(StdError::generic_err("invalid reply id or result")),
+fn execute_settle(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let Config { payment, mode, .. } = CONFIG.load(deps.storage)?;
+    let unois_denom = match mode {
+        OperationalMode::IbcPay { unois_denom }
+        | OperationalMode::IbcPayBatched { unois_denom, .. } => unois_denom,
+        OperationalMode::Funded {} => return Err(ContractError::SettlementNotSupported),
+    };
+    let payment_contract = payment.ok_or(ContractError::SettlementNotSupported)?;
+
+    if load_settlement(deps.storage, env.block.time)?
+        .debt
+        .is_zero()
+    {
+        return Err(ContractError::NoDebt);
     }
+    let debt = take_debt(deps.storage, env.block.time)?;
+
+    Ok(Response::new()
+        .add_message(unois_transfer(&env, unois_denom, payment_contract, debt))
+        .add_attribute("action", "settle")
+        .add_attribute("amount", debt.to_string()))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
-    match msg {
-        QueryMsg::Config {} => to_binary(&query_config(deps)?),
-        QueryMsg::Prices {} => to_binary(&query_prices(deps)?),
-        QueryMsg::Price { denom } => to_binary(&query_price(deps, denom)?),
-        QueryMsg::GatewayChannel {} => to_binary(&query_gateway_channel(deps)?),
-    }
+/// The time after which the beacon price can be refreshed. This is limited by both the
+/// last price update and the last refresh, such that unanswered refreshes are rate limited too.
+fn beacon_price_refreshable_after(
+    storage: &dyn Storage,
+    nois_beacon_price_updated: Timestamp,
+) -> StdResult<Timestamp> {
+    let last_request = LAST_BEACON_PRICE_REQUEST
+        .may_load(storage)?
+        .unwrap_or_default();
+    Ok(nois_beacon_price_updated
+        .max(last_request)
+        .plus_seconds(BEACON_PRICE_REFRESH_INTERVAL))
 }
 
-fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
-    let config = CONFIG.load(deps.storage)?;
-    Ok(ConfigResponse { config })
+fn execute_refresh_beacon_price(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let Config {
+        nois_beacon_price_updated,
+        ..
+    } = CONFIG.load(deps.storage)?;
+
+    let refreshable_after =
+        beacon_price_refreshable_after(deps.storage, nois_beacon_price_updated)?;
+    if env.block.time < refreshable_after {
+        return Err(ContractError::BeaconPriceRefreshTooEarly { refreshable_after });
+    }
+
+    let channel_id = get_gateway_channel(deps.storage)?;
+    let msg = IbcMsg::SendPacket {
+        channel_id,
+        data: to_binary(&InPacket::PullBeaconPrice {})?,
+        timeout: env
+            .block
+            .time
+            .plus_seconds(PULL_BEACON_PRICE_PACKET_LIFETIME)
+            .into(),
+    };
+    LAST_BEACON_PRICE_REQUEST.save(deps.storage, &env.block.time)?;
+
+    Ok(Response::new()
+        .add_message(msg)
+        .add_attribute("action", "refresh_beacon_price"))
 }
 
-fn query_prices(deps: Deps) -> StdResult<PricesResponse> {
-    let config = CONFIG.load(deps.storage)?;
-    Ok(PricesResponse {
-        prices: config.prices,
-    })
+/// The job ID of the beacon with the given index in a subscription
+fn subscription_job_id(job_id_prefix: &str, index: u32) -> String {
+    format!("{job_id_prefix}-{index}")
 }
 
-fn query_price(deps: Deps, denom: String) -> StdResult<PriceResponse> {
+fn execute_subscribe(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    interval: u64,
+    count: u32,
+    job_id_prefix: String,
+) -> Result<Response, ContractError> {
+    if interval == 0 || count == 0 {
+        return Err(ContractError::InvalidSubscription);
+    }
+    // All job IDs of the subscription must be valid
+    validate_job_id(&subscription_job_id(&job_id_prefix, u32::MAX))?;
     let config = CONFIG.load(deps.storage)?;
-    let price = config
-        .prices
-        .into_iter()
-        .find(|price| price.denom == denom)
-        .map(|coin| coin.amount);
-    Ok(PriceResponse { price })
-}
+    check_access(deps.storage, &config.access_mode, &info.sender)?;
+    check_rate_limits(
+        deps.storage,
+        &config.rate_limits,
+        &info.sender,
+        &env.block,
+        count,
+    )?;
+    if SUBSCRIPTIONS.has(deps.storage, (&info.sender, &job_id_prefix)) {
+        return Err(ContractError::SubscriptionExists);
+    }
 
-fn query_gateway_channel(deps: Deps) -> StdResult<GatewayChannelResponse> {
-    Ok(GatewayChannelResponse {
-        channel: GATEWAY_CHANNEL.may_load(deps.storage)?,
-    })
-}
+    let prices = effective_prices(deps.storage, &config.prices, &info.sender, env.block.time)?;
+    record_requests(deps.storage, &info.sender, env.block.time, count)?;
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-/// enforces ordering and versioing constraints
-pub fn ibc_channel_open(
-    _deps: DepsMut,
-    _env: Env,
-    msg: IbcChannelOpenMsg,
-) -> Result<Option<Ibc3ChannelOpenResponse>, ContractError> {
-    let channel = match msg {
-        IbcChannelOpenMsg::OpenInit { channel } => channel,
-        IbcChannelOpenMsg::OpenTry { .. } => return Err(ContractError::MustBeChainA),
+    // All beacons are paid upfront
+    let total_prices: Vec<Coin> = prices
+        .into_iter()
+        .map(|price| Coin {
+            amount: price.amount * Uint128::from(count),
+            denom: price.denom,
+        })
+        .collect();
+    let charged = validate_payment(&total_prices, &info.funds)?;
+    let refund = surplus(&info.funds, &charged);
+    count_payment(deps.storage, &info.sender, &charged)?;
+    // The beacons are held on behalf of the dapp until they are requested
+    add_escrow(deps.storage, &charged)?;
+
+    let mut subscription = Subscription {
+        interval,
+        remaining: count,
+        requested: 0,
+        price: Coin {
+            amount: charged.amount / Uint128::from(count),
+            denom: charged.denom.clone(),
+        },
+        next_after: env.block.time,
+        pending_request: None,
     };
 
-    check_order(&channel.order)?;
-    check_version(&channel.version)?;
+    let mut msgs = send_msgs(&info.sender, &refund)?;
+    let (payment_msgs, request_msg) = request_subscription_beacon(
+        deps,
+        &env,
+        config,
+        &info.sender,
+        &job_id_prefix,
+        &mut subscription,
+    )?;
+    msgs.extend(payment_msgs);
 
-    Ok(None)
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_submessage(request_msg)
+        .add_attribute("action", "subscribe")
+        .add_attribute("job_id_prefix", job_id_prefix)
+        .add_attribute("charged", charged.to_string())
+        .add_attribute("refund", coins_to_string(&refund)))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-/// Once established we store the channel ID to look up
-/// the destination address later.
-pub fn ibc_channel_connect(
+fn execute_unsubscribe(
     deps: DepsMut,
-    _env: Env,
-    msg: IbcChannelConnectMsg,
-) -> Result<IbcBasicResponse, ContractError> {
-    let channel = match msg {
-        IbcChannelConnectMsg::OpenAck {
-            channel,
-            counterparty_version: _,
-        } => channel,
-        IbcChannelConnectMsg::OpenConfirm { .. } => return Err(ContractError::MustBeChainA),
+    info: MessageInfo,
+    job_id_prefix: String,
+) -> Result<Response, ContractError> {
+    let subscription = SUBSCRIPTIONS
+        .may_load(deps.storage, (&info.sender, &job_id_prefix))?
+        .ok_or(ContractError::SubscriptionNotFound)?;
+    let (refund, msgs) =
+        cancel_subscription(deps.storage, &info.sender, &job_id_prefix, subscription)?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "unsubscribe")
+        .add_attribute("job_id_prefix", job_id_prefix)
+        .add_attribute("refund", refund.to_string()))
+}
+
+/// Removes the subscription and creates the messages refunding its remaining beacons.
+/// A request in flight is still delivered.
+fn cancel_subscription(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    job_id_prefix: &str,
+    subscription: Subscription,
+) -> StdResult<(Coin, Vec<CosmosMsg>)> {
+    SUBSCRIPTIONS.remove(storage, (owner, job_id_prefix));
+
+    let Subscription {
+        remaining, price, ..
+    } = subscription;
+    let refund = Coin {
+        amount: price.amount * Uint128::from(remaining),
+        denom: price.denom,
     };
+    count_refund(storage, owner, &refund)?;
+    remove_escrow(storage, &refund)?;
+    let msgs = if refund.amount.is_zero() {
+        vec![]
+    } else {
+        send_msgs(owner, std::slice::from_ref(&refund))?
+    };
+    Ok((refund, msgs))
+}
 
-    let channel_id = channel.endpoint.channel_id;
+fn execute_continue_subscription(
+    deps: DepsMut,
+    env: Env,
+    owner: String,
+    job_id_prefix: String,
+) -> Result<Response, ContractError> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let mut subscription = SUBSCRIPTIONS
+        .may_load(deps.storage, (&owner, &job_id_prefix))?
+        .ok_or(ContractError::SubscriptionNotFound)?;
+    if subscription.pending_request.is_some() || subscription.remaining == 0 {
+        return Err(ContractError::SubscriptionNotContinuable);
+    }
 
-    if GATEWAY_CHANNEL.may_load(deps.storage)?.is_some() {
-        return Err(ContractError::ChannelAlreadySet);
+    let config = CONFIG.load(deps.storage)?;
+    // The owner may have lost access since subscribing. The remaining beacons are refunded.
+    if check_access(deps.storage, &config.access_mode, &owner).is_err() {
+        let (refund, msgs) =
+            cancel_subscription(deps.storage, &owner, &job_id_prefix, subscription)?;
+        return Ok(Response::new()
+            .add_messages(msgs)
+            .add_attribute("action", "continue_subscription")
+            .add_attribute("job_id_prefix", job_id_prefix)
+            .add_attribute("access_denied", "true")
+            .add_attribute("refund", refund.to_string()));
     }
 
-    GATEWAY_CHANNEL.save(deps.storage, &channel_id)?;
-    Ok(IbcBasicResponse::new()
-        .add_attribute("action", "ibc_connect")
-        .add_attribute("channel_id", channel_id))
+    let (msgs, request_msg) = request_subscription_beacon(
+        deps,
+        &env,
+        config,
+        &owner,
+        &job_id_prefix,
+        &mut subscription,
+    )?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_submessage(request_msg)
+        .add_attribute("action", "continue_subscription")
+        .add_attribute("job_id_prefix", job_id_prefix)
+        .add_attribute(
+            "request_id",
+            subscription.pending_request.unwrap_or_default().to_string(),
+        ))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn ibc_channel_close(
+/// Creates the job for the next beacon of a subscription and the messages for requesting it
+fn request_subscription_beacon(
     deps: DepsMut,
-    _env: Env,
-    msg: IbcChannelCloseMsg,
-) -> Result<IbcBasicResponse, ContractError> {
-    match msg {
-        // This side of the channel never initiates a close.
-        // Transactions trying that should fail.
-        IbcChannelCloseMsg::CloseInit { channel: _ } => Err(ContractError::ChannelMustNotBeClosed),
-        // If the close is already done on the other chain we cannot
-        // stop that anymore. We ensure this transactions succeeds to
-        // allow the local channel's state to change to closed.
-        //
-        // By clearing the GATEWAY_CHANNEL we allow a new channel to be established.
-        IbcChannelCloseMsg::CloseConfirm { channel } => {
-            GATEWAY_CHANNEL.remove(deps.storage);
-            Ok(IbcBasicResponse::new()
-                .add_attribute("action", "ibc_close")
-                .add_attribute("channel_id", channel.endpoint.channel_id))
-        }
+    env: &Env,
+    config: Config,
+    owner: &Addr,
+    job_id_prefix: &str,
+    subscription: &mut Subscription,
+) -> Result<(Vec<CosmosMsg>, SubMsg), ContractError> {
+    // Requests for beacons in the past can be rejected by the gateway
+    let after = subscription.next_after.max(env.block.time);
+    let job_id = subscription_job_id(job_id_prefix, subscription.requested);
+    let gas_limit = callback_gas_limit_for(deps.storage, config.callback_gas_limit, owner)?;
+    let request_id = create_job(
+        deps.storage,
+        &Job {
+            sender: owner.clone(),
+            job_id: job_id.clone(),
+            after,
+            funds: vec![subscription.price.clone()],
+            paid_with_credit: false,
+            source_id: None,
+            request_height: env.block.height,
+            delivery_height: None,
+            callback_success: None,
+            error: None,
+            gateway_payment: None,
+            gateway_transfer: None,
+            callback_gas_limit: Some(gas_limit),
+        },
+    )?;
+    SUBSCRIPTION_REQUESTS.save(deps.storage, request_id, &job_id_prefix.to_string())?;
+    count_requests(deps.storage, owner, 1)?;
+
+    subscription.remaining -= 1;
+    remove_escrow(deps.storage, &subscription.price)?;
+    subscription.requested += 1;
+    subscription.next_after = after.plus_seconds(subscription.interval);
+    subscription.pending_request = Some(request_id);
+    SUBSCRIPTIONS.save(deps.storage, (owner, job_id_prefix), subscription)?;
+
+    let origin = to_binary(&RequestBeaconOrigin {
+        sender: owner.to_string(),
+        job_id,
+        request_id: Some(request_id),
+    })?;
+    beacon_request_msgs(deps, env, config, after, vec![request_id], vec![origin])
+}
+
+/// Marks the request of a subscription as done. Returns the job ID prefix of the
+/// subscription if more beacons have to be requested. Finished subscriptions are removed.
+fn finish_subscription_request(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    request_id: u64,
+) -> StdResult<Option<String>> {
+    let Some(job_id_prefix) = SUBSCRIPTION_REQUESTS.may_load(storage, request_id)? else {
+        return Ok(None);
+    };
+    SUBSCRIPTION_REQUESTS.remove(storage, request_id);
+
+    let key = (owner, job_id_prefix.as_str());
+    let Some(mut subscription) = SUBSCRIPTIONS.may_load(storage, key)? else {
+        // Unsubscribed in the meantime
+        return Ok(None);
+    };
+    if subscription.remaining == 0 {
+        SUBSCRIPTIONS.remove(storage, key);
+        return Ok(None);
     }
+    subscription.pending_request = None;
+    SUBSCRIPTIONS.save(storage, key, &subscription)?;
+    Ok(Some(job_id_prefix))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn ibc_packet_receive(
+fn execute_deposit(
     deps: DepsMut,
     env: Env,
-    msg: IbcPacketReceiveMsg,
-) -> Result<IbcReceiveResponse, Never> {
-    // put this in a closure so we can convert all error responses into acknowledgements
-    (|| {
-        let IbcPacketReceiveMsg { packet, .. } = msg;
-        let op: OutPacket = from_binary(&packet.data)?;
-        match op {
-            OutPacket::DeliverBeacon {
-                source_id: _,
-                published,
-                randomness,
-                origin,
-            } => receive_deliver_beacon(deps, published, randomness, origin),
-            OutPacket::Welcome { payment } => receive_welcome(deps, env, payment),
-            OutPacket::PushBeaconPrice {
-                timestamp,
-                amount,
-                denom,
-            } => receive_push_beacon_price(deps, env, timestamp, amount, denom),
-            _ => Err(ContractError::UnsupportedPacketType),
+    info: MessageInfo,
+    beneficiary: Option<String>,
+) -> Result<Response, ContractError> {
+    if info.funds.is_empty() {
+        return Err(ContractError::NoFundsSent);
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let beneficiary = match beneficiary {
+        Some(beneficiary) => deps.api.addr_validate(&beneficiary)?,
+        None => info.sender.clone(),
+    };
+
+    for amount in &info.funds {
+        if !config
+            .prices
+            .iter()
+            .any(|price| price.denom == amount.denom)
+        {
+            return Err(ContractError::UnsupportedDenom {
+                denom: amount.denom.clone(),
+            });
         }
-    })()
-    .or_else(|e| {
-        // we try to capture all app-level errors and convert them into
-        // acknowledgement packets that contain an error code.
-        let acknowledgement = StdAck::error(format!("Error processing packet: {e}"));
-        Ok(IbcReceiveResponse::new()
-            .set_ack(acknowledgement)
-            .add_event(Event::new("ibc").add_attribute("packet", "receive")))
-    })
+        add_credit(deps.storage, &beneficiary, amount)?;
+        record_credit_usage(
+            deps.storage,
+            &beneficiary,
+            &CreditUsage {
+                time: env.block.time,
+                amount: amount.clone(),
+                kind: CreditUsageKind::Deposit {
+                    depositor: info.sender.clone(),
+                },
+            },
+        )?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "deposit")
+        .add_attribute("beneficiary", beneficiary)
+        .add_attribute("amount", coins_to_string(&info.funds)))
 }
 
-fn receive_deliver_beacon(
+fn execute_withdraw_credit(
     deps: DepsMut,
-    published: Timestamp,
-    randomness: HexBinary,
-    origin: Binary,
-) -> Result<IbcReceiveResponse, ContractError> {
-    let Config {
-        callback_gas_limit, ..
-    } = CONFIG.load(deps.storage)?;
+    env: Env,
+    info: MessageInfo,
+    denom: String,
+    amount: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let amount = match amount {
+        Some(amount) => amount,
+        None => CREDITS
+            .may_load(deps.storage, (&info.sender, &denom))?
+            .unwrap_or_default(),
+    };
+    if amount.is_zero() {
+        return Err(ContractError::InsufficientCredit);
+    }
+    let amount = Coin { denom, amount };
+    remove_credit(deps.storage, &info.sender, &amount)?;
+    record_credit_usage(
+        deps.storage,
+        &info.sender,
+        &CreditUsage {
+            time: env.block.time,
+            amount: amount.clone(),
+            kind: CreditUsageKind::Withdrawal {},
+        },
+    )?;
 
-    let RequestBeaconOrigin { sender, job_id } = from_slice(&origin)?;
+    let msg = BankMsg::Send {
+        to_address: info.sender.to_string(),
+        amount: vec![amount.clone()],
+    };
+    Ok(Response::new()
+        .add_message(msg)
+        .add_attribute("action", "withdraw_credit")
+        .add_attribute("amount", amount.to_string()))
+}
 
-    // Create the message for executing the callback.
-    // This can fail for various reasons, like
-    // - `sender` not being a contract
-    // - the contract does not provide the NoisReceive {} interface
-    // - out of gas
-    // - any other processing error in the callback implementation
-    let msg = SubMsg::reply_on_error(
-        WasmMsg::Execute {
-            contract_addr: sender,
-            msg: to_binary(&ReceiverExecuteMsg::NoisReceive {
-                callback: NoisCallback {
-                    job_id: job_id.clone(),
-                    published,
-                    randomness,
-                },
-            })?,
-            funds: vec![],
-        },
-        CALLBACK_ID,
+#[allow(clippy::too_many_arguments)]
+fn execute_set_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    env: Env,
+    manager: Option<String>,
+    prices: Option<Vec<Coin>>,
+    payment: Option<String>,
+    nois_beacon_price: Option<Uint128>,
+    mode: Option<OperationalMode>,
+    access_mode: Option<AccessMode>,
+    rate_limits: Option<RateLimits>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    // if manager set, check the calling address is the authorised multisig otherwise error unauthorised
+    ensure_eq!(
+        &info.sender,                                                // &Addr
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?, // &Addr
+        ContractError::Unauthorized
+    );
+
+    set_config_unchecked(
+        deps,
+        env,
+        manager,
+        prices,
+        payment,
+        nois_beacon_price,
+        mode,
+        access_mode,
+        rate_limits,
     )
-    .with_gas_limit(callback_gas_limit);
+}
 
-    let ack = StdAck::success(OutPacketAck::DeliverBeacon {});
-    Ok(IbcReceiveResponse::new()
-        .set_ack(ack)
-        .add_attribute("action", "acknowledge_ibc_query")
-        .add_attribute("job_id", job_id)
-        .add_submessage(msg))
+fn execute_set_price_override(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    prices: Option<Vec<Coin>>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+        ContractError::Unauthorized
+    );
+
+    set_price_override_unchecked(deps, address, prices)
 }
 
-fn receive_welcome(
+fn set_price_override_unchecked(
     deps: DepsMut,
-    _env: Env,
-    payment: String,
-) -> Result<IbcReceiveResponse, ContractError> {
-    let mut config = CONFIG.load(deps.storage)?;
-    config.payment = Some(payment);
-    CONFIG.save(deps.storage, &config)?;
-    let ack = StdAck::success(OutPacketAck::Welcome {});
-    Ok(IbcReceiveResponse::new().set_ack(ack))
+    address: String,
+    prices: Option<Vec<Coin>>,
+) -> Result<Response, ContractError> {
+    let address = deps.api.addr_validate(&address)?;
+    match &prices {
+        Some(prices) => PRICE_OVERRIDES.save(deps.storage, &address, prices)?,
+        None => PRICE_OVERRIDES.remove(deps.storage, &address),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_price_override")
+        .add_attribute("address", address)
+        .add_attribute(
+            "prices",
+            prices.map(|p| coins_to_string(&p)).unwrap_or_default(),
+        ))
 }
 
-fn receive_push_beacon_price(
+fn execute_set_discounts(
     deps: DepsMut,
-    _env: Env,
-    timestamp: Timestamp,
-    amount: Uint128,
-    denom: String,
-) -> Result<IbcReceiveResponse, ContractError> {
-    update_nois_beacon_price(deps, timestamp, amount, denom)?;
-    let ack = StdAck::success(OutPacketAck::PushBeaconPrice {});
-    Ok(IbcReceiveResponse::new().set_ack(ack))
+    info: MessageInfo,
+    period: u64,
+    tiers: Vec<DiscountTier>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+        ContractError::Unauthorized
+    );
+
+    set_discounts_unchecked(deps, period, tiers)
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn ibc_packet_ack(
+fn set_discounts_unchecked(
     deps: DepsMut,
-    _env: Env,
-    msg: IbcPacketAckMsg,
-) -> Result<IbcBasicResponse, ContractError> {
-    let mut attributes = Vec::<Attribute>::new();
-    attributes.push(attr("action", "ack"));
-    let ack: StdAck = from_binary(&msg.acknowledgement.data)?;
-    let is_error: bool;
-    match ack {
-        StdAck::Result(data) => {
-            is_error = false;
-            let response: InPacketAck = from_binary(&data)?;
-            let ack_type: String = match response {
-                InPacketAck::RequestProcessed { source_id: _ } => "request_processed".to_string(),
-                InPacketAck::RequestQueued { source_id: _ } => "request_queued".to_string(),
-                InPacketAck::PullBeaconPrice {
-                    timestamp,
-                    amount,
-                    denom,
-                } => {
-                    update_nois_beacon_price(deps, timestamp, amount, denom)?;
-                    "beacon_price".to_string()
-                }
-                _ => "other".to_string(),
-            };
-            attributes.push(attr("ack_type", ack_type));
+    period: u64,
+    tiers: Vec<DiscountTier>,
+) -> Result<Response, ContractError> {
+    let discounts = Discounts { period, tiers };
+    validate_discounts(&discounts)?;
+    DISCOUNTS.save(deps.storage, &discounts)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_discounts")
+        .add_attribute("period", period.to_string())
+        .add_attribute("tiers", discounts.tiers.len().to_string()))
+}
+
+fn execute_set_callback_gas_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    gas_limit: Option<u64>,
+    dapp: Option<String>,
+) -> Result<Response, ContractError> {
+    let dapp = match dapp {
+        Some(dapp) => {
+            let dapp = deps.api.addr_validate(&dapp)?;
+            if dapp != info.sender {
+                let config = CONFIG.load(deps.storage)?;
+                ensure_eq!(
+                    &info.sender,
+                    config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+                    ContractError::Unauthorized
+                );
+            }
+            dapp
         }
-        StdAck::Error(err) => {
-            // The Request Beacon IBC packet failed, e.g. because the requested round
-            // is too old. Here we should send the dapp an error callback as the randomness
-            // will never come. Unfortunately we cannot map this packet to the job because
-            // we don't know the sequence when emitting a IbcMsg::SendPacket.
-            // https://github.com/CosmWasm/wasmd/issues/1154
-            is_error = true;
-            attributes.push(attr("error", err));
+        None => info.sender,
+    };
+
+    match gas_limit {
+        Some(gas_limit) => {
+            validate_gas_limit(deps.storage, gas_limit)?;
+            CALLBACK_GAS_LIMITS.save(deps.storage, &dapp, &gas_limit)?;
         }
+        None => CALLBACK_GAS_LIMITS.remove(deps.storage, &dapp),
     }
-    attributes.push(attr("is_error", is_error.to_string()));
-    Ok(IbcBasicResponse::new().add_attributes(attributes))
+
+    Ok(Response::new()
+        .add_attribute("action", "set_callback_gas_limit")
+        .add_attribute("dapp", dapp)
+        .add_attribute(
+            "gas_limit",
+            gas_limit.map(|l| l.to_string()).unwrap_or_default(),
+        ))
 }
 
-fn update_nois_beacon_price(
+fn execute_set_callback_gas_bounds(
     deps: DepsMut,
-    timestamp: Timestamp,
-    new_price: Uint128,
-    denom: String,
+    info: MessageInfo,
+    min: u64,
+    max: u64,
+    tiers: Vec<GasTier>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+        ContractError::Unauthorized
+    );
+
+    set_callback_gas_bounds_unchecked(deps, min, max, tiers)
+}
+
+fn set_callback_gas_bounds_unchecked(
+    deps: DepsMut,
+    min: u64,
+    max: u64,
+    tiers: Vec<GasTier>,
+) -> Result<Response, ContractError> {
+    let bounds = CallbackGasBounds { min, max, tiers };
+    validate_gas_bounds(&bounds)?;
+    CALLBACK_GAS_BOUNDS.save(deps.storage, &bounds)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_callback_gas_bounds")
+        .add_attribute("min", min.to_string())
+        .add_attribute("max", max.to_string()))
+}
+
+fn execute_update_address_list(
+    deps: DepsMut,
+    info: MessageInfo,
+    list: Map<&Addr, Empty>,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    ensure_eq!(
+        &info.sender,
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+        ContractError::Unauthorized
+    );
+
+    update_address_list_unchecked(deps, list, add, remove)
+}
+
+fn update_address_list_unchecked(
+    deps: DepsMut,
+    list: Map<&Addr, Empty>,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    for addr in &add {
+        let addr = deps.api.addr_validate(addr)?;
+        list.save(deps.storage, &addr, &Empty {})?;
+    }
+    for addr in &remove {
+        let addr = deps.api.addr_validate(addr)?;
+        list.remove(deps.storage, &addr);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_address_list")
+        .add_attribute("added", add.len().to_string())
+        .add_attribute("removed", remove.len().to_string()))
+}
+
+/// Checks if the given address is allowed to request beacons
+fn check_access(
+    storage: &dyn Storage,
+    access_mode: &AccessMode,
+    sender: &Addr,
 ) -> Result<(), ContractError> {
-    if denom != "unois" {
-        // We don't understand the denom of this price. Ignore the price info.
-        return Ok(());
+    let allowed = match access_mode {
+        AccessMode::Open {} => true,
+        AccessMode::Allowlist {} => ALLOWLIST.has(storage, sender),
+        AccessMode::Denylist {} => !DENYLIST.has(storage, sender),
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(ContractError::AccessDenied)
     }
+}
 
-    let mut config = CONFIG.load(deps.storage)?;
-    if config.nois_beacon_price_updated > timestamp {
-        // We just got an older information than we already have
-        return Ok(());
+fn execute_retry_callback(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    request_id: u64,
+    gas_limit: u64,
+    sender: Option<String>,
+) -> Result<Response, ContractError> {
+    let sender = match sender {
+        Some(sender) => {
+            let sender = deps.api.addr_validate(&sender)?;
+            if sender != info.sender {
+                let config = CONFIG.load(deps.storage)?;
+                ensure_eq!(
+                    &info.sender,
+                    config.manager.as_ref().ok_or(ContractError::Unauthorized)?,
+                    ContractError::Unauthorized
+                );
+            }
+            sender
+        }
+        None => info.sender,
+    };
+
+    let mut delivery = FAILED_DELIVERIES
+        .may_load(deps.storage, (&sender, request_id))?
+        .ok_or(ContractError::FailedCallbackNotFound)?;
+    if delivery.retries >= MAX_CALLBACK_RETRIES {
+        return Err(ContractError::CallbackRetriesExhausted);
+    }
+    if env.block.time > delivery.expires {
+        return Err(ContractError::CallbackExpired);
     }
 
-    config.nois_beacon_price = new_price;
-    config.nois_beacon_price_updated = timestamp;
-    CONFIG.save(deps.storage, &config)?;
-    Ok(())
+    delivery.retries += 1;
+    let msg = callback_msg(&delivery, gas_limit)?;
+    let retries = delivery.retries;
+    CALLBACK_DELIVERIES.push_back(deps.storage, &delivery)?;
+
+    Ok(Response::new()
+        .add_submessage(msg)
+        .add_attribute("action", "retry_callback")
+        .add_attribute("request_id", request_id.to_string())
+        .add_attribute("job_id", delivery.job_id)
+        .add_attribute("retries", retries.to_string()))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-/// we just ignore these now. shall we store some info?
-pub fn ibc_packet_timeout(
-    _deps: DepsMut,
-    _env: Env,
-    _msg: IbcPacketTimeoutMsg,
-) -> StdResult<IbcBasicResponse> {
-    Ok(IbcBasicResponse::new().add_attribute("action", "ibc_packet_timeout"))
+fn execute_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    denom: String,
+    amount: Option<Uint128>,
+    address: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    // if manager set, check the calling address is the authorised multisig otherwise error unauthorised
+    ensure_eq!(
+        &info.sender,                                                // &Addr
+        config.manager.as_ref().ok_or(ContractError::Unauthorized)?, // &Addr
+        ContractError::Unauthorized
+    );
+
+    withdraw_unchecked(deps, env, denom, amount, address)
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::state::OperationalMode;
+#[cfg_attr(not(feature = "library"), entry_point)]
+#[allow(unused)]
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::Withdraw {
+            denom,
+            amount,
+            address,
+        } => withdraw_unchecked(deps, env, denom, amount, address),
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::WithdrawToCommunityPool { denom, amount } => {
+            withdraw_community_pool_unchecked(deps, env, denom, amount)
+        }
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::SetConfig {
+            manager,
+            prices,
+            payment,
+            nois_beacon_price,
+            mode,
+            access_mode,
+            rate_limits,
+        } => set_config_unchecked(
+            deps,
+            env,
+            manager,
+            prices,
+            payment,
+            nois_beacon_price,
+            mode,
+            access_mode,
+            rate_limits,
+        ),
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::SetPriceOverride { address, prices } => {
+            set_price_override_unchecked(deps, address, prices)
+        }
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::SetDiscounts { period, tiers } => set_discounts_unchecked(deps, period, tiers),
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::SetBeaconCache { settings } => set_beacon_cache_unchecked(deps, settings),
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::SetPrimaryGatewayChannel { channel_id } => {
+            set_primary_gateway_channel_unchecked(deps, channel_id)
+        }
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::ApproveGatewayChannel {
+            channel_id,
+            approved,
+        } => approve_gateway_channel_unchecked(deps, channel_id, approved),
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::SetTopUp { unois_denom, auto } => set_top_up_unchecked(deps, unois_denom, auto),
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::TopUpPayment { amount } => top_up_payment_unchecked(deps, env, amount),
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::SetCallbackGasBounds { min, max, tiers } => {
+            set_callback_gas_bounds_unchecked(deps, min, max, tiers)
+        }
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::UpdateAllowlist { add, remove } => {
+            update_address_list_unchecked(deps, ALLOWLIST, add, remove)
+        }
+        #[cfg(feature = "governance_owned")]
+        SudoMsg::UpdateDenylist { add, remove } => {
+            update_address_list_unchecked(deps, DENYLIST, add, remove)
+        }
+    }
+}
+
+fn withdraw_unchecked(
+    deps: DepsMut,
+    env: Env,
+    denom: String,
+    amount: Option<Uint128>,
+    address: String,
+) -> Result<Response, ContractError> {
+    let address = deps.api.addr_validate(&address)?;
+    let balance = query_balance(&deps.querier, &env.contract.address, denom)?;
+    let amount = withdrawal_amount(deps.storage, balance, amount)?;
+
+    let res = Response::new()
+        .add_messages(send_msgs(&address, std::slice::from_ref(&amount))?)
+        .add_attribute("action", "withdraw")
+        .add_attribute("amount", amount.to_string());
+    Ok(res)
+}
+
+/// The amount to withdraw from the balance. Defaults to everything that can be withdrawn.
+fn withdrawal_amount(
+    storage: &dyn Storage,
+    balance: Coin,
+    amount: Option<Uint128>,
+) -> Result<Coin, ContractError> {
+    let available = withdrawable(storage, balance)?;
+    match amount {
+        Some(amount) if amount > available.amount => {
+            Err(ContractError::WithdrawalExceedsAvailable { available })
+        }
+        Some(amount) => Ok(Coin {
+            denom: available.denom,
+            amount,
+        }),
+        None => Ok(available),
+    }
+}
+
+#[allow(unused)]
+fn withdraw_community_pool_unchecked(
+    deps: DepsMut,
+    env: Env,
+    denom: String,
+    amount: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    // CW20 tokens cannot be sent to the community pool
+    if cw20_contract(&denom).is_some() {
+        return Err(ContractError::UnsupportedDenom { denom });
+    }
+    let balance = deps
+        .querier
+        .query_balance(env.contract.address.clone(), denom)?;
+    let amount = withdrawal_amount(deps.storage, balance, amount)?;
+
+    let msg = CosmosMsg::Stargate {
+        type_url: "/cosmos.distribution.v1beta1.MsgFundCommunityPool".to_string(),
+        value: encode_msg_fund_community_pool(&amount, &env.contract.address).into(),
+    };
+
+    let res = Response::new()
+        .add_message(msg)
+        .add_attribute("action", "withdraw_community_pool")
+        .add_attribute("amount", amount.to_string());
+    Ok(res)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn set_config_unchecked(
+    deps: DepsMut,
+    env: Env,
+    manager: Option<String>,
+    prices: Option<Vec<Coin>>,
+    payment: Option<String>,
+    nois_beacon_price: Option<Uint128>,
+    mode: Option<OperationalMode>,
+    access_mode: Option<AccessMode>,
+    rate_limits: Option<RateLimits>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let manager = match manager {
+        Some(ma) => Some(deps.api.addr_validate(&ma)?),
+        None => config.manager,
+    };
+    let prices = prices.unwrap_or(config.prices);
+
+    let test_mode = config.test_mode;
+    let callback_gas_limit = config.callback_gas_limit;
+    let payment = match payment {
+        Some(pa) => Some(pa),
+        None => config.payment,
+    };
+    let (nois_beacon_price, nois_beacon_price_updated) = match nois_beacon_price {
+        Some(bp) => (bp, env.block.time),
+        None => (config.nois_beacon_price, config.nois_beacon_price_updated),
+    };
+    let mode = mode.unwrap_or(config.mode);
+    let access_mode = access_mode.unwrap_or(config.access_mode);
+    let rate_limits = match rate_limits {
+        Some(rate_limits) => {
+            validate_rate_limits(&rate_limits)?;
+            rate_limits
+        }
+        None => config.rate_limits,
+    };
+
+    let new_config = Config {
+        manager,
+        prices,
+        test_mode,
+        callback_gas_limit,
+        payment,
+        nois_beacon_price,
+        nois_beacon_price_updated,
+        mode,
+        access_mode,
+        rate_limits,
+    };
+
+    CONFIG.save(deps.storage, &new_config)?;
+
+    Ok(Response::default())
+}
+
+fn get_gateway_channel(storage: &dyn Storage) -> Result<String, ContractError> {
+    let data = GATEWAY_CHANNEL.may_load(storage)?;
+    match data {
+        Some(d) => Ok(d),
+        None => Err(ContractError::UnsetChannel),
+    }
+}
+
+#[allow(unused)]
+fn encode_msg_fund_community_pool(amount: &Coin, depositor: &Addr) -> Vec<u8> {
+    // Coin: https://github.com/cosmos/cosmos-sdk/blob/v0.45.15/proto/cosmos/base/v1beta1/coin.proto#L14-L19
+    // MsgFundCommunityPool: https://github.com/cosmos/cosmos-sdk/blob/v0.45.15/proto/cosmos/distribution/v1beta1/tx.proto#L69-L76
+    let coin = Anything::new()
+        .append_bytes(1, &amount.denom)
+        .append_bytes(2, amount.amount.to_string());
+    Anything::new()
+        .append_message(1, &coin)
+        .append_bytes(2, depositor.as_bytes())
+        .into_vec()
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> StdResult<Response> {
+    match reply.id {
+        CALLBACK_ID => {
+            let mut attributes = vec![];
+            let success = match reply.result {
+                SubMsgResult::Ok(_) => {
+                    attributes.push(Attribute::new("success", "true"));
+                    true
+                }
+                SubMsgResult::Err(err) => {
+                    attributes.push(Attribute::new("success", "false"));
+                    attributes.push(Attribute::new("log", err));
+                    false
+                }
+            };
+            if let Some(delivery) = CALLBACK_DELIVERIES.pop_back(deps.storage)? {
+                if let Some(request_id) = delivery.request_id {
+                    JOBS.update(deps.storage, request_id, |job| -> StdResult<_> {
+                        let mut job = job.ok_or_else(|| StdError::not_found("Job"))?;
+                        job.callback_success = Some(success);
+                        Ok(job)
+                    })?;
+                    attributes.push(Attribute::new("request_id", request_id.to_string()));
+                }
+                if !success {
+                    count_failed_callback(deps.storage, &delivery.sender)?;
+                }
+                // Failed callbacks are stored such that they can be retried later on
+                if let Some(request_id) = delivery.request_id {
+                    let key = (&delivery.sender, request_id);
+                    if success || delivery.retries >= MAX_CALLBACK_RETRIES {
+                        FAILED_DELIVERIES.remove(deps.storage, key);
+                    } else {
+                        FAILED_DELIVERIES.save(deps.storage, key, &delivery)?;
+                    }
+                    prune_failed_deliveries(deps.storage, &delivery.sender, env.block.time)?;
+                }
+            }
+            let callback_event = Event::new("nois-callback").add_attributes(attributes);
+            Ok(Response::new().add_event(callback_event))
+        }
+        SEND_BEACON_REQUEST_ID => {
+            let response = reply.result.into_result().map_err(StdError::generic_err)?;
+            let (channel_id, sequence) = parse_send_packet_event(&response.events)?;
+            let request_ids = OUTGOING_REQUEST.load(deps.storage)?;
+            OUTGOING_REQUEST.remove(deps.storage);
+            PENDING_REQUESTS.save(deps.storage, (&channel_id, sequence), &request_ids)?;
+            Ok(Response::new()
+                .add_attribute("channel_id", channel_id)
+                .add_attribute("sequence", sequence.to_string()))
+        }
+        CONTINUE_SUBSCRIPTION_ID => {
+            // Only called on error. The subscription stays paused until someone
+            // executes `ContinueSubscription` or the dapp unsubscribes.
+            let err = reply.result.unwrap_err();
+            let event = Event::new("nois-subscription")
+                .add_attribute("success", "false")
+                .add_attribute("log", err);
+            Ok(Response::new().add_event(event))
+        }
+        ERROR_CALLBACK_ID => {
+            let mut attributes = vec![];
+            match reply.result {
+                SubMsgResult::Ok(_) => attributes.push(Attribute::new("success", "true")),
+                SubMsgResult::Err(err) => {
+                    attributes.push(Attribute::new("success", "false"));
+                    attributes.push(Attribute::new("log", err));
+                }
+            };
+            let callback_event = Event::new("nois-error-callback").add_attributes(attributes);
+            Ok(Response::new().add_event(callback_event))
+        }
+        _ => Err(StdError::generic_err("invalid reply id or result")),
+    }
+}
+
+/// Gets the channel ID and packet sequence from the `send_packet` event
+/// emitted by the IBC module when sending a packet.
+fn parse_send_packet_event(events: &[Event]) -> StdResult<(String, u64)> {
+    let event = events
+        .iter()
+        .find(|e| e.ty == "send_packet")
+        .ok_or_else(|| StdError::generic_err("Missing send_packet event"))?;
+    let attribute = |key: &str| {
+        event
+            .attributes
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+            .ok_or_else(|| StdError::generic_err(format!("Missing attribute {key}")))
+    };
+    let channel_id = attribute("packet_src_channel")?;
+    let sequence = attribute("packet_sequence")?
+        .parse::<u64>()
+        .map_err(|e| StdError::generic_err(format!("Invalid packet sequence: {e}")))?;
+    Ok((channel_id, sequence))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Prices {} => to_binary(&query_prices(deps)?),
+        QueryMsg::Price { denom } => to_binary(&query_price(deps, denom)?),
+        QueryMsg::GatewayChannel {} => to_binary(&query_gateway_channel(deps)?),
+        QueryMsg::GatewayChannels { start_after, limit } => {
+            to_binary(&query_gateway_channels(deps, start_after, limit)?)
+        }
+        QueryMsg::BeaconPrice {} => to_binary(&query_beacon_price(deps, env)?),
+        QueryMsg::BeaconCache {} => to_binary(&query_beacon_cache(deps)?),
+        QueryMsg::TopUp {} => to_binary(&query_top_up(deps)?),
+        QueryMsg::Debt {} => to_binary(&query_debt(deps)?),
+        QueryMsg::Job { request_id } => to_binary(&query_job(deps, request_id)?),
+        QueryMsg::JobsBySender {
+            sender,
+            start_after,
+            limit,
+        } => to_binary(&query_jobs_by_sender(deps, sender, start_after, limit)?),
+        QueryMsg::FailedCallbacks {
+            sender,
+            start_after,
+            limit,
+        } => to_binary(&query_failed_callbacks(deps, sender, start_after, limit)?),
+        QueryMsg::PriceFor { address } => to_binary(&query_price_for(deps, env, address)?),
+        QueryMsg::PriceOverrides { start_after, limit } => {
+            to_binary(&query_price_overrides(deps, start_after, limit)?)
+        }
+        QueryMsg::DappStats { start_after, limit } => {
+            to_binary(&query_dapp_stats(deps, start_after, limit)?)
+        }
+        QueryMsg::GlobalStats {} => to_binary(&query_global_stats(deps)?),
+        QueryMsg::Discounts {} => to_binary(&query_discounts(deps)?),
+        QueryMsg::CallbackGasLimit { address } => {
+            to_binary(&query_callback_gas_limit(deps, address)?)
+        }
+        QueryMsg::CallbackGasBounds {} => to_binary(&query_callback_gas_bounds(deps)?),
+        QueryMsg::Allowlist { start_after, limit } => {
+            to_binary(&query_address_list(deps, ALLOWLIST, start_after, limit)?)
+        }
+        QueryMsg::Denylist { start_after, limit } => {
+            to_binary(&query_address_list(deps, DENYLIST, start_after, limit)?)
+        }
+        QueryMsg::Subscriptions {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&query_subscriptions(deps, owner, start_after, limit)?),
+        QueryMsg::Credit { address } => to_binary(&query_credit(deps, address)?),
+        QueryMsg::CreditHistory {
+            address,
+            start_after,
+            limit,
+        } => to_binary(&query_credit_history(deps, address, start_after, limit)?),
+    }
+}
+
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ConfigResponse { config })
+}
+
+fn query_prices(deps: Deps) -> StdResult<PricesResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(PricesResponse {
+        prices: config.prices,
+    })
+}
+
+fn query_price(deps: Deps, denom: String) -> StdResult<PriceResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let price = config
+        .prices
+        .into_iter()
+        .find(|price| price.denom == denom)
+        .map(|coin| coin.amount);
+    Ok(PriceResponse { price })
+}
+
+fn query_price_for(deps: Deps, env: Env, address: String) -> StdResult<PricesResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let config = CONFIG.load(deps.storage)?;
+    let prices = effective_prices(deps.storage, &config.prices, &address, env.block.time)?;
+    Ok(PricesResponse { prices })
+}
+
+fn query_price_overrides(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<PriceOverridesResponse> {
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.as_ref().map(Bound::exclusive);
+
+    let overrides = PRICE_OVERRIDES
+        .range(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (address, prices) = item?;
+            Ok(QueriedPriceOverride {
+                address: address.into(),
+                prices,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(PriceOverridesResponse { overrides })
+}
+
+fn query_dapp_stats(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<DappStatsResponse> {
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.as_ref().map(Bound::exclusive);
+
+    let stats = DAPP_STATS
+        .range(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (address, stats) = item?;
+            Ok(QueriedDappStats {
+                address: address.into(),
+                stats,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(DappStatsResponse { stats })
+}
+
+fn query_global_stats(deps: Deps) -> StdResult<GlobalStatsResponse> {
+    Ok(GlobalStatsResponse {
+        stats: GLOBAL_STATS.may_load(deps.storage)?.unwrap_or_default(),
+    })
+}
+
+fn query_discounts(deps: Deps) -> StdResult<DiscountsResponse> {
+    Ok(DiscountsResponse {
+        discounts: DISCOUNTS.may_load(deps.storage)?,
+    })
+}
+
+fn query_callback_gas_limit(deps: Deps, address: String) -> StdResult<CallbackGasLimitResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let config = CONFIG.load(deps.storage)?;
+    let gas_limit = callback_gas_limit_for(deps.storage, config.callback_gas_limit, &address)?;
+    Ok(CallbackGasLimitResponse { gas_limit })
+}
+
+fn query_callback_gas_bounds(deps: Deps) -> StdResult<CallbackGasBoundsResponse> {
+    Ok(CallbackGasBoundsResponse {
+        bounds: CALLBACK_GAS_BOUNDS.may_load(deps.storage)?,
+    })
+}
+
+fn query_gateway_channel(deps: Deps) -> StdResult<GatewayChannelResponse> {
+    Ok(GatewayChannelResponse {
+        channel: GATEWAY_CHANNEL.may_load(deps.storage)?,
+    })
+}
+
+fn query_gateway_channels(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<GatewayChannelsResponse> {
+    let primary = GATEWAY_CHANNEL.may_load(deps.storage)?;
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.as_deref().map(Bound::exclusive);
+
+    let channels = GATEWAY_CHANNELS
+        .range(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (channel_id, channel) = item?;
+            Ok(QueriedGatewayChannel {
+                primary: primary.as_ref() == Some(&channel_id),
+                channel_id,
+                channel,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(GatewayChannelsResponse { channels })
+}
+
+fn query_beacon_cache(deps: Deps) -> StdResult<BeaconCacheResponse> {
+    Ok(BeaconCacheResponse {
+        settings: BEACON_CACHE_SETTINGS.may_load(deps.storage)?,
+    })
+}
+
+fn query_top_up(deps: Deps) -> StdResult<TopUpResponse> {
+    Ok(TopUpResponse {
+        top_up: TOP_UP.may_load(deps.storage)?,
+        requests_since_top_up: REQUESTS_SINCE_TOP_UP
+            .may_load(deps.storage)?
+            .unwrap_or_default(),
+    })
+}
+
+fn query_debt(deps: Deps) -> StdResult<DebtResponse> {
+    let settlement = SETTLEMENT.may_load(deps.storage)?;
+    Ok(DebtResponse {
+        debt: settlement.as_ref().map(|s| s.debt).unwrap_or_default(),
+        last_settled: settlement.map(|s| s.last_settled),
+    })
+}
+
+fn query_beacon_price(deps: Deps, env: Env) -> StdResult<BeaconPriceResponse> {
+    let Config {
+        nois_beacon_price,
+        nois_beacon_price_updated,
+        ..
+    } = CONFIG.load(deps.storage)?;
+    // The initial timestamp 0 means we never received a price
+    let updated = Some(nois_beacon_price_updated).filter(|t| t.nanos() != 0);
+    Ok(BeaconPriceResponse {
+        price: nois_beacon_price,
+        updated,
+        age: updated.map(|t| env.block.time.seconds().saturating_sub(t.seconds())),
+        refreshable_after: beacon_price_refreshable_after(deps.storage, nois_beacon_price_updated)?,
+    })
+}
+
+fn query_job(deps: Deps, request_id: u64) -> StdResult<JobResponse> {
+    let job = JOBS.may_load(deps.storage, request_id)?;
+    Ok(JobResponse {
+        job: job.map(|j| QueriedJob::new(request_id, j)),
+    })
+}
+
+fn query_jobs_by_sender(
+    deps: Deps,
+    sender: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<JobsResponse> {
+    let sender = deps.api.addr_validate(&sender)?;
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.map(Bound::exclusive);
+
+    let jobs: Vec<_> = JOBS_BY_SENDER
+        .prefix(&sender)
+        .keys(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|request_id| {
+            let request_id = request_id?;
+            let job = JOBS.load(deps.storage, request_id)?;
+            Ok(QueriedJob::new(request_id, job))
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(JobsResponse { jobs })
+}
+
+fn query_subscriptions(
+    deps: Deps,
+    owner: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<SubscriptionsResponse> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.as_deref().map(Bound::exclusive);
+
+    let subscriptions = SUBSCRIPTIONS
+        .prefix(&owner)
+        .range(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (job_id_prefix, subscription) = item?;
+            Ok(QueriedSubscription {
+                job_id_prefix,
+                subscription,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(SubscriptionsResponse { subscriptions })
+}
+
+fn query_address_list(
+    deps: Deps,
+    list: Map<&Addr, Empty>,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<AddressListResponse> {
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.as_ref().map(Bound::exclusive);
+
+    let addresses = list
+        .keys(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|addr| addr.map(String::from))
+        .collect::<StdResult<_>>()?;
+    Ok(AddressListResponse { addresses })
+}
+
+fn query_credit(deps: Deps, address: String) -> StdResult<CreditResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let credit = CREDITS
+        .prefix(&address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (denom, amount) = item?;
+            Ok(Coin { denom, amount })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(CreditResponse { credit })
+}
+
+fn query_credit_history(
+    deps: Deps,
+    address: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<CreditHistoryResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.map(Bound::exclusive);
+
+    let entries = CREDIT_HISTORY
+        .prefix(&address)
+        .range(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (index, usage) = item?;
+            Ok(QueriedCreditUsage::new(index, usage))
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(CreditHistoryResponse { entries })
+}
+
+/// Removes expired entries from the oldest failed deliveries of the sender
+fn prune_failed_deliveries(
+    storage: &mut dyn Storage,
+    sender: &Addr,
+    now: Timestamp,
+) -> StdResult<()> {
+    let expired = FAILED_DELIVERIES
+        .prefix(sender)
+        .range(storage, None, None, Order::Ascending)
+        .take(FAILED_DELIVERIES_PRUNE_LIMIT)
+        .filter(|item| !matches!(item, Ok((_, delivery)) if delivery.expires >= now))
+        .map(|item| item.map(|(request_id, _)| request_id))
+        .collect::<StdResult<Vec<_>>>()?;
+    for request_id in expired {
+        FAILED_DELIVERIES.remove(storage, (sender, request_id));
+    }
+    Ok(())
+}
+
+fn query_failed_callbacks(
+    deps: Deps,
+    sender: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<FailedCallbacksResponse> {
+    let sender = deps.api.addr_validate(&sender)?;
+    let limit = limit.unwrap_or(50) as usize;
+    let low_bound = start_after.map(Bound::exclusive);
+
+    let deliveries: Vec<_> = FAILED_DELIVERIES
+        .prefix(&sender)
+        .range(deps.storage, low_bound, None, Order::Ascending)
+        .take(limit)
+        .map(|d| d.map(|(_, delivery)| delivery))
+        .collect::<StdResult<_>>()?;
+
+    Ok(FailedCallbacksResponse { deliveries })
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+/// enforces ordering and versioing constraints
+pub fn ibc_channel_open(
+    _deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelOpenMsg,
+) -> Result<Option<Ibc3ChannelOpenResponse>, ContractError> {
+    let channel = match msg {
+        IbcChannelOpenMsg::OpenInit { channel } => channel,
+        IbcChannelOpenMsg::OpenTry { .. } => return Err(ContractError::MustBeChainA),
+    };
+
+    check_order(&channel.order)?;
+    check_version(&channel.version)?;
+
+    Ok(None)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+/// Once established we store the channel ID to look up
+/// the destination address later.
+pub fn ibc_channel_connect(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelConnectMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel = match msg {
+        IbcChannelConnectMsg::OpenAck {
+            channel,
+            counterparty_version: _,
+        } => channel,
+        IbcChannelConnectMsg::OpenConfirm { .. } => return Err(ContractError::MustBeChainA),
+    };
+
+    let channel_id = channel.endpoint.channel_id;
+    add_channel(deps.storage, &channel_id)?;
+
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_connect")
+        .add_attribute("channel_id", channel_id))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_close(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelCloseMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    match msg {
+        // This side of the channel never initiates a close.
+        // Transactions trying that should fail.
+        IbcChannelCloseMsg::CloseInit { channel: _ } => Err(ContractError::ChannelMustNotBeClosed),
+        // If the close is already done on the other chain we cannot
+        // stop that anymore. We ensure this transactions succeeds to
+        // allow the local channel's state to change to closed.
+        //
+        // If this was the primary channel, an approved backup channel takes over. Without
+        // backups, the manager has to approve a new channel via `SetPrimaryGatewayChannel`.
+        IbcChannelCloseMsg::CloseConfirm { channel } => {
+            let channel_id = channel.endpoint.channel_id;
+            let new_primary = remove_channel(deps.storage, &channel_id)?;
+            let mut res = IbcBasicResponse::new()
+                .add_attribute("action", "ibc_close")
+                .add_attribute("channel_id", &channel_id);
+            if let Some(new_primary) = new_primary {
+                res = res.add_attribute("new_primary_channel_id", new_primary);
+            }
+            Ok(res)
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_receive(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketReceiveMsg,
+) -> Result<IbcReceiveResponse, Never> {
+    // put this in a closure so we can convert all error responses into acknowledgements
+    (|| {
+        let IbcPacketReceiveMsg { packet, .. } = msg;
+        check_channel_approved(deps.storage, &packet.dest.channel_id)?;
+        let op: OutPacket = from_binary(&packet.data)?;
+        match op {
+            OutPacket::DeliverBeacon {
+                source_id,
+                published,
+                randomness,
+                origin,
+            } => receive_deliver_beacon(deps, env, source_id, published, randomness, origin),
+            OutPacket::Welcome { payment } => {
+                receive_welcome(deps, env, &packet.dest.channel_id, payment)
+            }
+            OutPacket::PushBeaconPrice {
+                timestamp,
+                amount,
+                denom,
+            } => receive_push_beacon_price(deps, env, timestamp, amount, denom),
+            _ => Err(ContractError::UnsupportedPacketType),
+        }
+    })()
+    .or_else(|e| {
+        // we try to capture all app-level errors and convert them into
+        // acknowledgement packets that contain an error code.
+        let acknowledgement = StdAck::error(format!("Error processing packet: {e}"));
+        Ok(IbcReceiveResponse::new()
+            .set_ack(acknowledgement)
+            .add_event(Event::new("ibc").add_attribute("packet", "receive")))
+    })
+}
+
+fn receive_deliver_beacon(
+    deps: DepsMut,
+    env: Env,
+    source_id: String,
+    published: Timestamp,
+    randomness: HexBinary,
+    origin: Binary,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let Config {
+        callback_gas_limit, ..
+    } = CONFIG.load(deps.storage)?;
+
+    let RequestBeaconOrigin {
+        sender,
+        job_id,
+        request_id,
+    } = from_slice(&origin)?;
+
+    cache_beacon(
+        deps.storage,
+        env.block.time,
+        &source_id,
+        published,
+        &randomness,
+    )?;
+
+    let sender = deps.api.addr_validate(&sender)?;
+
+    let callback_gas_limit = match request_id {
+        Some(request_id) => {
+            let job = JOBS.update(deps.storage, request_id, |job| -> StdResult<_> {
+                let mut job = job.ok_or_else(|| StdError::not_found("Job"))?;
+                job.source_id = Some(source_id);
+                job.delivery_height = Some(env.block.height);
+                Ok(job)
+            })?;
+            job_callback_gas_limit(deps.storage, callback_gas_limit, &job)?
+        }
+        None => callback_gas_limit_for(deps.storage, callback_gas_limit, &sender)?,
+    };
+
+    // The next request of a subscription is sent in a separate execution such that
+    // errors do not prevent this delivery
+    let subscription_msg = match request_id {
+        Some(request_id) => finish_subscription_request(deps.storage, &sender, request_id)?
+            .map(|job_id_prefix| -> StdResult<_> {
+                Ok(SubMsg::reply_on_error(
+                    WasmMsg::Execute {
+                        contract_addr: env.contract.address.to_string(),
+                        msg: to_binary(&ExecuteMsg::ContinueSubscription {
+                            owner: sender.to_string(),
+                            job_id_prefix,
+                        })?,
+                        funds: vec![],
+                    },
+                    CONTINUE_SUBSCRIPTION_ID,
+                ))
+            })
+            .transpose()?,
+        None => None,
+    };
+
+    count_delivery(deps.storage, &sender)?;
+    let delivery = Delivery {
+        sender,
+        job_id: job_id.clone(),
+        published,
+        randomness,
+        request_id,
+        retries: 0,
+        expires: env.block.time.plus_seconds(CALLBACK_RETRY_PERIOD),
+    };
+    let msg = callback_msg(&delivery, callback_gas_limit)?;
+    CALLBACK_DELIVERIES.push_back(deps.storage, &delivery)?;
+
+    let ack = StdAck::success(OutPacketAck::DeliverBeacon {});
+    Ok(IbcReceiveResponse::new()
+        .set_ack(ack)
+        .add_attribute("action", "acknowledge_ibc_query")
+        .add_attribute("job_id", job_id)
+        .add_submessage(msg)
+        .add_submessages(subscription_msg))
+}
+
+/// Creates the message for executing the callback.
+/// This can fail for various reasons, like
+/// - `sender` not being a contract
+/// - the contract does not provide the NoisReceive {} interface
+/// - out of gas
+/// - any other processing error in the callback implementation
+fn callback_msg(delivery: &Delivery, gas_limit: u64) -> StdResult<SubMsg> {
+    let msg = SubMsg::reply_always(
+        WasmMsg::Execute {
+            contract_addr: delivery.sender.to_string(),
+            msg: to_binary(&ReceiverExecuteMsg::NoisReceive {
+                callback: NoisCallback {
+                    job_id: delivery.job_id.clone(),
+                    published: delivery.published,
+                    randomness: delivery.randomness.clone(),
+                },
+            })?,
+            funds: vec![],
+        },
+        CALLBACK_ID,
+    )
+    .with_gas_limit(gas_limit);
+    Ok(msg)
+}
+
+fn receive_welcome(
+    deps: DepsMut,
+    _env: Env,
+    channel_id: &str,
+    payment: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    update_channel(deps.storage, channel_id, |channel| {
+        channel.payment = Some(payment.clone())
+    })?;
+    // Each channel has its own payment contract. We use the one of the primary channel.
+    if GATEWAY_CHANNEL.may_load(deps.storage)?.as_deref() == Some(channel_id) {
+        let mut config = CONFIG.load(deps.storage)?;
+        config.payment = Some(payment);
+        CONFIG.save(deps.storage, &config)?;
+    }
+    let ack = StdAck::success(OutPacketAck::Welcome {});
+    Ok(IbcReceiveResponse::new().set_ack(ack))
+}
+
+fn receive_push_beacon_price(
+    deps: DepsMut,
+    _env: Env,
+    timestamp: Timestamp,
+    amount: Uint128,
+    denom: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    update_nois_beacon_price(deps, timestamp, amount, denom)?;
+    let ack = StdAck::success(OutPacketAck::PushBeaconPrice {});
+    Ok(IbcReceiveResponse::new().set_ack(ack))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_ack(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketAckMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let mut attributes = Vec::<Attribute>::new();
+    attributes.push(attr("action", "ack"));
+    let ack: StdAck = from_binary(&msg.acknowledgement.data)?;
+    let mut msgs = Vec::<CosmosMsg>::new();
+    let mut submsgs = Vec::<SubMsg>::new();
+
+    // The jobs this packet was sent for. This is empty for other packet types
+    // as well as for requests sent before the proxy started tracking them.
+    let packet = msg.original_packet;
+    let mut jobs = take_pending_jobs(deps.storage, &packet.src.channel_id, packet.sequence)?;
+    let is_success = matches!(ack, StdAck::Result(_));
+    let error_code = ack.error_code();
+    update_channel(deps.storage, &packet.src.channel_id, |channel| {
+        if is_success {
+            channel.acks += 1;
+        } else {
+            channel.error_acks += 1;
+        }
+    })?;
+
+    let is_error: bool;
+    match ack {
+        StdAck::Result(data) => {
+            is_error = false;
+            let response: InPacketAck = from_binary(&data)?;
+            let ack_type: String = match response {
+                InPacketAck::RequestProcessed { source_id } => {
+                    set_job_source_id(deps.storage, &mut jobs, source_id)?;
+                    "request_processed".to_string()
+                }
+                InPacketAck::RequestQueued { source_id } => {
+                    set_job_source_id(deps.storage, &mut jobs, source_id)?;
+                    "request_queued".to_string()
+                }
+                InPacketAck::PullBeaconPrice {
+                    timestamp,
+                    amount,
+                    denom,
+                } => {
+                    update_nois_beacon_price(deps, timestamp, amount, denom)?;
+                    "beacon_price".to_string()
+                }
+                _ => "other".to_string(),
+            };
+            attributes.push(attr("ack_type", ack_type));
+        }
+        StdAck::Error(err) => {
+            // The Request Beacon IBC packet failed, e.g. because the requested round
+            // is too old. We refund the dapp and send it an error callback as the
+            // randomness will never come.
+            is_error = true;
+            let Config {
+                callback_gas_limit, ..
+            } = CONFIG.load(deps.storage)?;
+            let mut gateway_payment_total = Uint128::zero();
+            let mut gateway_transfer_total = Uint128::zero();
+            for (request_id, mut job) in jobs {
+                job.error = Some(err.clone());
+                JOBS.save(deps.storage, request_id, &job)?;
+                // Failed subscription requests pause the subscription
+                finish_subscription_request(deps.storage, &job.sender, request_id)?;
+                let gas_limit = job_callback_gas_limit(deps.storage, callback_gas_limit, &job)?;
+                let Job {
+                    sender,
+                    job_id,
+                    funds,
+                    paid_with_credit,
+                    gateway_payment,
+                    gateway_transfer,
+                    ..
+                } = job;
+                gateway_payment_total += gateway_payment.unwrap_or_default();
+                gateway_transfer_total += gateway_transfer.unwrap_or_default();
+
+                msgs.extend(refund_job(
+                    deps.storage,
+                    &env,
+                    request_id,
+                    &sender,
+                    &funds,
+                    paid_with_credit,
+                )?);
+                attributes.push(attr("job_id", &job_id));
+                attributes.push(attr("refund", coins_to_string(&funds)));
+                submsgs.push(
+                    SubMsg::reply_on_error(
+                        WasmMsg::Execute {
+                            contract_addr: sender.into(),
+                            msg: to_binary(&ErrorReceiverExecuteMsg::NoisReceiveError {
+                                callback: NoisErrorCallback {
+                                    job_id,
+                                    error: err.clone(),
+                                    refund: funds,
+                                },
+                            })?,
+                            funds: vec![],
+                        },
+                        ERROR_CALLBACK_ID,
+                    )
+                    .with_gas_limit(gas_limit),
+                );
+            }
+            // The gateway does not charge for failed requests, so their payment is kept
+            // for later requests. A request rejected with
+            // `AckErrorCode::InsufficientPaymentBalance` shows that the prepaid amount
+            // did not arrive at the payment contract, e.g. because its transfer timed out.
+            // The prepaid amount is dropped then and only the unois sent along with this
+            // request is kept, since it may still arrive after the request.
+            let returned = if error_code == Some(AckErrorCode::InsufficientPaymentBalance) {
+                let dropped = clear_gateway_prepaid(deps.storage)?;
+                attributes.push(attr("gateway_prepaid_dropped", dropped.to_string()));
+                gateway_transfer_total
+            } else {
+                gateway_payment_total
+            };
+            if !returned.is_zero() {
+                let prepaid = return_gateway_payment(deps.storage, returned)?;
+                attributes.push(attr("gateway_payment_returned", returned.to_string()));
+                attributes.push(attr("gateway_prepaid", prepaid.to_string()));
+            }
+            if let Some(code) = error_code {
+                attributes.push(attr("error_code", code.as_str()));
+            }
+            attributes.push(attr("error", err));
+        }
+    }
+    attributes.push(attr("is_error", is_error.to_string()));
+    Ok(IbcBasicResponse::new()
+        .add_messages(msgs)
+        .add_submessages(submsgs)
+        .add_attributes(attributes))
+}
+
+/// Removes the pending request entry of the given packet and loads the jobs sent in it
+fn take_pending_jobs(
+    storage: &mut dyn Storage,
+    channel_id: &str,
+    sequence: u64,
+) -> StdResult<Vec<(u64, Job)>> {
+    let request_ids = PENDING_REQUESTS
+        .may_load(storage, (channel_id, sequence))?
+        .unwrap_or_default();
+    PENDING_REQUESTS.remove(storage, (channel_id, sequence));
+    request_ids
+        .into_iter()
+        .map(|request_id| Ok((request_id, JOBS.load(storage, request_id)?)))
+        .collect()
+}
+
+/// Stores the source ID from the gateway's ack in the jobs
+fn set_job_source_id(
+    storage: &mut dyn Storage,
+    jobs: &mut [(u64, Job)],
+    source_id: String,
+) -> StdResult<()> {
+    for (request_id, job) in jobs {
+        job.source_id = Some(source_id.clone());
+        JOBS.save(storage, *request_id, job)?;
+    }
+    Ok(())
+}
+
+fn update_nois_beacon_price(
+    deps: DepsMut,
+    timestamp: Timestamp,
+    new_price: Uint128,
+    denom: String,
+) -> Result<(), ContractError> {
+    if denom != "unois" {
+        // We don't understand the denom of this price. Ignore the price info.
+        return Ok(());
+    }
+
+    let mut config = CONFIG.load(deps.storage)?;
+    if config.nois_beacon_price_updated > timestamp {
+        // We just got an older information than we already have
+        return Ok(());
+    }
+
+    config.nois_beacon_price = new_price;
+    config.nois_beacon_price_updated = timestamp;
+    CONFIG.save(deps.storage, &config)?;
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+/// Refunds the dapp and sends it a timeout callback when a beacon request timed out.
+/// Requests paid from prepaid credit are refunded to the credit.
+///
+/// This is only called for packets on the gateway channel. The proxy is not notified
+/// about ICS-20 transfers sent in `OperationalMode::IbcPay`. Since the gateway never
+/// charged for a timed out request, unsettled debt of `OperationalMode::IbcPayBatched`
+/// is reduced. Unois already sent is not counted as prepaid because the transfer may
+/// have timed out as well. It either stays in the payment contract as a surplus or was
+/// refunded to the proxy's balance by the transfer module and is used for later transfers.
+pub fn ibc_packet_timeout(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let packet = msg.packet;
+    let jobs = take_pending_jobs(deps.storage, &packet.src.channel_id, packet.sequence)?;
+    update_channel(deps.storage, &packet.src.channel_id, |channel| {
+        channel.timeouts += 1
+    })?;
+
+    let mut res = IbcBasicResponse::new().add_attribute("action", "ibc_packet_timeout");
+
+    let Config {
+        callback_gas_limit, ..
+    } = CONFIG.load(deps.storage)?;
+    let mut gateway_payment_total = Uint128::zero();
+    for (request_id, mut job) in jobs {
+        job.error = Some("timeout".to_string());
+        JOBS.save(deps.storage, request_id, &job)?;
+        // Timed out subscription requests pause the subscription
+        finish_subscription_request(deps.storage, &job.sender, request_id)?;
+        let gas_limit = job_callback_gas_limit(deps.storage, callback_gas_limit, &job)?;
+        let Job {
+            sender,
+            job_id,
+            funds,
+            paid_with_credit,
+            gateway_payment,
+            ..
+        } = job;
+        gateway_payment_total += gateway_payment.unwrap_or_default();
+
+        res = res.add_messages(refund_job(
+            deps.storage,
+            &env,
+            request_id,
+            &sender,
+            &funds,
+            paid_with_credit,
+        )?);
+        res = res
+            .add_attribute("job_id", &job_id)
+            .add_attribute("refund", coins_to_string(&funds))
+            .add_submessage(
+                SubMsg::reply_on_error(
+                    WasmMsg::Execute {
+                        contract_addr: sender.into(),
+                        msg: to_binary(&ErrorReceiverExecuteMsg::NoisReceiveTimeout {
+                            callback: NoisTimeoutCallback {
+                                job_id,
+                                refund: funds,
+                            },
+                        })?,
+                        funds: vec![],
+                    },
+                    ERROR_CALLBACK_ID,
+                )
+                .with_gas_limit(gas_limit),
+            );
+    }
+    if !gateway_payment_total.is_zero() {
+        let reversed = reverse_gateway_debt(deps.storage, gateway_payment_total)?;
+        res = res.add_attribute("gateway_debt_reversed", reversed.to_string());
+    }
+    Ok(res)
+}
+
+/// Refunds the funds a job was paid with. Jobs paid from credit are refunded to the
+/// credit, all others by sending the funds back to the sender.
+fn refund_job(
+    storage: &mut dyn Storage,
+    env: &Env,
+    request_id: u64,
+    sender: &Addr,
+    funds: &[Coin],
+    paid_with_credit: bool,
+) -> StdResult<Vec<CosmosMsg>> {
+    for amount in funds {
+        count_refund(storage, sender, amount)?;
+    }
+    if !paid_with_credit {
+        return send_msgs(sender, funds);
+    }
+    for amount in funds {
+        add_credit(storage, sender, amount)?;
+        record_credit_usage(
+            storage,
+            sender,
+            &CreditUsage {
+                time: env.block.time,
+                amount: amount.clone(),
+                kind: CreditUsageKind::Refund { request_id },
+            },
+        )?;
+    }
+    Ok(vec![])
+}
+
+fn coins_to_string(coins: &[Coin]) -> String {
+    coins
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{GatewayChannel, OperationalMode, Stats, FIRST_CHANNEL_CONNECTED};
+
+    use super::*;
+    use cosmwasm_std::{
+        coin, coins,
+        testing::{
+            mock_dependencies, mock_dependencies_with_balance, mock_env,
+            mock_ibc_channel_close_confirm, mock_ibc_channel_close_init,
+            mock_ibc_channel_connect_ack, mock_ibc_channel_connect_confirm,
+            mock_ibc_channel_open_init, mock_ibc_packet_ack, mock_ibc_packet_recv,
+            mock_ibc_packet_timeout, mock_info, MockApi, MockQuerier, MockStorage,
+            MOCK_CONTRACT_ADDR,
+        },
+        ContractResult, CosmosMsg, IbcAcknowledgement, OwnedDeps, ReplyOn, SubMsgResponse,
+        SystemResult, Uint128, WasmQuery,
+    };
+    use nois_protocol::{APP_ORDER, BAD_APP_ORDER, IBC_APP_VERSION};
+
+    const CREATOR: &str = "creator";
+
+    fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let initial_funds = vec![
+            Coin::new(22334455, "unoisx"),
+            Coin::new(
+                123321,
+                "ibc/CB480EB3697F39DB828D9EFA021ABE681BFCD72E23894019B8DDB1AB94039081",
+            ),
+        ];
+        let mut deps = mock_dependencies_with_balance(&initial_funds);
+        let msg = InstantiateMsg {
+            manager: Some(CREATOR.to_string()),
+            prices: vec![Coin::new(1_000000, "unoisx")],
+            test_mode: true,
+            callback_gas_limit: 500_000,
+            mode: OperationalMode::Funded {},
+            access_mode: None,
+        };
+        let info = mock_info(CREATOR, &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        deps
+    }
+
+    /// Gets the value of the first attribute with the given key
+    fn first_attr(data: impl AsRef<[Attribute]>, search_key: &str) -> Option<String> {
+        data.as_ref().iter().find_map(|a| {
+            if a.key == search_key {
+                Some(a.value.clone())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Simulates the reply of the IBC module after sending the beacon request packet
+    fn reply_send_packet(deps: DepsMut, channel_id: &str, sequence: u64) -> Response {
+        let event = Event::new("send_packet")
+            .add_attribute("packet_src_channel", channel_id)
+            .add_attribute("packet_sequence", sequence.to_string());
+        let msg = Reply {
+            id: SEND_BEACON_REQUEST_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![event],
+                data: None,
+            }),
+        };
+        reply(deps, mock_env(), msg).unwrap()
+    }
+
+    fn setup_channel(mut deps: DepsMut) {
+        let init = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_open(deps.branch(), mock_env(), init).unwrap();
+
+        let ack = mock_ibc_channel_connect_ack("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_connect(deps, mock_env(), ack).unwrap();
+    }
+
+    #[test]
+    fn instantiate_works() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            manager: Some(CREATOR.to_string()),
+            prices: vec![Coin::new(1_000000, "unoisx")],
+            test_mode: false,
+            callback_gas_limit: 500_000,
+            mode: OperationalMode::Funded {},
+            access_mode: None,
+        };
+        let info = mock_info(CREATOR, &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+    }
+
+    #[test]
+    fn migrate_works() {
+        let mut deps = setup();
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(version.contract, CONTRACT_NAME);
+        assert_eq!(version.version, CONTRACT_VERSION);
+
+        // Migrate to same version and set new config fields
+        let msg = MigrateMsg {
+            access_mode: Some(AccessMode::Denylist {}),
+            rate_limits: Some(RateLimits {
+                sender_per_block: Some(5),
+                ..RateLimits::default()
+            }),
+        };
+        let res = migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "previous_version").unwrap(),
+            CONTRACT_VERSION
+        );
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(config.access_mode, AccessMode::Denylist {});
+        assert_eq!(config.rate_limits.sender_per_block, Some(5));
+
+        // Config fields not set in MigrateMsg are kept
+        let msg = MigrateMsg {
+            access_mode: None,
+            rate_limits: None,
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(config.access_mode, AccessMode::Denylist {});
+        assert_eq!(config.rate_limits.sender_per_block, Some(5));
+
+        // Invalid rate limits
+        let msg = MigrateMsg {
+            access_mode: None,
+            rate_limits: Some(RateLimits {
+                sender_per_window: Some(5),
+                ..RateLimits::default()
+            }),
+        };
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidRateLimits));
+    }
+
+    #[test]
+    fn migrate_from_unversioned_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        // The state as stored by 0.13: no cw2 version, access mode,
+        // rate limits or gateway channel list
+        let config = String::from_utf8(deps.storage.get(b"config").unwrap()).unwrap();
+        let (config, _) = config.split_once(r#","access_mode":"#).unwrap();
+        deps.storage
+            .set(b"config", format!("{config}}}").as_bytes());
+        deps.storage.remove(b"contract_info");
+        GATEWAY_CHANNELS.remove(&mut deps.storage, "channel-12");
+        FIRST_CHANNEL_CONNECTED.remove(&mut deps.storage);
+        CONFIG.load(&deps.storage).unwrap_err();
+
+        let msg = MigrateMsg {
+            access_mode: None,
+            rate_limits: Some(RateLimits {
+                sender_per_block: Some(5),
+                ..RateLimits::default()
+            }),
+        };
+        let res = migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "previous_version").unwrap(),
+            "unknown"
+        );
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(config.access_mode, AccessMode::Open {});
+        assert_eq!(config.rate_limits.sender_per_block, Some(5));
+        assert!(
+            GATEWAY_CHANNELS
+                .load(&deps.storage, "channel-12")
+                .unwrap()
+                .approved
+        );
+        assert!(FIRST_CHANNEL_CONNECTED.load(&deps.storage).unwrap());
+        assert_eq!(
+            get_contract_version(&deps.storage).unwrap().version,
+            CONTRACT_VERSION
+        );
+    }
+
+    #[test]
+    fn migrate_refuses_downgrade_and_other_contracts() {
+        let mut deps = setup();
+
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "999.0.0").unwrap();
+        let err = migrate(
+            deps.as_mut(),
+            mock_env(),
+            MigrateMsg {
+                access_mode: None,
+                rate_limits: None,
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotDowngrade {
+                previous_version: "999.0.0".to_string(),
+                new_version: CONTRACT_VERSION.to_string(),
+            }
+        );
+
+        set_contract_version(&mut deps.storage, "crates.io:cw20-base", "0.1.0").unwrap();
+        let err = migrate(
+            deps.as_mut(),
+            mock_env(),
+            MigrateMsg {
+                access_mode: None,
+                rate_limits: None,
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrateFromContract {
+                previous_contract: "crates.io:cw20-base".to_string(),
+            }
+        );
+    }
+
+    //
+    // Execute tests
+    //
+
+    #[test]
+    fn get_next_randomness_works() {
+        let mut deps = setup();
+
+        // Requires a channel to forward requests to
+        setup_channel(deps.as_mut());
+
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("dapp", &coins(22334455, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        // Overpayment is refunded
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "dapp".to_string(),
+                amount: coins(21334455, "unoisx"),
+            })
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "charged").unwrap(),
+            "1000000unoisx"
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "refund").unwrap(),
+            "21334455unoisx"
+        );
+        let out_msg = &res.messages[1];
+        assert_eq!(out_msg.gas_limit, None);
+        assert_eq!(out_msg.reply_on, ReplyOn::Success);
+        assert_eq!(out_msg.id, SEND_BEACON_REQUEST_ID);
+        assert!(matches!(
+            out_msg.msg,
+            CosmosMsg::Ibc(IbcMsg::SendPacket { .. })
+        ));
+    }
+
+    #[test]
+    fn get_next_randomnes_for_invalid_inputs() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        // Job ID too long
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "cb480eb3697f39db828d9efa021abe681bfcd72e23894019b8ddb1ab94039081-and-counting"
+                .to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::JobIdTooLong));
+    }
+
+    #[test]
+    fn get_randomness_after_works() {
+        let mut deps = setup();
+
+        // Requires a channel to forward requests to
+        setup_channel(deps.as_mut());
+
+        let msg = ExecuteMsg::GetRandomnessAfter {
+            after: Timestamp::from_seconds(1666343642),
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("dapp", &coins(22334455, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        // Overpayment is refunded
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "dapp".to_string(),
+                amount: coins(21334455, "unoisx"),
+            })
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "charged").unwrap(),
+            "1000000unoisx"
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "refund").unwrap(),
+            "21334455unoisx"
+        );
+        let out_msg = &res.messages[1];
+        assert_eq!(out_msg.gas_limit, None);
+        assert_eq!(out_msg.reply_on, ReplyOn::Success);
+        assert_eq!(out_msg.id, SEND_BEACON_REQUEST_ID);
+        assert!(matches!(
+            out_msg.msg,
+            CosmosMsg::Ibc(IbcMsg::SendPacket { .. })
+        ));
+    }
+
+    #[test]
+    fn get_randomness_after_fails_for_invalid_inputs() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        // Job ID too long
+        let msg = ExecuteMsg::GetRandomnessAfter {
+            after: Timestamp::from_seconds(1666343642),
+            job_id: "cb480eb3697f39db828d9efa021abe681bfcd72e23894019b8ddb1ab94039081-and-counting"
+                .to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::JobIdTooLong));
+    }
+
+    #[test]
+    fn when_manager_is_not_set_manager_permissions_are_unathorised() {
+        // Check that if manager not set, a random person cannot execute manager-like operations.
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            manager: None,
+            prices: vec![Coin::new(1_000000, "unoisx")],
+            test_mode: false,
+            callback_gas_limit: 500_000,
+            mode: OperationalMode::Funded {},
+            access_mode: None,
+        };
+        let info = mock_info(CREATOR, &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        // withdraw
+        let msg = ExecuteMsg::Withdraw {
+            denom: "unoisx".to_string(),
+            amount: Some(Uint128::new(12)),
+            address: "some-address".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+        // withdraw all
+        let msg = ExecuteMsg::Withdraw {
+            denom: "unoisx".to_string(),
+            amount: None,
+            address: "some-address".to_string(),
+        };
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+        // Edit config
+        let msg = ExecuteMsg::SetConfig {
+            manager: Some("some-manager".to_string()),
+            prices: None,
+            payment: None,
+            nois_beacon_price: None,
+            mode: None,
+            access_mode: None,
+            rate_limits: None,
+        };
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+    }
+
+    #[test]
+    fn withdraw_works() {
+        let mut deps = setup();
+
+        let msg = ExecuteMsg::Withdraw {
+            denom: "unoisx".to_string(),
+            amount: Some(Uint128::new(12)),
+            address: "some-address".to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("dapp", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "some-address".to_string(),
+                amount: coins(12, "unoisx"),
+            })
+        );
+    }
+
+    #[test]
+    fn withdraw_all_works() {
+        let mut deps = setup();
+
+        let msg = ExecuteMsg::Withdraw {
+            denom: "unoisx".to_string(),
+            amount: None,
+            address: "some-address".to_string(),
+        };
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("dapp", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            msg.clone(),
+        )
+        .unwrap();
+
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "some-address".to_string(),
+                amount: coins(22334455, "unoisx"),
+            })
+        );
+
+        // Credit of dapps is not withdrawn
+        let deposit = ExecuteMsg::Deposit { beneficiary: None };
+        let info = mock_info("dapp", &coins(4455, "unoisx"));
+        execute(deps.as_mut(), mock_env(), info, deposit).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "some-address".to_string(),
+                amount: coins(22330000, "unoisx"),
+            })
+        );
+        let msg = ExecuteMsg::Withdraw {
+            denom: "unoisx".to_string(),
+            amount: Some(Uint128::new(22330001)),
+            address: "some-address".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::WithdrawalExceedsAvailable { available } if available == coin(22330000, "unoisx")
+        ));
+    }
+
+    //
+    // Query tests
+    //
+
+    #[test]
+    fn query_prices_works() {
+        let deps = setup();
+
+        let PricesResponse { prices } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Prices {}).unwrap()).unwrap();
+        assert_eq!(prices, coins(1000000, "unoisx"));
+    }
+
+    #[test]
+    fn query_price_works() {
+        let deps = setup();
+
+        let PriceResponse { price } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Price {
+                    denom: "shitcoin".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(price, None);
+
+        let PriceResponse { price } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Price {
+                    denom: "unoisx".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(price, Some(Uint128::new(1000000)));
+    }
+
+    //
+    // IBC tests
+    //
+
+    #[test]
+    fn ibc_channel_open_checks_version_and_order() {
+        let mut deps = setup();
+
+        // All good
+        let valid_handshake = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), valid_handshake).unwrap();
+
+        // Wrong order
+        let wrong_order = mock_ibc_channel_open_init("channel-12", BAD_APP_ORDER, IBC_APP_VERSION);
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), wrong_order).unwrap_err();
+        assert!(matches!(res, ContractError::ChannelError(..)));
+
+        // Wrong version
+        let wrong_version = mock_ibc_channel_open_init("channel-12", APP_ORDER, "another version");
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), wrong_version).unwrap_err();
+        assert!(matches!(res, ContractError::ChannelError(..)));
+    }
+
+    #[test]
+    fn ibc_channel_connect_works() {
+        // We are chain A and get the ChanOpenAck
+
+        let mut deps = setup();
+
+        // Channel is unset
+        let GatewayChannelResponse { channel } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
+                .unwrap();
+        assert_eq!(channel, None);
+
+        let msg = mock_ibc_channel_connect_ack("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+
+        // Channel is now set
+        let GatewayChannelResponse { channel } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
+                .unwrap();
+        assert_eq!(channel, Some("channel-12".to_string()));
+
+        // One more ChanOpenAck
+        let msg = mock_ibc_channel_connect_ack("channel-12", APP_ORDER, IBC_APP_VERSION);
+        let err = ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::ChannelAlreadySet));
+
+        // ChanOpenConfirm is rejected
+        let msg = mock_ibc_channel_connect_confirm("channel-12", APP_ORDER, IBC_APP_VERSION);
+        let err = ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::MustBeChainA));
+    }
+
+    #[test]
+    fn ibc_channel_close_works() {
+        let mut deps = setup();
+
+        // Open
+        let valid_handshake = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), valid_handshake).unwrap();
+
+        // Connect
+        let msg = mock_ibc_channel_connect_ack("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+
+        // Channel is now set
+        let GatewayChannelResponse { channel } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
+                .unwrap();
+        assert_eq!(channel, Some("channel-12".to_string()));
+
+        // Closing channel fails
+        let msg = mock_ibc_channel_close_init("channel-12", APP_ORDER, IBC_APP_VERSION);
+        let err = ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::ChannelMustNotBeClosed));
+
+        // Channel is still set
+        let GatewayChannelResponse { channel } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
+                .unwrap();
+        assert_eq!(channel, Some("channel-12".to_string()));
+
+        // The other side closed
+        let msg = mock_ibc_channel_close_confirm("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+
+        // Channel is unset
+        let GatewayChannelResponse { channel } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
+                .unwrap();
+        assert_eq!(channel, None);
+    }
+
+    #[test]
+    fn ibc_packet_ack_works() {
+        let mut deps = setup();
+
+        // The proxy -> gateway packet we get the acknowledgement for
+        let packet = InPacket::RequestBeacon {
+            after: Timestamp::from_seconds(321),
+            origin: to_binary(&RequestBeaconOrigin {
+                sender: "contract345".to_string(),
+                job_id: "hello".to_string(),
+                request_id: None,
+            })
+            .unwrap(),
+        };
+
+        // Success ack (processed)
+        let ack = StdAck::success(InPacketAck::RequestProcessed {
+            source_id: "backend:123:456".to_string(),
+        });
+        let msg = mock_ibc_packet_ack(
+            "channel-12",
+            &packet,
+            IbcAcknowledgement::encode_json(&ack).unwrap(),
+        )
+        .unwrap();
+        let IbcBasicResponse { attributes, .. } =
+            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(first_attr(&attributes, "action").unwrap(), "ack");
+        assert_eq!(first_attr(&attributes, "is_error").unwrap(), "false");
+        assert_eq!(first_attr(&attributes, "error"), None);
+        assert_eq!(
+            first_attr(&attributes, "ack_type").unwrap(),
+            "request_processed"
+        );
+
+        // Success ack (queued)
+        let ack = StdAck::success(InPacketAck::RequestQueued {
+            source_id: "backend:123:456".to_string(),
+        });
+        let msg = mock_ibc_packet_ack(
+            "channel-12",
+            &packet,
+            IbcAcknowledgement::encode_json(&ack).unwrap(),
+        )
+        .unwrap();
+        let IbcBasicResponse { attributes, .. } =
+            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(first_attr(&attributes, "action").unwrap(), "ack");
+        assert_eq!(first_attr(&attributes, "is_error").unwrap(), "false");
+        assert_eq!(first_attr(&attributes, "error"), None);
+        assert_eq!(
+            first_attr(&attributes, "ack_type").unwrap(),
+            "request_queued"
+        );
+
+        // Error ack
+        let ack = StdAck::error("kaputt");
+        let msg = mock_ibc_packet_ack(
+            "channel-12",
+            &packet,
+            IbcAcknowledgement::encode_json(&ack).unwrap(),
+        )
+        .unwrap();
+        let IbcBasicResponse { attributes, .. } =
+            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(first_attr(&attributes, "action").unwrap(), "ack");
+        assert_eq!(first_attr(&attributes, "is_error").unwrap(), "true");
+        assert_eq!(first_attr(&attributes, "error").unwrap(), "kaputt");
+        assert_eq!(first_attr(&attributes, "ack_type"), None);
+    }
+
+    #[test]
+    fn ibc_packet_ack_sends_error_callback() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[0].msg else {
+            panic!("Unexpected message type");
+        };
+        let packet: InPacket = from_binary(data).unwrap();
+
+        let res = reply_send_packet(deps.as_mut(), "channel-12", 29);
+        assert_eq!(first_attr(&res.attributes, "sequence").unwrap(), "29");
+
+        // Error ack
+        let ack = StdAck::error("kaputt");
+        let msg = mock_ibc_packet_ack(
+            "channel-12",
+            &packet,
+            IbcAcknowledgement::encode_json(&ack).unwrap(),
+        )
+        .unwrap();
+        let IbcBasicResponse {
+            attributes,
+            messages,
+            ..
+        } = ibc_packet_ack(deps.as_mut(), mock_env(), msg.clone()).unwrap();
+        assert_eq!(first_attr(&attributes, "is_error").unwrap(), "true");
+        assert_eq!(first_attr(&attributes, "job_id").unwrap(), "foo");
+        assert_eq!(first_attr(&attributes, "refund").unwrap(), "1000000unoisx");
+        assert_eq!(messages.len(), 2);
+        // The dapp is refunded
+        assert_eq!(
+            messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "dapp".to_string(),
+                amount: coins(1_000_000, "unoisx"),
+            })
+        );
+        assert_eq!(messages[1].id, ERROR_CALLBACK_ID);
+        assert_eq!(messages[1].reply_on, ReplyOn::Error);
+        assert_eq!(messages[1].gas_limit, Some(500_000));
+        assert_eq!(
+            messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "dapp".to_string(),
+                msg: to_binary(&ErrorReceiverExecuteMsg::NoisReceiveError {
+                    callback: NoisErrorCallback {
+                        job_id: "foo".to_string(),
+                        error: "kaputt".to_string(),
+                        refund: coins(1_000_000, "unoisx"),
+                    },
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+
+        // The request is removed after the ack, so a second ack does not callback again
+        let IbcBasicResponse { messages, .. } =
+            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(messages.len(), 0);
+    }
+
+    #[test]
+    fn ibc_packet_ack_success_does_not_send_callback() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[0].msg else {
+            panic!("Unexpected message type");
+        };
+        let packet: InPacket = from_binary(data).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+
+        let ack = StdAck::success(InPacketAck::RequestQueued {
+            source_id: "backend:123:456".to_string(),
+        });
+        let msg = mock_ibc_packet_ack(
+            "channel-12",
+            &packet,
+            IbcAcknowledgement::encode_json(&ack).unwrap(),
+        )
+        .unwrap();
+        let IbcBasicResponse { messages, .. } =
+            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(messages.len(), 0);
+        assert_eq!(
+            PENDING_REQUESTS
+                .may_load(deps.as_ref().storage, ("channel-12", 29))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn job_registry_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        fn query_job(deps: Deps, request_id: u64) -> QueriedJob {
+            let JobResponse { job } =
+                from_binary(&query(deps, mock_env(), QueryMsg::Job { request_id }).unwrap())
+                    .unwrap();
+            job.unwrap()
+        }
+
+        // Unknown job
+        let JobResponse { job } = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::Job { request_id: 1 }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(job, None);
+
+        // Request
+        let msg = ExecuteMsg::GetRandomnessAfter {
+            after: Timestamp::from_seconds(1666343642),
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(first_attr(&res.attributes, "request_id").unwrap(), "1");
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[0].msg else {
+            panic!("Unexpected message type");
+        };
+        let packet: InPacket = from_binary(data).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+
+        let queried = query_job(deps.as_ref(), 1);
+        assert_eq!(
+            queried,
+            QueriedJob {
+                request_id: 1,
+                sender: Addr::unchecked("dapp"),
+                job_id: "foo".to_string(),
+                after: Timestamp::from_seconds(1666343642),
+                funds: coins(1_000_000, "unoisx"),
+                paid_with_credit: false,
+                source_id: None,
+                request_height: mock_env().block.height,
+                delivery_height: None,
+                callback_success: None,
+                error: None,
+            }
+        );
+
+        // Ack
+        let ack = StdAck::success(InPacketAck::RequestQueued {
+            source_id: "backend:123:456".to_string(),
+        });
+        let msg = mock_ibc_packet_ack(
+            "channel-12",
+            &packet,
+            IbcAcknowledgement::encode_json(&ack).unwrap(),
+        )
+        .unwrap();
+        ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        let queried = query_job(deps.as_ref(), 1);
+        assert_eq!(queried.source_id.unwrap(), "backend:123:456");
+
+        // Delivery
+        let InPacket::RequestBeacon { origin, .. } = packet else {
+            panic!("Unexpected packet type");
+        };
+        let mut env = mock_env();
+        env.block.height += 10;
+        let deliver = OutPacket::DeliverBeacon {
+            source_id: "backend:123:456".to_string(),
+            published: Timestamp::from_seconds(1666343645),
+            randomness: HexBinary::from_hex(
+                "aabbccddaabbccddaabbccddaabbccddaabbccddaabbccddaabbccddaabbccdd",
+            )
+            .unwrap(),
+            origin,
+        };
+        let msg = mock_ibc_packet_recv("channel-12", &deliver).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), env.clone(), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].reply_on, ReplyOn::Always);
+        let queried = query_job(deps.as_ref(), 1);
+        assert_eq!(queried.delivery_height, Some(env.block.height));
+        assert_eq!(queried.callback_success, None);
+
+        // Callback result
+        let msg = Reply {
+            id: CALLBACK_ID,
+            result: SubMsgResult::Err("out of gas".to_string()),
+        };
+        let res = reply(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            first_attr(&res.events[0].attributes, "request_id").unwrap(),
+            "1"
+        );
+        let queried = query_job(deps.as_ref(), 1);
+        assert_eq!(queried.callback_success, Some(false));
+
+        // Second request by other dapp
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "bar".to_string(),
+        };
+        let info = mock_info("other_dapp", &coins(1_000_000, "unoisx"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 30);
+        // Third request by dapp
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "baz".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000_000, "unoisx"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 31);
+
+        let JobsResponse { jobs } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::JobsBySender {
+                    sender: "dapp".to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let job_ids: Vec<_> = jobs
+            .iter()
+            .map(|j| (j.request_id, j.job_id.as_str()))
+            .collect();
+        assert_eq!(job_ids, [(1, "foo"), (3, "baz")]);
+
+        let JobsResponse { jobs } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::JobsBySender {
+                    sender: "dapp".to_string(),
+                    start_after: Some(1),
+                    limit: Some(5),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let job_ids: Vec<_> = jobs
+            .iter()
+            .map(|j| (j.request_id, j.job_id.as_str()))
+            .collect();
+        assert_eq!(job_ids, [(3, "baz")]);
+    }
+
+    /// Requests a beacon for the given dapp and delivers it right away
+    fn request_and_deliver(mut deps: DepsMut, sender: &str, job_id: &str, sequence: u64) {
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: job_id.to_string(),
+        };
+        let info = mock_info(sender, &coins(1_000_000, "unoisx"));
+        let res = execute(deps.branch(), mock_env(), info, msg).unwrap();
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[0].msg else {
+            panic!("Unexpected message type");
+        };
+        let InPacket::RequestBeacon { origin, .. } = from_binary(data).unwrap() else {
+            panic!("Unexpected packet type");
+        };
+        reply_send_packet(deps.branch(), "channel-12", sequence);
+
+        let deliver = OutPacket::DeliverBeacon {
+            source_id: "backend:123:456".to_string(),
+            published: Timestamp::from_seconds(1666343645),
+            randomness: HexBinary::from_hex(
+                "aabbccddaabbccddaabbccddaabbccddaabbccddaabbccddaabbccddaabbccdd",
+            )
+            .unwrap(),
+            origin,
+        };
+        let msg = mock_ibc_packet_recv("channel-12", &deliver).unwrap();
+        ibc_packet_receive(deps, mock_env(), msg).unwrap();
+    }
+
+    fn callback_reply(deps: DepsMut, success: bool) {
+        let result = if success {
+            SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            })
+        } else {
+            SubMsgResult::Err("out of gas".to_string())
+        };
+        let msg = Reply {
+            id: CALLBACK_ID,
+            result,
+        };
+        reply(deps, mock_env(), msg).unwrap();
+    }
+
+    #[test]
+    fn retry_callback_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        fn failed_callbacks(deps: Deps, sender: &str) -> Vec<Delivery> {
+            let FailedCallbacksResponse { deliveries } = from_binary(
+                &query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::FailedCallbacks {
+                        sender: sender.to_string(),
+                        start_after: None,
+                        limit: None,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            deliveries
+        }
+
+        request_and_deliver(deps.as_mut(), "dapp", "foo", 29);
+        callback_reply(deps.as_mut(), false);
+        let failed = failed_callbacks(deps.as_ref(), "dapp");
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].job_id, "foo");
+        assert_eq!(failed[0].retries, 0);
+        assert_eq!(failed[0].request_id, Some(1));
+
+        // Unknown job
+        let msg = ExecuteMsg::RetryCallback {
+            request_id: 2,
+            gas_limit: 2_000_000,
+            sender: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::FailedCallbackNotFound));
+
+        // Retry by dapp fails again
+        let msg = ExecuteMsg::RetryCallback {
+            request_id: 1,
+            gas_limit: 2_000_000,
+            sender: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].id, CALLBACK_ID);
+        assert_eq!(res.messages[0].gas_limit, Some(2_000_000));
+        assert_eq!(first_attr(&res.attributes, "retries").unwrap(), "1");
+        callback_reply(deps.as_mut(), false);
+        let failed = failed_callbacks(deps.as_ref(), "dapp");
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].retries, 1);
+
+        // Other dapps cannot retry
+        let msg = ExecuteMsg::RetryCallback {
+            request_id: 1,
+            gas_limit: 2_000_000,
+            sender: Some("dapp".to_string()),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("attacker", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+
+        // Retry by manager succeeds
+        let msg = ExecuteMsg::RetryCallback {
+            request_id: 1,
+            gas_limit: 2_000_000,
+            sender: Some("dapp".to_string()),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        callback_reply(deps.as_mut(), true);
+        assert_eq!(failed_callbacks(deps.as_ref(), "dapp").len(), 0);
+        let JobResponse { job } = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::Job { request_id: 1 }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(job.unwrap().callback_success, Some(true));
+
+        // Nothing to retry anymore
+        let msg = ExecuteMsg::RetryCallback {
+            request_id: 1,
+            gas_limit: 2_000_000,
+            sender: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::FailedCallbackNotFound));
+    }
+
+    #[test]
+    fn retry_callback_is_limited() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        request_and_deliver(deps.as_mut(), "dapp", "foo", 29);
+        callback_reply(deps.as_mut(), false);
+
+        let msg = ExecuteMsg::RetryCallback {
+            request_id: 1,
+            gas_limit: 2_000_000,
+            sender: None,
+        };
+
+        // Expired
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(CALLBACK_RETRY_PERIOD + 1);
+        let err = execute(deps.as_mut(), env, mock_info("dapp", &[]), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::CallbackExpired));
+
+        // Exhausted
+        for _ in 0..MAX_CALLBACK_RETRIES {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("dapp", &[]),
+                msg.clone(),
+            )
+            .unwrap();
+            callback_reply(deps.as_mut(), false);
+        }
+        // Exhausted deliveries are removed
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::FailedCallbackNotFound));
+    }
+
+    #[test]
+    fn failed_callbacks_are_stored_per_request() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let failed_request_ids = |deps: Deps| -> Vec<Option<u64>> {
+            let FailedCallbacksResponse { deliveries } = from_binary(
+                &query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::FailedCallbacks {
+                        sender: "dapp".to_string(),
+                        start_after: None,
+                        limit: None,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            deliveries.into_iter().map(|d| d.request_id).collect()
+        };
+
+        // The same job ID twice
+        request_and_deliver(deps.as_mut(), "dapp", "foo", 29);
+        callback_reply(deps.as_mut(), false);
+        request_and_deliver(deps.as_mut(), "dapp", "foo", 30);
+        callback_reply(deps.as_mut(), false);
+        assert_eq!(failed_request_ids(deps.as_ref()), [Some(1), Some(2)]);
+
+        // Expired deliveries are pruned when the next callback of the dapp is executed
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(CALLBACK_RETRY_PERIOD + 1);
+        request_and_deliver(deps.as_mut(), "dapp", "bar", 31);
+        let msg = Reply {
+            id: CALLBACK_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        reply(deps.as_mut(), env, msg).unwrap();
+        assert_eq!(failed_request_ids(deps.as_ref()), []);
+    }
+
+    #[test]
+    fn ibc_packet_timeout_refunds_and_sends_callback() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_500_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        // Surplus is refunded immediately
+        assert_eq!(
+            first_attr(&res.attributes, "refund").unwrap(),
+            "500000unoisx"
+        );
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[1].msg else {
+            panic!("Unexpected message type");
+        };
+        let packet: InPacket = from_binary(data).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+
+        let msg = mock_ibc_packet_timeout("channel-12", &packet).unwrap();
+        let IbcBasicResponse {
+            attributes,
+            messages,
+            ..
+        } = ibc_packet_timeout(deps.as_mut(), mock_env(), msg.clone()).unwrap();
+        assert_eq!(first_attr(&attributes, "job_id").unwrap(), "foo");
+        assert_eq!(first_attr(&attributes, "refund").unwrap(), "1000000unoisx");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "dapp".to_string(),
+                amount: coins(1_000_000, "unoisx"),
+            })
+        );
+        assert_eq!(messages[1].id, ERROR_CALLBACK_ID);
+        assert_eq!(messages[1].reply_on, ReplyOn::Error);
+        assert_eq!(
+            messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "dapp".to_string(),
+                msg: to_binary(&ErrorReceiverExecuteMsg::NoisReceiveTimeout {
+                    callback: NoisTimeoutCallback {
+                        job_id: "foo".to_string(),
+                        refund: coins(1_000_000, "unoisx"),
+                    },
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+
+        // Refunds only once
+        let IbcBasicResponse { messages, .. } =
+            ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(messages.len(), 0);
+    }
+
+    #[test]
+    fn ibc_packet_timeout_does_not_prepay_gateway() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            prices: None,
+            payment: Some("nois1payment".to_string()),
+            nois_beacon_price: Some(Uint128::new(50)),
+            mode: Some(OperationalMode::IbcPay {
+                unois_denom: IbcDenom {
+                    ics20_channel: "channel-5".to_string(),
+                    denom: "ibc/unois".to_string(),
+                },
+            }),
+            access_mode: None,
+            rate_limits: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let request = |deps: DepsMut, job_id: &str| -> (Vec<SubMsg>, InPacket) {
+            let msg = ExecuteMsg::GetNextRandomness {
+                job_id: job_id.to_string(),
+            };
+            let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+            let res = execute(deps, mock_env(), info, msg).unwrap();
+            let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages.last().unwrap().msg
+            else {
+                panic!("Unexpected message type");
+            };
+            let packet = from_binary(data).unwrap();
+            (res.messages, packet)
+        };
+        let transfer = CosmosMsg::Ibc(IbcMsg::Transfer {
+            channel_id: "channel-5".to_string(),
+            to_address: "nois1payment".to_string(),
+            amount: Coin::new(50, "ibc/unois"),
+            timeout: mock_env()
+                .block
+                .time
+                .plus_seconds(TRANSFER_PACKET_LIFETIME)
+                .into(),
+        });
+
+        // The transfer is sent along with the request
+        let (messages, packet) = request(deps.as_mut(), "one");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].msg, transfer);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+
+        // The dapp is refunded in full. The transfer may have timed out as well,
+        // so it is not counted as prepaid.
+        let msg = mock_ibc_packet_timeout("channel-12", &packet).unwrap();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "refund").unwrap(),
+            "1000000unoisx"
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "gateway_debt_reversed").unwrap(),
+            "0"
+        );
+        assert_eq!(first_attr(&res.attributes, "gateway_prepaid"), None);
+
+        // The next request sends a new transfer
+        let (messages, _) = request(deps.as_mut(), "two");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].msg, transfer);
+    }
+
+    #[test]
+    fn insufficient_payment_balance_drops_gateway_prepaid() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            prices: None,
+            payment: Some("nois1payment".to_string()),
+            nois_beacon_price: Some(Uint128::new(50)),
+            mode: Some(OperationalMode::IbcPay {
+                unois_denom: IbcDenom {
+                    ics20_channel: "channel-5".to_string(),
+                    denom: "ibc/unois".to_string(),
+                },
+            }),
+            access_mode: None,
+            rate_limits: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let request = |deps: DepsMut, job_id: &str| -> (Vec<SubMsg>, InPacket) {
+            let msg = ExecuteMsg::GetNextRandomness {
+                job_id: job_id.to_string(),
+            };
+            let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+            let res = execute(deps, mock_env(), info, msg).unwrap();
+            let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages.last().unwrap().msg
+            else {
+                panic!("Unexpected message type");
+            };
+            let packet = from_binary(data).unwrap();
+            (res.messages, packet)
+        };
+
+        let reject = |deps: DepsMut, packet: &InPacket| -> IbcBasicResponse {
+            let ack = StdAck::error_with_code(
+                AckErrorCode::InsufficientPaymentBalance,
+                "Error processing packet: Insufficient balance in payment contract",
+            );
+            let msg = mock_ibc_packet_ack(
+                "channel-12",
+                packet,
+                IbcAcknowledgement::encode_json(&ack).unwrap(),
+            )
+            .unwrap();
+            ibc_packet_ack(deps, mock_env(), msg).unwrap()
+        };
+        let refund = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "dapp".to_string(),
+            amount: coins(1_000000, "unoisx"),
+        });
+
+        // The request times out. Its transfer may have timed out too.
+        let (messages, packet) = request(deps.as_mut(), "one");
+        assert_eq!(messages.len(), 2);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let msg = mock_ibc_packet_timeout("channel-12", &packet).unwrap();
+        ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+
+        // The next request arrives at the gateway before the transfer sent along with it.
+        // The dapp is refunded and only this transfer becomes prepaid.
+        let (messages, packet) = request(deps.as_mut(), "two");
+        assert_eq!(messages.len(), 2);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let res = reject(deps.as_mut(), &packet);
+        assert_eq!(first_attr(&res.attributes, "is_error").unwrap(), "true");
+        assert_eq!(
+            first_attr(&res.attributes, "error_code").unwrap(),
+            "insufficient_payment_balance"
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "gateway_prepaid_dropped").unwrap(),
+            "0"
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "gateway_prepaid").unwrap(),
+            "50"
+        );
+        assert_eq!(res.messages[0].msg, refund);
+
+        // The payment is used for the next request
+        let (messages, packet) = request(deps.as_mut(), "three");
+        assert_eq!(messages.len(), 1);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+
+        // If the prepaid amount never arrives, the gateway rejects the request.
+        // The prepaid amount is dropped and nothing was sent along with this request.
+        let res = reject(deps.as_mut(), &packet);
+        assert_eq!(
+            first_attr(&res.attributes, "gateway_prepaid_dropped").unwrap(),
+            "0"
+        );
+        assert_eq!(first_attr(&res.attributes, "gateway_prepaid"), None);
+        assert_eq!(res.messages[0].msg, refund);
+
+        // The next request sends a new transfer
+        let (messages, _) = request(deps.as_mut(), "four");
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[test]
+    fn credit_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let credit = |deps: Deps, address: &str| -> Vec<Coin> {
+            let CreditResponse { credit } = from_binary(
+                &query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::Credit {
+                        address: address.to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            credit
+        };
+
+        // Requests without funds and without credit fail
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientPayment));
+
+        // Deposit validation
+        let msg = ExecuteMsg::Deposit { beneficiary: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::NoFundsSent));
+        let msg = ExecuteMsg::Deposit { beneficiary: None };
+        let info = mock_info("dapp", &coins(100, "ujuno"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::UnsupportedDenom { denom } if denom == "ujuno"));
+
+        // Deposit on behalf of the dapp
+        let msg = ExecuteMsg::Deposit {
+            beneficiary: Some("dapp".to_string()),
+        };
+        let info = mock_info("backend", &coins(2_500_000, "unoisx"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(credit(deps.as_ref(), "dapp"), coins(2_500_000, "unoisx"));
+        assert_eq!(credit(deps.as_ref(), "backend"), vec![]);
+
+        // Request paid with credit
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap();
+        assert_eq!(credit(deps.as_ref(), "dapp"), coins(1_500_000, "unoisx"));
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[0].msg else {
+            panic!("Unexpected message type");
+        };
+        let packet: InPacket = from_binary(data).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let job = JOBS.load(&deps.storage, 1).unwrap();
+        assert!(job.paid_with_credit);
+        assert_eq!(job.funds, coins(1_000_000, "unoisx"));
+
+        // Timeout refunds to credit
+        let msg = mock_ibc_packet_timeout("channel-12", &packet).unwrap();
+        let IbcBasicResponse { messages, .. } =
+            ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, ERROR_CALLBACK_ID);
+        assert_eq!(credit(deps.as_ref(), "dapp"), coins(2_500_000, "unoisx"));
+
+        // Withdraw
+        let msg = ExecuteMsg::WithdrawCredit {
+            denom: "unoisx".to_string(),
+            amount: Some(Uint128::new(3_000_000)),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientCredit));
+        let msg = ExecuteMsg::WithdrawCredit {
+            denom: "unoisx".to_string(),
+            amount: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "dapp".to_string(),
+                amount: coins(2_500_000, "unoisx"),
+            })
+        );
+        assert_eq!(credit(deps.as_ref(), "dapp"), vec![]);
+
+        // History
+        let CreditHistoryResponse { entries } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::CreditHistory {
+                    address: "dapp".to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let kinds: Vec<_> = entries.iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                CreditUsageKind::Deposit {
+                    depositor: Addr::unchecked("backend")
+                },
+                CreditUsageKind::Payment { request_id: 1 },
+                CreditUsageKind::Refund { request_id: 1 },
+                CreditUsageKind::Withdrawal {},
+            ]
+        );
+        let CreditHistoryResponse { entries } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::CreditHistory {
+                    address: "dapp".to_string(),
+                    start_after: Some(1),
+                    limit: Some(1),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].index, 2);
+        assert_eq!(entries[0].amount, coins(1_000_000, "unoisx")[0]);
+    }
+
+    #[test]
+    fn access_mode_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let request = |deps: DepsMut, sender: &str| {
+            let msg = ExecuteMsg::GetNextRandomness {
+                job_id: "foo".to_string(),
+            };
+            execute(
+                deps,
+                mock_env(),
+                mock_info(sender, &coins(1_000_000, "unoisx")),
+                msg,
+            )
+        };
+        let set_access_mode = |deps: DepsMut, access_mode: AccessMode| {
+            let msg = ExecuteMsg::SetConfig {
+                manager: None,
+                prices: None,
+                payment: None,
+                nois_beacon_price: None,
+                mode: None,
+                access_mode: Some(access_mode),
+                rate_limits: None,
+            };
+            execute(deps, mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        };
+
+        // Open by default
+        request(deps.as_mut(), "dapp1").unwrap();
+
+        // Only manager can update lists
+        let msg = ExecuteMsg::UpdateAllowlist {
+            add: vec!["dapp1".to_string(), "dapp2".to_string()],
+            remove: vec![],
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("dapp1", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let msg = ExecuteMsg::UpdateAllowlist {
+            add: vec!["dapp3".to_string()],
+            remove: vec!["dapp2".to_string()],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let msg = ExecuteMsg::UpdateDenylist {
+            add: vec!["dapp1".to_string()],
+            remove: vec![],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        // Allowlist
+        set_access_mode(deps.as_mut(), AccessMode::Allowlist {});
+        request(deps.as_mut(), "dapp1").unwrap();
+        let err = request(deps.as_mut(), "dapp2").unwrap_err();
+        assert!(matches!(err, ContractError::AccessDenied));
+        request(deps.as_mut(), "dapp3").unwrap();
+
+        // Denylist
+        set_access_mode(deps.as_mut(), AccessMode::Denylist {});
+        let err = request(deps.as_mut(), "dapp1").unwrap_err();
+        assert!(matches!(err, ContractError::AccessDenied));
+        request(deps.as_mut(), "dapp2").unwrap();
+
+        // Queries
+        let AddressListResponse { addresses } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Allowlist {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(addresses, vec!["dapp1", "dapp3"]);
+        let AddressListResponse { addresses } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Allowlist {
+                    start_after: Some("dapp1".to_string()),
+                    limit: Some(1),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(addresses, vec!["dapp3"]);
+        let AddressListResponse { addresses } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Denylist {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(addresses, vec!["dapp1"]);
+    }
+
+    #[test]
+    fn price_for_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let price_for = |deps: Deps, address: &str| -> Vec<Coin> {
+            let PricesResponse { prices } = from_binary(
+                &query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::PriceFor {
+                        address: address.to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            prices
+        };
+
+        assert_eq!(
+            price_for(deps.as_ref(), "partner"),
+            coins(1_000_000, "unoisx")
+        );
+
+        // Only manager can set overrides and discounts
+        let msg = ExecuteMsg::SetPriceOverride {
+            address: "partner".to_string(),
+            prices: Some(coins(400_000, "unoisx")),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("partner", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let msg = ExecuteMsg::SetDiscounts {
+            period: 3600,
+            tiers: vec![DiscountTier {
+                min_requests: 1,
+                discount_percent: 50,
+            }],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        assert_eq!(
+            price_for(deps.as_ref(), "partner"),
+            coins(400_000, "unoisx")
+        );
+        assert_eq!(price_for(deps.as_ref(), "dapp"), coins(1_000_000, "unoisx"));
+
+        // Overpayment over the effective price is refunded
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("partner", &coins(1_000_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "charged").unwrap(),
+            "400000unoisx"
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "refund").unwrap(),
+            "600000unoisx"
+        );
+
+        // Discount applies after the first request
+        assert_eq!(
+            price_for(deps.as_ref(), "partner"),
+            coins(200_000, "unoisx")
+        );
+
+        // Remove override
+        let msg = ExecuteMsg::SetPriceOverride {
+            address: "partner".to_string(),
+            prices: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        assert_eq!(
+            price_for(deps.as_ref(), "partner"),
+            coins(500_000, "unoisx")
+        );
+        let PriceOverridesResponse { overrides } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::PriceOverrides {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(overrides, vec![]);
+    }
+
+    #[test]
+    fn get_randomness_batch_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        // Batch size is limited
+        let msg = ExecuteMsg::GetRandomnessBatch {
+            after: Timestamp::from_seconds(1666343642),
+            job_ids: vec![],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBatchSize { .. }));
+        let msg = ExecuteMsg::GetRandomnessBatch {
+            after: Timestamp::from_seconds(1666343642),
+            job_ids: (0..51).map(|i| format!("job {i}")).collect(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBatchSize { .. }));
+
+        // Payment must cover all jobs
+        let msg = ExecuteMsg::GetRandomnessBatch {
+            after: Timestamp::from_seconds(1666343642),
+            job_ids: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        };
+        let info = mock_info("dapp", &coins(2_999_999, "unoisx"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientPayment));
+
+        let info = mock_info("dapp", &coins(3_500_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "charged").unwrap(),
+            "3000000unoisx"
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "refund").unwrap(),
+            "500000unoisx"
+        );
+        assert_eq!(res.messages.len(), 2);
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[1].msg else {
+            panic!("Unexpected message type");
+        };
+        let packet: InPacket = from_binary(data).unwrap();
+        let InPacket::RequestBeaconBatch { origins, .. } = &packet else {
+            panic!("Unexpected packet type");
+        };
+        assert_eq!(origins.len(), 3);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        for request_id in 1..=3 {
+            let job = JOBS.load(&deps.storage, request_id).unwrap();
+            assert_eq!(job.funds, coins(1_000_000, "unoisx"));
+        }
+
+        // Every job is refunded and gets an error callback
+        let ack = StdAck::error("kaputt");
+        let msg = mock_ibc_packet_ack(
+            "channel-12",
+            &packet,
+            IbcAcknowledgement::encode_json(&ack).unwrap(),
+        )
+        .unwrap();
+        let IbcBasicResponse { messages, .. } =
+            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(messages.len(), 6);
+        for message in &messages[..3] {
+            assert_eq!(
+                message.msg,
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "dapp".to_string(),
+                    amount: coins(1_000_000, "unoisx"),
+                })
+            );
+        }
+        for (message, job_id) in messages[3..].iter().zip(["a", "b", "c"]) {
+            assert_eq!(
+                message.msg,
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: "dapp".to_string(),
+                    msg: to_binary(&ErrorReceiverExecuteMsg::NoisReceiveError {
+                        callback: NoisErrorCallback {
+                            job_id: job_id.to_string(),
+                            error: "kaputt".to_string(),
+                            refund: coins(1_000_000, "unoisx"),
+                        },
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn callback_gas_limit_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let deliver = |deps: DepsMut, sender: &str| -> SubMsg {
+            let packet = OutPacket::DeliverBeacon {
+                source_id: "backend:123:456".to_string(),
+                published: Timestamp::from_seconds(1666343645),
+                randomness: HexBinary::from_hex(
+                    "aabbccddaabbccddaabbccddaabbccddaabbccddaabbccddaabbccddaabbccdd",
+                )
+                .unwrap(),
+                origin: to_binary(&RequestBeaconOrigin {
+                    sender: sender.to_string(),
+                    job_id: "foo".to_string(),
+                    request_id: None,
+                })
+                .unwrap(),
+            };
+            let msg = mock_ibc_packet_recv("channel-12", &packet).unwrap();
+            let res = ibc_packet_receive(deps, mock_env(), msg).unwrap();
+            res.messages[0].clone()
+        };
+
+        // Registration requires bounds
+        let msg = ExecuteMsg::SetCallbackGasLimit {
+            gas_limit: Some(1_500_000),
+            dapp: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("dapp", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::CallbackGasLimitOutOfBounds { .. }
+        ));
+
+        let bounds_msg = ExecuteMsg::SetCallbackGasBounds {
+            min: 100_000,
+            max: 2_000_000,
+            tiers: vec![GasTier {
+                min_gas_limit: 1_000_000,
+                price_increase_percent: 20,
+            }],
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("dapp", &[]),
+            bounds_msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            bounds_msg,
+        )
+        .unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap();
+
+        // Only manager can set limits for other dapps
+        let msg = ExecuteMsg::SetCallbackGasLimit {
+            gas_limit: Some(200_000),
+            dapp: Some("dapp".to_string()),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("other", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+
+        let CallbackGasLimitResponse { gas_limit } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::CallbackGasLimit {
+                    address: "dapp".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(gas_limit, 1_500_000);
+
+        // Delivery uses the registered limit
+        assert_eq!(deliver(deps.as_mut(), "dapp").gas_limit, Some(1_500_000));
+        assert_eq!(deliver(deps.as_mut(), "other").gas_limit, Some(500_000));
+
+        // The gas tier increases the price
+        let PricesResponse { prices } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::PriceFor {
+                    address: "dapp".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(prices, coins(1_200_000, "unoisx"));
+
+        // A request keeps the limit it was paid for, even if the registration changes
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "paid".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_200_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[0].msg else {
+            panic!("Unexpected message type");
+        };
+        let InPacket::RequestBeacon { origin, .. } = from_binary(data).unwrap() else {
+            panic!("Unexpected packet type");
+        };
+        reply_send_packet(deps.as_mut(), "channel-12", 1);
+        let msg = ExecuteMsg::SetCallbackGasLimit {
+            gas_limit: Some(200_000),
+            dapp: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap();
+        let packet = OutPacket::DeliverBeacon {
+            source_id: "backend:123:456".to_string(),
+            published: Timestamp::from_seconds(1666343645),
+            randomness: HexBinary::from_hex(
+                "aabbccddaabbccddaabbccddaabbccddaabbccddaabbccddaabbccddaabbccdd",
+            )
+            .unwrap(),
+            origin,
+        };
+        let msg = mock_ibc_packet_recv("channel-12", &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(res.messages[0].gas_limit, Some(1_500_000));
+        assert_eq!(deliver(deps.as_mut(), "dapp").gas_limit, Some(200_000));
+    }
+
+    #[test]
+    fn refresh_beacon_price_works() {
+        let mut deps = setup();
+        let env = mock_env();
+
+        // No price received yet
+        let BeaconPriceResponse {
+            price,
+            updated,
+            age,
+            refreshable_after,
+        } = from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::BeaconPrice {}).unwrap())
+            .unwrap();
+        assert_eq!(price, Uint128::zero());
+        assert_eq!(updated, None);
+        assert_eq!(age, None);
+        assert_eq!(refreshable_after, Timestamp::from_seconds(3600));
+
+        // Requires a channel to send the packet to
+        let msg = ExecuteMsg::RefreshBeaconPrice {};
+        let err = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::UnsetChannel));
+        setup_channel(deps.as_mut());
+
+        let msg = ExecuteMsg::RefreshBeaconPrice {};
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let packet = match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id,
+                data,
+                timeout,
+            }) => {
+                assert_eq!(channel_id, "channel-12");
+                assert_eq!(
+                    timeout,
+                    &env.block
+                        .time
+                        .plus_seconds(PULL_BEACON_PRICE_PACKET_LIFETIME)
+                        .into()
+                );
+                from_binary::<InPacket>(data).unwrap()
+            }
+            _ => panic!("Unexpected message"),
+        };
+        assert_eq!(packet, InPacket::PullBeaconPrice {});
+
+        // Unanswered refreshes are rate limited too
+        let msg = ExecuteMsg::RefreshBeaconPrice {};
+        let err = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BeaconPriceRefreshTooEarly {
+                refreshable_after: env.block.time.plus_seconds(BEACON_PRICE_REFRESH_INTERVAL)
+            }
+        );
+
+        // Gateway responds with the price
+        let updated = env.block.time.minus_seconds(100);
+        let ack = StdAck::success(InPacketAck::PullBeaconPrice {
+            timestamp: updated,
+            amount: Uint128::new(50_000000),
+            denom: "unois".to_string(),
+        });
+        let msg = mock_ibc_packet_ack(
+            "channel-12",
+            &packet,
+            IbcAcknowledgement::encode_json(&ack).unwrap(),
+        )
+        .unwrap();
+        let IbcBasicResponse { attributes, .. } =
+            ibc_packet_ack(deps.as_mut(), env.clone(), msg).unwrap();
+        assert_eq!(first_attr(&attributes, "ack_type").unwrap(), "beacon_price");
+
+        let res: BeaconPriceResponse =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::BeaconPrice {}).unwrap())
+                .unwrap();
+        assert_eq!(
+            res,
+            BeaconPriceResponse {
+                price: Uint128::new(50_000000),
+                updated: Some(updated),
+                age: Some(100),
+                refreshable_after: env.block.time.plus_seconds(BEACON_PRICE_REFRESH_INTERVAL),
+            }
+        );
+
+        // Works once the interval passed
+        let mut env = env;
+        env.block.time = env.block.time.plus_seconds(BEACON_PRICE_REFRESH_INTERVAL);
+        let msg = ExecuteMsg::RefreshBeaconPrice {};
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    #[test]
+    fn settle_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+        let unois_denom = IbcDenom {
+            ics20_channel: "channel-5".to_string(),
+            denom: "ibc/unois".to_string(),
+        };
+
+        // Not possible in funded mode
+        let msg = ExecuteMsg::Settle {};
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::SettlementNotSupported));
+
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            prices: None,
+            payment: Some("nois1payment".to_string()),
+            nois_beacon_price: Some(Uint128::new(50)),
+            mode: Some(OperationalMode::IbcPayBatched {
+                unois_denom: unois_denom.clone(),
+                threshold: Uint128::new(150),
+                interval: 3600,
+            }),
+            access_mode: None,
+            rate_limits: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::Settle {};
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::NoDebt));
+
+        // The first request prefunds the payment contract with the threshold
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "zero".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Ibc(IbcMsg::Transfer {
+                channel_id: "channel-5".to_string(),
+                to_address: "nois1payment".to_string(),
+                amount: Coin::new(200, "ibc/unois"),
+                timeout: mock_env()
+                    .block
+                    .time
+                    .plus_seconds(TRANSFER_PACKET_LIFETIME)
+                    .into(),
+            })
+        );
+        reply_send_packet(deps.as_mut(), "channel-12", 28);
+
+        // Requests accrue debt without sending a transfer
+        for job_id in ["one", "two"] {
+            let msg = ExecuteMsg::GetNextRandomness {
+                job_id: job_id.to_string(),
+            };
+            let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+            let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+            assert_eq!(res.messages.len(), 1);
+            reply_send_packet(deps.as_mut(), "channel-12", 29);
+        }
+        let DebtResponse { debt, last_settled } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Debt {}).unwrap()).unwrap();
+        assert_eq!(debt, Uint128::new(100));
+        assert_eq!(last_settled, Some(mock_env().block.time));
+
+        // Reaching the threshold sends the debt
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "three".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Ibc(IbcMsg::Transfer {
+                channel_id: "channel-5".to_string(),
+                to_address: "nois1payment".to_string(),
+                amount: Coin::new(150, "ibc/unois"),
+                timeout: mock_env()
+                    .block
+                    .time
+                    .plus_seconds(TRANSFER_PACKET_LIFETIME)
+                    .into(),
+            })
+        );
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let DebtResponse { debt, .. } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Debt {}).unwrap()).unwrap();
+        assert_eq!(debt, Uint128::zero());
+
+        // Passing the interval sends the debt
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "four".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(3600);
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "five".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+        let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+
+        // Settle sends the debt at any time
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "six".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let msg = ExecuteMsg::Settle {};
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(first_attr(&res.attributes, "amount").unwrap(), "50");
+        let DebtResponse { debt, last_settled } =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Debt {}).unwrap()).unwrap();
+        assert_eq!(debt, Uint128::zero());
+        assert_eq!(last_settled, Some(env.block.time));
+    }
+
+    #[test]
+    fn batched_settlement_keeps_gateway_payment_funded() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            prices: None,
+            payment: Some("nois1payment".to_string()),
+            nois_beacon_price: Some(Uint128::new(50)),
+            mode: Some(OperationalMode::IbcPayBatched {
+                unois_denom: IbcDenom {
+                    ics20_channel: "channel-5".to_string(),
+                    denom: "ibc/unois".to_string(),
+                },
+                threshold: Uint128::new(150),
+                interval: 3600,
+            }),
+            access_mode: None,
+            rate_limits: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        // Returns the unois transferred to the payment contract and the request packet
+        fn request(deps: DepsMut, job_id: String) -> (Uint128, InPacket) {
+            let msg = ExecuteMsg::GetNextRandomness { job_id };
+            let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+            let res = execute(deps, mock_env(), info, msg).unwrap();
+            let mut transferred = Uint128::zero();
+            let mut packet = None;
+            for sub in res.messages {
+                match sub.msg {
+                    CosmosMsg::Ibc(IbcMsg::Transfer { amount, .. }) => transferred += amount.amount,
+                    CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => {
+                        packet = Some(from_binary(&data).unwrap())
+                    }
+                    _ => {}
+                }
+            }
+            (transferred, packet.unwrap())
+        }
+
+        // The balance of the payment contract. The gateway rejects requests it does not cover.
+        let mut balance = Uint128::zero();
+        for i in 0..10 {
+            let (transferred, packet) = request(deps.as_mut(), format!("job{i}"));
+            balance += transferred;
+            reply_send_packet(deps.as_mut(), "channel-12", 29);
+
+            if i == 4 {
+                // The debt of a timed out request is reversed
+                let DebtResponse { debt, .. } =
+                    from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Debt {}).unwrap())
+                        .unwrap();
+                assert_eq!(debt, Uint128::new(50));
+                let msg = mock_ibc_packet_timeout("channel-12", &packet).unwrap();
+                let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+                assert_eq!(
+                    first_attr(&res.attributes, "gateway_debt_reversed").unwrap(),
+                    "50"
+                );
+                let DebtResponse { debt, .. } =
+                    from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Debt {}).unwrap())
+                        .unwrap();
+                assert_eq!(debt, Uint128::zero());
+                continue;
+            }
+
+            assert!(balance >= Uint128::new(50), "request {i} is underfunded");
+            balance -= Uint128::new(50);
+        }
+    }
+
+    #[test]
+    fn top_up_payment_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+        let unois_denom = IbcDenom {
+            ics20_channel: "channel-5".to_string(),
+            denom: "ibc/CB480EB3697F39DB828D9EFA021ABE681BFCD72E23894019B8DDB1AB94039081"
+                .to_string(),
+        };
+
+        // Only manager
+        let msg = ExecuteMsg::TopUpPayment {
+            amount: Uint128::new(1000),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized));
+
+        let msg = ExecuteMsg::TopUpPayment {
+            amount: Uint128::new(1000),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::TopUpNotConfigured));
+
+        let msg = ExecuteMsg::SetTopUp {
+            unois_denom: unois_denom.clone(),
+            auto: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::TopUpPayment {
+            amount: Uint128::new(1000),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentContractNotSet));
+
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            prices: None,
+            payment: Some("nois1payment".to_string()),
+            nois_beacon_price: None,
+            mode: None,
+            access_mode: None,
+            rate_limits: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::TopUpPayment {
+            amount: Uint128::new(1000),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(IbcMsg::Transfer {
+                channel_id: "channel-5".to_string(),
+                to_address: "nois1payment".to_string(),
+                amount: Coin::new(1000, &unois_denom.denom),
+                timeout: mock_env()
+                    .block
+                    .time
+                    .plus_seconds(TRANSFER_PACKET_LIFETIME)
+                    .into(),
+            })]
+        );
+
+        // Automatic top-up every 2 requests
+        let msg = ExecuteMsg::SetTopUp {
+            unois_denom: unois_denom.clone(),
+            auto: Some(AutoTopUp {
+                every_requests: 0,
+                amount: Uint128::new(100000),
+            }),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidAutoTopUp));
+        let msg = ExecuteMsg::SetTopUp {
+            unois_denom: unois_denom.clone(),
+            auto: Some(AutoTopUp {
+                every_requests: 2,
+                amount: Uint128::new(100000),
+            }),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let request = |deps: DepsMut, job_id: &str| {
+            let msg = ExecuteMsg::GetNextRandomness {
+                job_id: job_id.to_string(),
+            };
+            let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+            let res = execute(deps, mock_env(), info, msg).unwrap();
+            res.messages.len()
+        };
+
+        assert_eq!(request(deps.as_mut(), "one"), 1);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        assert_eq!(request(deps.as_mut(), "two"), 2);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let TopUpResponse {
+            requests_since_top_up,
+            ..
+        } = from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::TopUp {}).unwrap()).unwrap();
+        assert_eq!(requests_since_top_up, 0);
+
+        // Top-up is postponed when the balance is insufficient
+        let msg = ExecuteMsg::SetTopUp {
+            unois_denom,
+            auto: Some(AutoTopUp {
+                every_requests: 1,
+                amount: Uint128::new(200000),
+            }),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        assert_eq!(request(deps.as_mut(), "three"), 1);
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let TopUpResponse {
+            requests_since_top_up,
+            ..
+        } = from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::TopUp {}).unwrap()).unwrap();
+        assert_eq!(requests_since_top_up, 1);
+    }
+
+    #[test]
+    fn beacon_cache_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+        let round = 72780;
+        let published = drand_common::time_of_round(round);
+        let after = published.minus_seconds(5);
+
+        let msg = ExecuteMsg::SetBeaconCache {
+            settings: Some(BeaconCacheSettings {
+                lifetime: 600,
+                discount_percent: 101,
+            }),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBeaconCacheSettings));
+        let msg = ExecuteMsg::SetBeaconCache {
+            settings: Some(BeaconCacheSettings {
+                lifetime: 600,
+                discount_percent: 40,
+            }),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        // First request goes to the gateway
+        let msg = ExecuteMsg::GetRandomnessAfter {
+            after,
+            job_id: "first".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let first_request_id: u64 = first_attr(&res.attributes, "request_id")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages[0].msg else {
+            panic!("Unexpected message type");
+        };
+        let InPacket::RequestBeacon { origin, .. } = from_binary(data).unwrap() else {
+            panic!("Unexpected packet type");
+        };
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+
+        let randomness = HexBinary::from([0xbb; 32]);
+        let deliver = OutPacket::DeliverBeacon {
+            source_id: format!("drand:{}:{round}", drand_common::DRAND_CHAIN_HASH),
+            published,
+            randomness: randomness.clone(),
+            origin,
+        };
+        let msg = mock_ibc_packet_recv("channel-12", &deliver).unwrap();
+        ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+
+        // Second request is answered from the cache at a discount. It is made from within
+        // the callback of the first request, which did not reply yet.
+        let msg = ExecuteMsg::GetRandomnessAfter {
+            after,
+            job_id: "second".to_string(),
+        };
+        let info = mock_info("other_dapp", &coins(1_000000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(first_attr(&res.attributes, "cached").unwrap(), "true");
+        assert_eq!(
+            first_attr(&res.attributes, "charged").unwrap(),
+            "600000unoisx"
+        );
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "other_dapp".to_string(),
+                amount: coins(400000, "unoisx"),
+            })
+        );
+        assert_eq!(res.messages[1].id, CALLBACK_ID);
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "other_dapp".to_string(),
+                msg: to_binary(&ReceiverExecuteMsg::NoisReceive {
+                    callback: NoisCallback {
+                        job_id: "second".to_string(),
+                        published,
+                        randomness,
+                    },
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+        let request_id: u64 = first_attr(&res.attributes, "request_id")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let JobResponse { job } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Job { request_id }).unwrap())
+                .unwrap();
+        let job = job.unwrap();
+        assert_eq!(job.delivery_height, Some(mock_env().block.height));
+
+        // The nested callback replies first
+        callback_reply(deps.as_mut(), false);
+        callback_reply(deps.as_mut(), true);
+        let JobResponse { job } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Job { request_id }).unwrap())
+                .unwrap();
+        assert_eq!(job.unwrap().callback_success, Some(false));
+        let JobResponse { job } = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Job {
+                    request_id: first_request_id,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(job.unwrap().callback_success, Some(true));
+
+        // Batch requests always go to the gateway
+        let msg = ExecuteMsg::GetRandomnessBatch {
+            after,
+            job_ids: vec!["third".to_string(), "fourth".to_string()],
+        };
+        let info = mock_info("dapp", &coins(2_000000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(first_attr(&res.attributes, "cached"), None);
+        reply_send_packet(deps.as_mut(), "channel-12", 30);
+
+        // Disabled cache
+        let msg = ExecuteMsg::SetBeaconCache { settings: None };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let msg = ExecuteMsg::GetRandomnessAfter {
+            after,
+            job_id: "fifth".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(first_attr(&res.attributes, "cached"), None);
+    }
+
+    #[test]
+    fn subscribe_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
 
-    use super::*;
-    use cosmwasm_std::{
-        coins,
-        testing::{
-            mock_dependencies, mock_dependencies_with_balance, mock_env,
-            mock_ibc_channel_close_confirm, mock_ibc_channel_close_init,
-            mock_ibc_channel_connect_ack, mock_ibc_channel_connect_confirm,
-            mock_ibc_channel_open_init, mock_ibc_packet_ack, mock_info, MockApi, MockQuerier,
-            MockStorage,
-        },
-        CosmosMsg, IbcAcknowledgement, OwnedDeps, ReplyOn, Uint128,
-    };
-    use nois_protocol::{InPacketAck, APP_ORDER, BAD_APP_ORDER, IBC_APP_VERSION};
+        fn origin_of(res: &Response) -> (Timestamp, Binary) {
+            let CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) = &res.messages.last().unwrap().msg
+            else {
+                panic!("Unexpected message type");
+            };
+            let InPacket::RequestBeacon { after, origin } = from_binary(data).unwrap() else {
+                panic!("Unexpected packet type");
+            };
+            (after, origin)
+        }
 
-    const CREATOR: &str = "creator";
+        fn deliver(deps: DepsMut, origin: Binary) -> IbcReceiveResponse {
+            let deliver = OutPacket::DeliverBeacon {
+                source_id: "backend:123:456".to_string(),
+                published: Timestamp::from_seconds(1666343645),
+                randomness: HexBinary::from([0xcc; 32]),
+                origin,
+            };
+            let msg = mock_ibc_packet_recv("channel-12", &deliver).unwrap();
+            ibc_packet_receive(deps, mock_env(), msg).unwrap()
+        }
 
-    fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
-        let initial_funds = vec![
-            Coin::new(22334455, "unoisx"),
-            Coin::new(
-                123321,
-                "ibc/CB480EB3697F39DB828D9EFA021ABE681BFCD72E23894019B8DDB1AB94039081",
-            ),
-        ];
-        let mut deps = mock_dependencies_with_balance(&initial_funds);
-        let msg = InstantiateMsg {
-            manager: Some(CREATOR.to_string()),
-            prices: vec![Coin::new(1_000000, "unoisx")],
-            test_mode: true,
-            callback_gas_limit: 500_000,
-            mode: OperationalMode::Funded {},
+        let msg = ExecuteMsg::Subscribe {
+            interval: 60,
+            count: 0,
+            job_id_prefix: "raffle".to_string(),
         };
-        let info = mock_info(CREATOR, &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        deps
-    }
+        let info = mock_info("dapp", &coins(3_000000, "unoisx"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidSubscription));
 
-    /// Gets the value of the first attribute with the given key
-    fn first_attr(data: impl AsRef<[Attribute]>, search_key: &str) -> Option<String> {
-        data.as_ref().iter().find_map(|a| {
-            if a.key == search_key {
-                Some(a.value.clone())
-            } else {
-                None
-            }
-        })
-    }
+        let msg = ExecuteMsg::Subscribe {
+            interval: 60,
+            count: 3,
+            job_id_prefix: "raffle".to_string(),
+        };
+        let info = mock_info("dapp", &coins(2_000000, "unoisx"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientPayment));
 
-    fn setup_channel(mut deps: DepsMut) {
-        let init = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
-        ibc_channel_open(deps.branch(), mock_env(), init).unwrap();
+        let info = mock_info("dapp", &coins(3_000001, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "charged").unwrap(),
+            "3000000unoisx"
+        );
+        assert_eq!(res.messages.len(), 2);
+        let (after, origin) = origin_of(&res);
+        assert_eq!(after, mock_env().block.time);
+        let RequestBeaconOrigin { job_id, .. } = from_binary(&origin).unwrap();
+        assert_eq!(job_id, "raffle-0");
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
 
-        let ack = mock_ibc_channel_connect_ack("channel-12", APP_ORDER, IBC_APP_VERSION);
-        ibc_channel_connect(deps, mock_env(), ack).unwrap();
-    }
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::SubscriptionExists));
 
-    #[test]
-    fn instantiate_works() {
-        let mut deps = mock_dependencies();
-        let msg = InstantiateMsg {
-            manager: Some(CREATOR.to_string()),
-            prices: vec![Coin::new(1_000000, "unoisx")],
-            test_mode: false,
-            callback_gas_limit: 500_000,
-            mode: OperationalMode::Funded {},
+        let query_msg = QueryMsg::Subscriptions {
+            owner: "dapp".to_string(),
+            start_after: None,
+            limit: None,
         };
-        let info = mock_info(CREATOR, &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-    }
+        let SubscriptionsResponse { subscriptions } =
+            from_binary(&query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap()).unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].job_id_prefix, "raffle");
+        let subscription = &subscriptions[0].subscription;
+        assert_eq!(subscription.remaining, 2);
+        assert_eq!(subscription.requested, 1);
+        assert_eq!(subscription.price, Coin::new(1_000000, "unoisx"));
+        assert_eq!(subscription.next_after, after.plus_seconds(60));
+        assert!(subscription.pending_request.is_some());
 
-    //
-    // Execute tests
-    //
+        // Delivery triggers the next request
+        let res = deliver(deps.as_mut(), origin);
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(res.messages[0].id, CALLBACK_ID);
+        assert_eq!(res.messages[1].id, CONTINUE_SUBSCRIPTION_ID);
+        assert_eq!(res.messages[1].reply_on, ReplyOn::Error);
+        let CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr, msg, ..
+        }) = &res.messages[1].msg
+        else {
+            panic!("Unexpected message type");
+        };
+        assert_eq!(contract_addr, MOCK_CONTRACT_ADDR);
+        callback_reply(deps.as_mut(), true);
 
-    #[test]
-    fn get_next_randomness_works() {
-        let mut deps = setup();
+        let continue_msg: ExecuteMsg = from_binary(msg).unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(MOCK_CONTRACT_ADDR, &[]),
+            continue_msg.clone(),
+        )
+        .unwrap();
+        let (after, origin) = origin_of(&res);
+        assert_eq!(after, mock_env().block.time.plus_seconds(60));
+        let RequestBeaconOrigin { job_id, .. } = from_binary(&origin).unwrap();
+        assert_eq!(job_id, "raffle-1");
+        reply_send_packet(deps.as_mut(), "channel-12", 30);
 
-        // Requires a channel to forward requests to
-        setup_channel(deps.as_mut());
+        // Only one request in flight
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            continue_msg,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::SubscriptionNotContinuable));
 
-        let msg = ExecuteMsg::GetNextRandomness {
-            job_id: "foo".to_string(),
+        // Unsubscribe refunds the remaining beacon
+        let msg = ExecuteMsg::Unsubscribe {
+            job_id_prefix: "raffle".to_string(),
         };
-        let info = mock_info("dapp", &coins(22334455, "unoisx"));
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "dapp".to_string(),
+                amount: coins(1_000000, "unoisx"),
+            })]
+        );
+        let SubscriptionsResponse { subscriptions } =
+            from_binary(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap();
+        assert_eq!(subscriptions, vec![]);
+
+        // The request in flight is still delivered
+        let res = deliver(deps.as_mut(), origin);
         assert_eq!(res.messages.len(), 1);
-        let out_msg = &res.messages[0];
-        assert_eq!(out_msg.gas_limit, None);
-        assert_eq!(out_msg.reply_on, ReplyOn::Never);
-        assert!(matches!(
-            out_msg.msg,
-            CosmosMsg::Ibc(IbcMsg::SendPacket { .. })
-        ));
+        assert_eq!(res.messages[0].id, CALLBACK_ID);
     }
 
     #[test]
-    fn get_next_randomnes_for_invalid_inputs() {
+    fn continue_subscription_refunds_when_access_denied() {
         let mut deps = setup();
         setup_channel(deps.as_mut());
 
-        // Job ID too long
-        let msg = ExecuteMsg::GetNextRandomness {
-            job_id: "cb480eb3697f39db828d9efa021abe681bfcd72e23894019b8ddb1ab94039081-and-counting"
-                .to_string(),
+        let msg = ExecuteMsg::Subscribe {
+            interval: 60,
+            count: 3,
+            job_id_prefix: "raffle".to_string(),
         };
-        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
-        assert!(matches!(err, ContractError::JobIdTooLong));
-    }
+        let info = mock_info("dapp", &coins(3_000000, "unoisx"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
 
-    #[test]
-    fn get_randomness_after_works() {
-        let mut deps = setup();
+        // Dapp gets denied access after subscribing
+        let msg = ExecuteMsg::UpdateDenylist {
+            add: vec!["dapp".to_string()],
+            remove: vec![],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let msg = ExecuteMsg::SetConfig {
+            manager: None,
+            prices: None,
+            payment: None,
+            nois_beacon_price: None,
+            mode: None,
+            access_mode: Some(AccessMode::Denylist {}),
+            rate_limits: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
 
-        // Requires a channel to forward requests to
-        setup_channel(deps.as_mut());
+        // The request in flight is delivered but no further beacon is requested
+        let request_id = SUBSCRIPTIONS
+            .load(&deps.storage, (&Addr::unchecked("dapp"), "raffle"))
+            .unwrap()
+            .pending_request
+            .unwrap();
+        let origin = to_binary(&RequestBeaconOrigin {
+            sender: "dapp".to_string(),
+            job_id: "raffle-0".to_string(),
+            request_id: Some(request_id),
+        })
+        .unwrap();
+        let deliver = OutPacket::DeliverBeacon {
+            source_id: "backend:123:456".to_string(),
+            published: Timestamp::from_seconds(1666343645),
+            randomness: HexBinary::from([0xcc; 32]),
+            origin,
+        };
+        let msg = mock_ibc_packet_recv("channel-12", &deliver).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(res.messages[1].id, CONTINUE_SUBSCRIPTION_ID);
 
-        let msg = ExecuteMsg::GetRandomnessAfter {
-            after: Timestamp::from_seconds(1666343642),
-            job_id: "foo".to_string(),
+        let msg = ExecuteMsg::ContinueSubscription {
+            owner: "dapp".to_string(),
+            job_id_prefix: "raffle".to_string(),
         };
-        let info = mock_info("dapp", &coins(22334455, "unoisx"));
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(res.messages.len(), 1);
-        let out_msg = &res.messages[0];
-        assert_eq!(out_msg.gas_limit, None);
-        assert_eq!(out_msg.reply_on, ReplyOn::Never);
-        assert!(matches!(
-            out_msg.msg,
-            CosmosMsg::Ibc(IbcMsg::SendPacket { .. })
-        ));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(MOCK_CONTRACT_ADDR, &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "access_denied").unwrap(),
+            "true"
+        );
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "dapp".to_string(),
+                amount: coins(2_000000, "unoisx"),
+            })]
+        );
+        assert!(!SUBSCRIPTIONS.has(&deps.storage, (&Addr::unchecked("dapp"), "raffle")));
+
+        // Nothing left to continue
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::SubscriptionNotFound));
     }
 
     #[test]
-    fn get_randomness_after_fails_for_invalid_inputs() {
+    fn stats_works() {
         let mut deps = setup();
         setup_channel(deps.as_mut());
 
-        // Job ID too long
-        let msg = ExecuteMsg::GetRandomnessAfter {
-            after: Timestamp::from_seconds(1666343642),
-            job_id: "cb480eb3697f39db828d9efa021abe681bfcd72e23894019b8ddb1ab94039081-and-counting"
-                .to_string(),
+        // No stats yet
+        let GlobalStatsResponse { stats } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GlobalStats {}).unwrap())
+                .unwrap();
+        assert_eq!(stats, Stats::default());
+
+        request_and_deliver(deps.as_mut(), "dapp1", "job 1", 29);
+        callback_reply(deps.as_mut(), true);
+        request_and_deliver(deps.as_mut(), "dapp1", "job 2", 30);
+        callback_reply(deps.as_mut(), false);
+        request_and_deliver(deps.as_mut(), "dapp2", "job 3", 31);
+        callback_reply(deps.as_mut(), true);
+
+        let msg = QueryMsg::DappStats {
+            start_after: None,
+            limit: None,
         };
-        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
-        assert!(matches!(err, ContractError::JobIdTooLong));
+        let DappStatsResponse { stats } =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(
+            stats,
+            vec![
+                QueriedDappStats {
+                    address: "dapp1".to_string(),
+                    stats: Stats {
+                        requests: 2,
+                        deliveries: 2,
+                        failed_callbacks: 1,
+                        paid: coins(2_000_000, "unoisx"),
+                    },
+                },
+                QueriedDappStats {
+                    address: "dapp2".to_string(),
+                    stats: Stats {
+                        requests: 1,
+                        deliveries: 1,
+                        failed_callbacks: 0,
+                        paid: coins(1_000_000, "unoisx"),
+                    },
+                },
+            ]
+        );
+
+        // Pagination
+        let msg = QueryMsg::DappStats {
+            start_after: Some("dapp1".to_string()),
+            limit: Some(1),
+        };
+        let DappStatsResponse { stats } =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].address, "dapp2");
+
+        let GlobalStatsResponse { stats } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GlobalStats {}).unwrap())
+                .unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                requests: 3,
+                deliveries: 3,
+                failed_callbacks: 1,
+                paid: coins(3_000_000, "unoisx"),
+            }
+        );
     }
 
     #[test]
-    fn when_manager_is_not_set_manager_permissions_are_unathorised() {
-        // Check that if manager not set, a random person cannot execute manager-like operations.
-        let mut deps = mock_dependencies();
-        let msg = InstantiateMsg {
-            manager: None,
-            prices: vec![Coin::new(1_000000, "unoisx")],
-            test_mode: false,
-            callback_gas_limit: 500_000,
-            mode: OperationalMode::Funded {},
+    fn rate_limits_work() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
+
+        let request = |deps: DepsMut, sender: &str| {
+            let msg = ExecuteMsg::GetNextRandomness {
+                job_id: "foo".to_string(),
+            };
+            execute(
+                deps,
+                mock_env(),
+                mock_info(sender, &coins(1_000_000, "unoisx")),
+                msg,
+            )
         };
-        let info = mock_info(CREATOR, &[]);
-        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        // withdraw
-        let msg = ExecuteMsg::Withdraw {
-            denom: "unoisx".to_string(),
-            amount: Some(Uint128::new(12)),
-            address: "some-address".to_string(),
+        let set_rate_limits = |deps: DepsMut, rate_limits: RateLimits| {
+            let msg = ExecuteMsg::SetConfig {
+                manager: None,
+                prices: None,
+                payment: None,
+                nois_beacon_price: None,
+                mode: None,
+                access_mode: None,
+                rate_limits: Some(rate_limits),
+            };
+            execute(deps, mock_env(), mock_info(CREATOR, &[]), msg)
         };
-        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
-        assert!(matches!(err, ContractError::Unauthorized));
-        // withdraw all
-        let msg = ExecuteMsg::Withdraw {
-            denom: "unoisx".to_string(),
-            amount: None,
-            address: "some-address".to_string(),
+
+        // Window limits require a window
+        let err = set_rate_limits(
+            deps.as_mut(),
+            RateLimits {
+                sender_per_window: Some(1),
+                ..RateLimits::default()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidRateLimits));
+
+        set_rate_limits(
+            deps.as_mut(),
+            RateLimits {
+                sender_per_block: Some(1),
+                global_per_block: Some(2),
+                ..RateLimits::default()
+            },
+        )
+        .unwrap();
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.config.rate_limits.sender_per_block, Some(1));
+
+        request(deps.as_mut(), "dapp1").unwrap();
+        let err = request(deps.as_mut(), "dapp1").unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RateLimitExceeded {
+                limit: 1,
+                window: 0
+            }
+        ));
+        request(deps.as_mut(), "dapp2").unwrap();
+        let err = request(deps.as_mut(), "dapp3").unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RateLimitExceeded {
+                limit: 2,
+                window: 0
+            }
+        ));
+
+        // Subscriptions count all of their requests
+        set_rate_limits(
+            deps.as_mut(),
+            RateLimits {
+                sender_per_block: Some(2),
+                ..RateLimits::default()
+            },
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Subscribe {
+            interval: 60,
+            count: 3,
+            job_id_prefix: "raffle".to_string(),
         };
+        let info = mock_info("dapp4", &coins(3_000000, "unoisx"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RateLimitExceeded {
+                limit: 2,
+                window: 0
+            }
+        ));
+
+        // No limits
+        set_rate_limits(deps.as_mut(), RateLimits::default()).unwrap();
+        request(deps.as_mut(), "dapp1").unwrap();
+    }
+
+    #[test]
+    fn receive_cw20_works() {
+        let mut deps = setup();
+        setup_channel(deps.as_mut());
 
-        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
-        assert!(matches!(err, ContractError::Unauthorized));
-        // Edit config
         let msg = ExecuteMsg::SetConfig {
-            manager: Some("some-manager".to_string()),
-            prices: None,
+            manager: None,
+            prices: Some(vec![
+                Coin::new(1_000000, "unoisx"),
+                Coin::new(500, "cw20:token"),
+            ]),
             payment: None,
             nois_beacon_price: None,
             mode: None,
+            access_mode: None,
+            rate_limits: None,
         };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
 
-        let err = execute(deps.as_mut(), mock_env(), mock_info("dapp", &[]), msg).unwrap_err();
-        assert!(matches!(err, ContractError::Unauthorized));
-    }
+        // Overpayment is refunded in the CW20 token
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "dapp".to_string(),
+            amount: Uint128::new(600),
+            msg: to_binary(&ReceiveMsg::GetNextRandomness {
+                job_id: "foo".to_string(),
+            })
+            .unwrap(),
+        });
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&cw20::Cw20ExecuteMsg::Transfer {
+                    recipient: "dapp".to_string(),
+                    amount: Uint128::new(100),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+        assert_eq!(
+            first_attr(&res.attributes, "charged").unwrap(),
+            "500cw20:token"
+        );
+        let request_id: u64 = first_attr(&res.attributes, "request_id")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let job = JOBS.load(&deps.storage, request_id).unwrap();
+        assert_eq!(job.sender, "dapp");
+        assert_eq!(job.funds, coins(500, "cw20:token"));
 
-    #[test]
-    fn withdraw_works() {
-        let mut deps = setup();
+        // Tokens of other contracts are not accepted
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "dapp".to_string(),
+            amount: Uint128::new(600),
+            msg: to_binary(&ReceiveMsg::GetNextRandomness {
+                job_id: "foo".to_string(),
+            })
+            .unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("other", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientPayment));
+
+        // Native funds are not accepted along with CW20 tokens
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "dapp".to_string(),
+            amount: Uint128::new(500),
+            msg: to_binary(&ReceiveMsg::GetNextRandomness {
+                job_id: "foo".to_string(),
+            })
+            .unwrap(),
+        });
+        let info = mock_info("token", &coins(1_000000, "unoisx"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::NativeFundsWithCw20));
 
+        // Native funds cannot pretend to be CW20 tokens
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("dapp", &coins(500, "cw20:token"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::Cw20AsNativeFunds));
+
+        // Withdraw CW20 tokens
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, .. } if contract_addr == "token" => {
+                SystemResult::Ok(ContractResult::Ok(
+                    to_binary(&cw20::BalanceResponse {
+                        balance: Uint128::new(500),
+                    })
+                    .unwrap(),
+                ))
+            }
+            _ => panic!("Unexpected query: {query:?}"),
+        });
         let msg = ExecuteMsg::Withdraw {
-            denom: "unoisx".to_string(),
-            amount: Some(Uint128::new(12)),
+            denom: "cw20:token".to_string(),
+            amount: Some(Uint128::new(500)),
             address: "some-address".to_string(),
         };
-        let err = execute(
-            deps.as_mut(),
-            mock_env(),
-            mock_info("dapp", &[]),
-            msg.clone(),
-        )
-        .unwrap_err();
-        assert!(matches!(err, ContractError::Unauthorized));
         let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
-        assert_eq!(res.messages.len(), 1);
         assert_eq!(
-            res.messages[0].msg,
-            CosmosMsg::Bank(BankMsg::Send {
-                to_address: "some-address".to_string(),
-                amount: coins(12, "unoisx"),
-            })
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&cw20::Cw20ExecuteMsg::Transfer {
+                    recipient: "some-address".to_string(),
+                    amount: Uint128::new(500),
+                })
+                .unwrap(),
+                funds: vec![],
+            })]
         );
     }
 
     #[test]
-    fn withdraw_all_works() {
+    fn gateway_channels_work() {
         let mut deps = setup();
+        setup_channel(deps.as_mut());
 
-        let msg = ExecuteMsg::Withdraw {
-            denom: "unoisx".to_string(),
-            amount: None,
-            address: "some-address".to_string(),
+        // A second channel is connected but not approved
+        let msg = mock_ibc_channel_connect_ack("channel-13", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+        let welcome = OutPacket::Welcome {
+            payment: "payment13".to_string(),
         };
+        let msg = mock_ibc_packet_recv("channel-13", &welcome).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: StdAck = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, StdAck::Error(_)));
 
+        // Only the manager can approve
+        let msg = ExecuteMsg::ApproveGatewayChannel {
+            channel_id: "channel-13".to_string(),
+            approved: true,
+        };
         let err = execute(
             deps.as_mut(),
             mock_env(),
@@ -946,224 +5889,174 @@ mod tests {
         )
         .unwrap_err();
         assert!(matches!(err, ContractError::Unauthorized));
-        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
-
-        assert_eq!(res.messages.len(), 1);
-        assert_eq!(
-            res.messages[0].msg,
-            CosmosMsg::Bank(BankMsg::Send {
-                to_address: "some-address".to_string(),
-                amount: coins(22334455, "unoisx"),
-            })
-        );
-    }
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
 
-    //
-    // Query tests
-    //
+        // The payment contract of a backup channel is stored but not used
+        let msg = mock_ibc_packet_recv("channel-13", &welcome).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: StdAck = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(ack, StdAck::success(OutPacketAck::Welcome {}));
+        assert_eq!(CONFIG.load(&deps.storage).unwrap().payment, None);
 
-    #[test]
-    fn query_prices_works() {
-        let deps = setup();
+        // Requests time out on the primary channel
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "foo".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let CosmosMsg::Ibc(IbcMsg::SendPacket {
+            channel_id, data, ..
+        }) = &res.messages[0].msg
+        else {
+            panic!("Unexpected message type");
+        };
+        assert_eq!(channel_id, "channel-12");
+        let packet: InPacket = from_binary(data).unwrap();
+        reply_send_packet(deps.as_mut(), "channel-12", 29);
+        let msg = mock_ibc_packet_timeout("channel-12", &packet).unwrap();
+        ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
 
-        let PricesResponse { prices } =
-            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Prices {}).unwrap()).unwrap();
-        assert_eq!(prices, coins(1000000, "unoisx"));
-    }
+        // Switch to the backup channel
+        let msg = ExecuteMsg::SetPrimaryGatewayChannel {
+            channel_id: "channel-13".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        assert_eq!(
+            CONFIG.load(&deps.storage).unwrap().payment,
+            Some("payment13".to_string())
+        );
+        let msg = ExecuteMsg::GetNextRandomness {
+            job_id: "bar".to_string(),
+        };
+        let info = mock_info("dapp", &coins(1_000_000, "unoisx"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let CosmosMsg::Ibc(IbcMsg::SendPacket { channel_id, .. }) = &res.messages[0].msg else {
+            panic!("Unexpected message type");
+        };
+        assert_eq!(channel_id, "channel-13");
 
-    #[test]
-    fn query_price_works() {
-        let deps = setup();
+        // The primary channel cannot be revoked
+        let msg = ExecuteMsg::ApproveGatewayChannel {
+            channel_id: "channel-13".to_string(),
+            approved: false,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::CannotRevokePrimaryChannel));
+        let msg = ExecuteMsg::SetPrimaryGatewayChannel {
+            channel_id: "channel-14".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::UnknownChannel));
 
-        let PriceResponse { price } = from_binary(
-            &query(
-                deps.as_ref(),
-                mock_env(),
-                QueryMsg::Price {
-                    denom: "shitcoin".to_string(),
+        let msg = QueryMsg::GatewayChannels {
+            start_after: None,
+            limit: None,
+        };
+        let GatewayChannelsResponse { channels } =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(
+            channels,
+            vec![
+                QueriedGatewayChannel {
+                    channel_id: "channel-12".to_string(),
+                    primary: false,
+                    channel: GatewayChannel {
+                        approved: true,
+                        payment: None,
+                        acks: 0,
+                        error_acks: 0,
+                        timeouts: 1,
+                    },
                 },
-            )
-            .unwrap(),
-        )
-        .unwrap();
-        assert_eq!(price, None);
-
-        let PriceResponse { price } = from_binary(
-            &query(
-                deps.as_ref(),
-                mock_env(),
-                QueryMsg::Price {
-                    denom: "unoisx".to_string(),
+                QueriedGatewayChannel {
+                    channel_id: "channel-13".to_string(),
+                    primary: true,
+                    channel: GatewayChannel {
+                        approved: true,
+                        payment: Some("payment13".to_string()),
+                        acks: 0,
+                        error_acks: 0,
+                        timeouts: 0,
+                    },
                 },
-            )
-            .unwrap(),
-        )
-        .unwrap();
-        assert_eq!(price, Some(Uint128::new(1000000)));
-    }
-
-    //
-    // IBC tests
-    //
-
-    #[test]
-    fn ibc_channel_open_checks_version_and_order() {
-        let mut deps = setup();
-
-        // All good
-        let valid_handshake = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
-        ibc_channel_open(deps.as_mut(), mock_env(), valid_handshake).unwrap();
-
-        // Wrong order
-        let wrong_order = mock_ibc_channel_open_init("channel-12", BAD_APP_ORDER, IBC_APP_VERSION);
-        let res = ibc_channel_open(deps.as_mut(), mock_env(), wrong_order).unwrap_err();
-        assert!(matches!(res, ContractError::ChannelError(..)));
-
-        // Wrong version
-        let wrong_version = mock_ibc_channel_open_init("channel-12", APP_ORDER, "another version");
-        let res = ibc_channel_open(deps.as_mut(), mock_env(), wrong_version).unwrap_err();
-        assert!(matches!(res, ContractError::ChannelError(..)));
-    }
-
-    #[test]
-    fn ibc_channel_connect_works() {
-        // We are chain A and get the ChanOpenAck
-
-        let mut deps = setup();
-
-        // Channel is unset
-        let GatewayChannelResponse { channel } =
-            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
-                .unwrap();
-        assert_eq!(channel, None);
-
-        let msg = mock_ibc_channel_connect_ack("channel-12", APP_ORDER, IBC_APP_VERSION);
-        ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+            ]
+        );
 
-        // Channel is now set
+        // When the primary channel closes, the backup takes over
+        let msg = mock_ibc_channel_close_confirm("channel-13", APP_ORDER, IBC_APP_VERSION);
+        let res = ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            first_attr(&res.attributes, "new_primary_channel_id").unwrap(),
+            "channel-12"
+        );
         let GatewayChannelResponse { channel } =
             from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
                 .unwrap();
         assert_eq!(channel, Some("channel-12".to_string()));
-
-        // One more ChanOpenAck
-        let msg = mock_ibc_channel_connect_ack("channel-12", APP_ORDER, IBC_APP_VERSION);
-        let err = ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap_err();
-        assert!(matches!(err, ContractError::ChannelAlreadySet));
-
-        // ChanOpenConfirm is rejected
-        let msg = mock_ibc_channel_connect_confirm("channel-12", APP_ORDER, IBC_APP_VERSION);
-        let err = ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap_err();
-        assert!(matches!(err, ContractError::MustBeChainA));
     }
 
     #[test]
-    fn ibc_channel_close_works() {
+    fn gateway_channel_failover_updates_payment() {
         let mut deps = setup();
+        setup_channel(deps.as_mut());
+        let welcome = |deps: DepsMut, channel_id: &str, payment: &str| {
+            let packet = OutPacket::Welcome {
+                payment: payment.to_string(),
+            };
+            let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+            ibc_packet_receive(deps, mock_env(), msg).unwrap();
+        };
+        welcome(deps.as_mut(), "channel-12", "payment12");
+        assert_eq!(
+            CONFIG.load(&deps.storage).unwrap().payment,
+            Some("payment12".to_string())
+        );
 
-        // Open
-        let valid_handshake = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
-        ibc_channel_open(deps.as_mut(), mock_env(), valid_handshake).unwrap();
-
-        // Connect
-        let msg = mock_ibc_channel_connect_ack("channel-12", APP_ORDER, IBC_APP_VERSION);
+        let msg = mock_ibc_channel_connect_ack("channel-13", APP_ORDER, IBC_APP_VERSION);
         ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+        let msg = ExecuteMsg::ApproveGatewayChannel {
+            channel_id: "channel-13".to_string(),
+            approved: true,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        welcome(deps.as_mut(), "channel-13", "payment13");
+        assert_eq!(
+            CONFIG.load(&deps.storage).unwrap().payment,
+            Some("payment12".to_string())
+        );
 
-        // Channel is now set
-        let GatewayChannelResponse { channel } =
-            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
-                .unwrap();
-        assert_eq!(channel, Some("channel-12".to_string()));
-
-        // Closing channel fails
-        let msg = mock_ibc_channel_close_init("channel-12", APP_ORDER, IBC_APP_VERSION);
-        let err = ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap_err();
-        assert!(matches!(err, ContractError::ChannelMustNotBeClosed));
-
-        // Channel is still set
-        let GatewayChannelResponse { channel } =
-            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
-                .unwrap();
-        assert_eq!(channel, Some("channel-12".to_string()));
-
-        // The other side closed
+        // The backup takes over along with its payment contract
         let msg = mock_ibc_channel_close_confirm("channel-12", APP_ORDER, IBC_APP_VERSION);
         ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            CONFIG.load(&deps.storage).unwrap().payment,
+            Some("payment13".to_string())
+        );
 
-        // Channel is unset
+        // Without backup, new channels are not approved automatically
+        let msg = mock_ibc_channel_close_confirm("channel-13", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+        let msg = mock_ibc_channel_connect_ack("channel-14", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
         let GatewayChannelResponse { channel } =
             from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
                 .unwrap();
         assert_eq!(channel, None);
-    }
-
-    #[test]
-    fn ibc_packet_ack_works() {
-        let mut deps = setup();
-
-        // The proxy -> gateway packet we get the acknowledgement for
-        let packet = InPacket::RequestBeacon {
-            after: Timestamp::from_seconds(321),
-            origin: to_binary(&RequestBeaconOrigin {
-                sender: "contract345".to_string(),
-                job_id: "hello".to_string(),
-            })
-            .unwrap(),
+        let packet = OutPacket::Welcome {
+            payment: "payment14".to_string(),
         };
+        let msg = mock_ibc_packet_recv("channel-14", &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: StdAck = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, StdAck::Error(_)));
 
-        // Success ack (processed)
-        let ack = StdAck::success(InPacketAck::RequestProcessed {
-            source_id: "backend:123:456".to_string(),
-        });
-        let msg = mock_ibc_packet_ack(
-            "channel-12",
-            &packet,
-            IbcAcknowledgement::encode_json(&ack).unwrap(),
-        )
-        .unwrap();
-        let IbcBasicResponse { attributes, .. } =
-            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
-        assert_eq!(first_attr(&attributes, "action").unwrap(), "ack");
-        assert_eq!(first_attr(&attributes, "is_error").unwrap(), "false");
-        assert_eq!(first_attr(&attributes, "error"), None);
-        assert_eq!(
-            first_attr(&attributes, "ack_type").unwrap(),
-            "request_processed"
-        );
-
-        // Success ack (queued)
-        let ack = StdAck::success(InPacketAck::RequestQueued {
-            source_id: "backend:123:456".to_string(),
-        });
-        let msg = mock_ibc_packet_ack(
-            "channel-12",
-            &packet,
-            IbcAcknowledgement::encode_json(&ack).unwrap(),
-        )
-        .unwrap();
-        let IbcBasicResponse { attributes, .. } =
-            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
-        assert_eq!(first_attr(&attributes, "action").unwrap(), "ack");
-        assert_eq!(first_attr(&attributes, "is_error").unwrap(), "false");
-        assert_eq!(first_attr(&attributes, "error"), None);
-        assert_eq!(
-            first_attr(&attributes, "ack_type").unwrap(),
-            "request_queued"
-        );
-
-        // Error ack
-        let ack = StdAck::error("kaputt");
-        let msg = mock_ibc_packet_ack(
-            "channel-12",
-            &packet,
-            IbcAcknowledgement::encode_json(&ack).unwrap(),
-        )
-        .unwrap();
-        let IbcBasicResponse { attributes, .. } =
-            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
-        assert_eq!(first_attr(&attributes, "action").unwrap(), "ack");
-        assert_eq!(first_attr(&attributes, "is_error").unwrap(), "true");
-        assert_eq!(first_attr(&attributes, "error").unwrap(), "kaputt");
-        assert_eq!(first_attr(&attributes, "ack_type"), None);
+        let msg = ExecuteMsg::SetPrimaryGatewayChannel {
+            channel_id: "channel-14".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let GatewayChannelResponse { channel } =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GatewayChannel {}).unwrap())
+                .unwrap();
+        assert_eq!(channel, Some("channel-14".to_string()));
     }
 }
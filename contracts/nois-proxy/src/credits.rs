@@ -0,0 +1,134 @@
+use cosmwasm_std::{Addr, Coin, StdResult, Storage};
+
+use crate::error::ContractError;
+use crate::escrow::{add_escrow, remove_escrow};
+use crate::state::{CreditUsage, CREDITS, CREDIT_HISTORY, CREDIT_HISTORY_LEN};
+
+/// Adds the amount to the credit of the given address
+pub fn add_credit(storage: &mut dyn Storage, address: &Addr, amount: &Coin) -> StdResult<()> {
+    let current = CREDITS
+        .may_load(storage, (address, &amount.denom))?
+        .unwrap_or_default();
+    CREDITS.save(
+        storage,
+        (address, &amount.denom),
+        &(current + amount.amount),
+    )?;
+    add_escrow(storage, amount)
+}
+
+/// Removes the amount from the credit of the given address. Errors if the credit is insufficient.
+pub fn remove_credit(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    amount: &Coin,
+) -> Result<(), ContractError> {
+    let current = CREDITS
+        .may_load(storage, (address, &amount.denom))?
+        .unwrap_or_default();
+    let new = current
+        .checked_sub(amount.amount)
+        .map_err(|_| ContractError::InsufficientCredit)?;
+    if new.is_zero() {
+        CREDITS.remove(storage, (address, &amount.denom));
+    } else {
+        CREDITS.save(storage, (address, &amount.denom), &new)?;
+    }
+    remove_escrow(storage, amount)?;
+    Ok(())
+}
+
+/// Finds the first price in the one-of list which can be paid from the credit of the
+/// given address.
+pub fn find_credit_payment(
+    storage: &dyn Storage,
+    address: &Addr,
+    prices: &[Coin],
+) -> Result<Coin, ContractError> {
+    if prices.is_empty() {
+        return Err(ContractError::NoPaymentOption);
+    }
+
+    for price in prices {
+        let credit = CREDITS
+            .may_load(storage, (address, &price.denom))?
+            .unwrap_or_default();
+        if credit >= price.amount {
+            return Ok(price.clone());
+        }
+    }
+    Err(ContractError::InsufficientPayment)
+}
+
+/// Appends an entry to the credit history of the given address
+pub fn record_credit_usage(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    usage: &CreditUsage,
+) -> StdResult<()> {
+    let index = CREDIT_HISTORY_LEN
+        .may_load(storage, address)?
+        .unwrap_or_default();
+    CREDIT_HISTORY.save(storage, (address, index), usage)?;
+    CREDIT_HISTORY_LEN.save(storage, address, &(index + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::{coin, testing::mock_dependencies, Uint128};
+
+    #[test]
+    fn add_and_remove_credit_works() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+        let dapp = Addr::unchecked("dapp");
+
+        add_credit(s, &dapp, &coin(100, "bucks")).unwrap();
+        add_credit(s, &dapp, &coin(50, "bucks")).unwrap();
+        assert_eq!(
+            CREDITS.load(s, (&dapp, "bucks")).unwrap(),
+            Uint128::new(150)
+        );
+
+        remove_credit(s, &dapp, &coin(120, "bucks")).unwrap();
+        assert_eq!(CREDITS.load(s, (&dapp, "bucks")).unwrap(), Uint128::new(30));
+
+        let err = remove_credit(s, &dapp, &coin(31, "bucks")).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientCredit));
+        let err = remove_credit(s, &dapp, &coin(1, "sand")).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientCredit));
+
+        // Empty credit is removed
+        remove_credit(s, &dapp, &coin(30, "bucks")).unwrap();
+        assert_eq!(CREDITS.may_load(s, (&dapp, "bucks")).unwrap(), None);
+    }
+
+    #[test]
+    fn find_credit_payment_works() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+        let dapp = Addr::unchecked("dapp");
+
+        // No payment option
+        let err = find_credit_payment(s, &dapp, &[]).unwrap_err();
+        assert!(matches!(err, ContractError::NoPaymentOption));
+
+        let prices = vec![coin(100, "bucks"), coin(20, "sand")];
+        let err = find_credit_payment(s, &dapp, &prices).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientPayment));
+
+        add_credit(s, &dapp, &coin(99, "bucks")).unwrap();
+        let err = find_credit_payment(s, &dapp, &prices).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientPayment));
+
+        add_credit(s, &dapp, &coin(20, "sand")).unwrap();
+        let payment = find_credit_payment(s, &dapp, &prices).unwrap();
+        assert_eq!(payment, coin(20, "sand"));
+
+        // First option is preferred
+        add_credit(s, &dapp, &coin(1, "bucks")).unwrap();
+        let payment = find_credit_payment(s, &dapp, &prices).unwrap();
+        assert_eq!(payment, coin(100, "bucks"));
+    }
+}
@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Coin, StdError, Timestamp};
 
 use nois_protocol::ChannelError;
 
@@ -13,6 +13,18 @@ pub enum ContractError {
     #[error("Unauthorized")]
     Unauthorized,
 
+    #[error("Cannot migrate from contract '{previous_contract}'.")]
+    CannotMigrateFromContract { previous_contract: String },
+
+    #[error("Cannot downgrade from version {previous_version} to {new_version}.")]
+    CannotDowngrade {
+        previous_version: String,
+        new_version: String,
+    },
+
+    #[error("Invalid contract version '{version}'.")]
+    InvalidVersion { version: String },
+
     #[error("Job ID exceeds length limit.")]
     JobIdTooLong,
 
@@ -22,6 +34,50 @@ pub enum ContractError {
     #[error("Insufficient payment.")]
     InsufficientPayment,
 
+    #[error("Batch must contain between 1 and {max} jobs.")]
+    InvalidBatchSize { max: usize },
+
+    #[error("Sender is not allowed to request beacons from this proxy.")]
+    AccessDenied,
+
+    #[error("Discount tiers must be sorted by min_requests, must not exceed 100 % and require a period.")]
+    InvalidDiscounts,
+
+    #[error(
+        "Callback gas bounds must be a valid range and gas tiers must be sorted by min_gas_limit."
+    )]
+    InvalidCallbackGasBounds,
+
+    #[error("Callback gas limit must be between {min} and {max}.")]
+    CallbackGasLimitOutOfBounds { min: u64, max: u64 },
+
+    #[error("Insufficient credit.")]
+    InsufficientCredit,
+
+    #[error("No funds were sent.")]
+    NoFundsSent,
+
+    #[error("Only {available} can be withdrawn. The rest is held on behalf of dapps.")]
+    WithdrawalExceedsAvailable { available: Coin },
+
+    #[error("CW20 tokens must be sent via the CW20 contract")]
+    Cw20AsNativeFunds,
+
+    #[error("Native funds must not be sent along with CW20 tokens")]
+    NativeFundsWithCw20,
+
+    #[error("Denom '{denom}' is not accepted as payment by this proxy.")]
+    UnsupportedDenom { denom: String },
+
+    #[error("No failed callback found for this job.")]
+    FailedCallbackNotFound,
+
+    #[error("The callback was retried too often.")]
+    CallbackRetriesExhausted,
+
+    #[error("The callback cannot be retried anymore since it expired.")]
+    CallbackExpired,
+
     //
     // IBC
     //
@@ -31,12 +87,60 @@ pub enum ContractError {
     #[error("Unsupported packet type.")]
     UnsupportedPacketType,
 
+    #[error("Beacon price was updated recently. It can be refreshed after {refreshable_after}.")]
+    BeaconPriceRefreshTooEarly { refreshable_after: Timestamp },
+
+    #[error("The proxy does not pay in IBCed unois or the payment contract is not set")]
+    SettlementNotSupported,
+
+    #[error("There is no debt to settle")]
+    NoDebt,
+
+    #[error("Invalid subscription. Interval and count must not be zero.")]
+    InvalidSubscription,
+
+    #[error("A subscription with this job ID prefix already exists")]
+    SubscriptionExists,
+
+    #[error("Subscription not found")]
+    SubscriptionNotFound,
+
+    #[error("The subscription has a beacon request in flight or no remaining beacons")]
+    SubscriptionNotContinuable,
+
+    #[error("Invalid beacon cache settings. The discount must not exceed 100 %.")]
+    InvalidBeaconCacheSettings,
+
+    #[error("Invalid rate limits. The window must be set when limiting requests per window.")]
+    InvalidRateLimits,
+
+    #[error("Rate limit of {limit} requests per {} exceeded", if *window == 0 { "block".to_string() } else { format!("{window} seconds") })]
+    RateLimitExceeded { limit: u32, window: u64 },
+
+    #[error("Top-up of the payment contract is not configured")]
+    TopUpNotConfigured,
+
+    #[error("Payment contract address is not set")]
+    PaymentContractNotSet,
+
+    #[error("Invalid automatic top-up. The number of requests must not be zero.")]
+    InvalidAutoTopUp,
+
     #[error("Channel is not stored. Channel not yet established or closed.")]
     UnsetChannel,
 
     #[error("Channel was already created. Channel is immutable and cannot be overriden")]
     ChannelAlreadySet,
 
+    #[error("Channel '{channel_id}' is not an approved gateway channel")]
+    UnapprovedChannel { channel_id: String },
+
+    #[error("Channel is not a gateway channel of this proxy")]
+    UnknownChannel,
+
+    #[error("The primary gateway channel cannot be revoked")]
+    CannotRevokePrimaryChannel,
+
     #[error("Channel must not be closed.")]
     ChannelMustNotBeClosed,
 
@@ -0,0 +1,59 @@
+use cosmwasm_std::{Coin, StdResult, Storage};
+
+use crate::state::ESCROW;
+
+/// Adds the amount to the funds held on behalf of dapps
+pub fn add_escrow(storage: &mut dyn Storage, amount: &Coin) -> StdResult<()> {
+    let current = ESCROW.may_load(storage, &amount.denom)?.unwrap_or_default();
+    ESCROW.save(storage, &amount.denom, &(current + amount.amount))
+}
+
+/// Removes the amount from the funds held on behalf of dapps
+pub fn remove_escrow(storage: &mut dyn Storage, amount: &Coin) -> StdResult<()> {
+    let current = ESCROW.may_load(storage, &amount.denom)?.unwrap_or_default();
+    let new = current.checked_sub(amount.amount)?;
+    if new.is_zero() {
+        ESCROW.remove(storage, &amount.denom);
+    } else {
+        ESCROW.save(storage, &amount.denom, &new)?;
+    }
+    Ok(())
+}
+
+/// The part of the balance that does not belong to dapps and can be withdrawn
+pub fn withdrawable(storage: &dyn Storage, balance: Coin) -> StdResult<Coin> {
+    let escrowed = ESCROW
+        .may_load(storage, &balance.denom)?
+        .unwrap_or_default();
+    Ok(Coin {
+        amount: balance.amount.saturating_sub(escrowed),
+        denom: balance.denom,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::{coin, testing::MockStorage};
+
+    #[test]
+    fn escrow_works() {
+        let mut storage = MockStorage::new();
+
+        // Nothing escrowed
+        let available = withdrawable(&storage, coin(100, "unoisx")).unwrap();
+        assert_eq!(available, coin(100, "unoisx"));
+
+        add_escrow(&mut storage, &coin(30, "unoisx")).unwrap();
+        add_escrow(&mut storage, &coin(40, "unoisx")).unwrap();
+        add_escrow(&mut storage, &coin(5, "other")).unwrap();
+        let available = withdrawable(&storage, coin(100, "unoisx")).unwrap();
+        assert_eq!(available, coin(30, "unoisx"));
+        let available = withdrawable(&storage, coin(50, "unoisx")).unwrap();
+        assert_eq!(available, coin(0, "unoisx"));
+
+        remove_escrow(&mut storage, &coin(70, "unoisx")).unwrap();
+        assert_eq!(ESCROW.may_load(&storage, "unoisx").unwrap(), None);
+        remove_escrow(&mut storage, &coin(6, "other")).unwrap_err();
+    }
+}
@@ -0,0 +1,157 @@
+use cosmwasm_std::{Addr, StdResult, Storage};
+
+use crate::error::ContractError;
+use crate::state::{CallbackGasBounds, Job, CALLBACK_GAS_BOUNDS, CALLBACK_GAS_LIMITS};
+
+/// Checks that the bounds are a valid range and the tiers are sorted
+pub fn validate_gas_bounds(bounds: &CallbackGasBounds) -> Result<(), ContractError> {
+    if bounds.min > bounds.max {
+        return Err(ContractError::InvalidCallbackGasBounds);
+    }
+    let sorted = bounds
+        .tiers
+        .windows(2)
+        .all(|pair| pair[0].min_gas_limit < pair[1].min_gas_limit);
+    if !sorted {
+        return Err(ContractError::InvalidCallbackGasBounds);
+    }
+    Ok(())
+}
+
+/// Checks that a gas limit can be registered. Without bounds only the default
+/// limit can be used, so registration is not possible.
+pub fn validate_gas_limit(storage: &dyn Storage, gas_limit: u64) -> Result<(), ContractError> {
+    match CALLBACK_GAS_BOUNDS.may_load(storage)? {
+        Some(CallbackGasBounds { min, max, .. }) if (min..=max).contains(&gas_limit) => Ok(()),
+        Some(CallbackGasBounds { min, max, .. }) => {
+            Err(ContractError::CallbackGasLimitOutOfBounds { min, max })
+        }
+        None => Err(ContractError::CallbackGasLimitOutOfBounds { min: 0, max: 0 }),
+    }
+}
+
+/// The gas limit for callbacks to the given dapp.
+///
+/// This is the registered limit, clamped to the current bounds in case they changed after
+/// registration. Falls back to `default` if the dapp has no limit registered or
+/// the bounds were removed.
+pub fn callback_gas_limit_for(storage: &dyn Storage, default: u64, dapp: &Addr) -> StdResult<u64> {
+    let Some(gas_limit) = CALLBACK_GAS_LIMITS.may_load(storage, dapp)? else {
+        return Ok(default);
+    };
+    Ok(match CALLBACK_GAS_BOUNDS.may_load(storage)? {
+        Some(bounds) => gas_limit.clamp(bounds.min, bounds.max),
+        None => default,
+    })
+}
+
+/// The gas limit for callbacks of a job. This is the limit stored at request time, such
+/// that changes of the registration or bounds do not affect requests that were already paid.
+pub fn job_callback_gas_limit(storage: &dyn Storage, default: u64, job: &Job) -> StdResult<u64> {
+    match job.callback_gas_limit {
+        Some(gas_limit) => Ok(gas_limit),
+        None => callback_gas_limit_for(storage, default, &job.sender),
+    }
+}
+
+/// The price increase in percent for the given dapp based on its registered gas limit
+pub fn gas_price_increase_percent(storage: &dyn Storage, dapp: &Addr) -> StdResult<u32> {
+    let Some(gas_limit) = CALLBACK_GAS_LIMITS.may_load(storage, dapp)? else {
+        return Ok(0);
+    };
+    let Some(bounds) = CALLBACK_GAS_BOUNDS.may_load(storage)? else {
+        return Ok(0);
+    };
+    let gas_limit = gas_limit.clamp(bounds.min, bounds.max);
+    Ok(bounds
+        .tiers
+        .iter()
+        .rev()
+        .find(|tier| gas_limit >= tier.min_gas_limit)
+        .map(|tier| tier.price_increase_percent)
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::GasTier;
+    use cosmwasm_std::testing::mock_dependencies;
+
+    fn bounds() -> CallbackGasBounds {
+        CallbackGasBounds {
+            min: 100_000,
+            max: 2_000_000,
+            tiers: vec![
+                GasTier {
+                    min_gas_limit: 500_000,
+                    price_increase_percent: 0,
+                },
+                GasTier {
+                    min_gas_limit: 1_000_000,
+                    price_increase_percent: 50,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn validate_gas_bounds_works() {
+        validate_gas_bounds(&bounds()).unwrap();
+
+        let mut invalid = bounds();
+        invalid.min = 3_000_000;
+        let err = validate_gas_bounds(&invalid).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidCallbackGasBounds));
+
+        let mut invalid = bounds();
+        invalid.tiers.reverse();
+        let err = validate_gas_bounds(&invalid).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidCallbackGasBounds));
+    }
+
+    #[test]
+    fn callback_gas_limit_for_works() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+        let dapp = Addr::unchecked("dapp");
+
+        // Nothing registered
+        assert_eq!(callback_gas_limit_for(s, 500_000, &dapp).unwrap(), 500_000);
+        assert_eq!(gas_price_increase_percent(s, &dapp).unwrap(), 0);
+        let err = validate_gas_limit(s, 500_000).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::CallbackGasLimitOutOfBounds { .. }
+        ));
+
+        CALLBACK_GAS_BOUNDS.save(s, &bounds()).unwrap();
+        validate_gas_limit(s, 1_500_000).unwrap();
+        let err = validate_gas_limit(s, 2_000_001).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::CallbackGasLimitOutOfBounds {
+                min: 100_000,
+                max: 2_000_000
+            }
+        ));
+
+        CALLBACK_GAS_LIMITS.save(s, &dapp, &1_500_000).unwrap();
+        assert_eq!(
+            callback_gas_limit_for(s, 500_000, &dapp).unwrap(),
+            1_500_000
+        );
+        assert_eq!(gas_price_increase_percent(s, &dapp).unwrap(), 50);
+
+        // Clamped to new bounds
+        let mut new_bounds = bounds();
+        new_bounds.max = 800_000;
+        CALLBACK_GAS_BOUNDS.save(s, &new_bounds).unwrap();
+        assert_eq!(callback_gas_limit_for(s, 500_000, &dapp).unwrap(), 800_000);
+        assert_eq!(gas_price_increase_percent(s, &dapp).unwrap(), 0);
+
+        // Bounds removed
+        CALLBACK_GAS_BOUNDS.remove(s);
+        assert_eq!(callback_gas_limit_for(s, 500_000, &dapp).unwrap(), 500_000);
+    }
+}
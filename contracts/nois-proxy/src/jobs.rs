@@ -13,13 +13,13 @@ pub fn validate_job_id(job_id: &str) -> Result<(), ContractError> {
 }
 
 /// Checks if provided funds are sufficient to pay the price in one of the
-/// supported denoms. Payment cannot be split across multiple denoms. Extra funds
-/// are ignored.
+/// supported denoms. Payment cannot be split across multiple denoms. Returns
+/// the price that is charged. Use [`surplus`] to get the funds to be refunded.
 ///
 /// When `prices` is an empty list the user cannot pay because there is no possible
 /// denomination in which they could do that. This can be desired in case the cantract
 /// does not want to accapt any payment (i.e. is closed).
-pub fn validate_payment(prices: &[Coin], funds: &[Coin]) -> Result<(), ContractError> {
+pub fn validate_payment(prices: &[Coin], funds: &[Coin]) -> Result<Coin, ContractError> {
     if prices.is_empty() {
         return Err(ContractError::NoPaymentOption);
     }
@@ -29,13 +29,39 @@ pub fn validate_payment(prices: &[Coin], funds: &[Coin]) -> Result<(), ContractE
         if let Some(price) = prices.get(&fund.denom) {
             // user can pay in this provided denom
             if fund.amount >= *price {
-                return Ok(());
+                return Ok(Coin {
+                    denom: fund.denom.clone(),
+                    amount: *price,
+                });
             }
         }
     }
     Err(ContractError::InsufficientPayment)
 }
 
+/// Returns the funds that remain after the charged amount was taken. This
+/// includes the surplus in the charged denom as well as all unrelated coins.
+pub fn surplus(funds: &[Coin], charged: &Coin) -> Vec<Coin> {
+    let mut charged = Some(charged);
+    let mut out = Vec::with_capacity(funds.len());
+    for fund in funds {
+        let amount = match charged {
+            Some(c) if c.denom == fund.denom && fund.amount >= c.amount => {
+                charged = None;
+                fund.amount - c.amount
+            }
+            _ => fund.amount,
+        };
+        if !amount.is_zero() {
+            out.push(Coin {
+                denom: fund.denom.clone(),
+                amount,
+            });
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use cosmwasm_std::{coin, coins};
@@ -69,8 +95,10 @@ mod tests {
 
         // Single payment option
         let prices = coins(100, "bucks");
-        validate_payment(&prices, &[coin(100, "bucks")]).unwrap();
-        validate_payment(&prices, &[coin(150, "bucks")]).unwrap();
+        let charged = validate_payment(&prices, &[coin(100, "bucks")]).unwrap();
+        assert_eq!(charged, coin(100, "bucks"));
+        let charged = validate_payment(&prices, &[coin(150, "bucks")]).unwrap();
+        assert_eq!(charged, coin(100, "bucks"));
         validate_payment(&prices, &[coin(100, "bucks"), coin(20, "ujunox")]).unwrap();
         validate_payment(&prices, &[coin(20, "ujunox"), coin(100, "bucks")]).unwrap();
         validate_payment(
@@ -91,9 +119,11 @@ mod tests {
         // Multi payment option
         let prices = vec![coin(100, "bucks"), coin(20, "sand")];
         validate_payment(&prices, &[coin(100, "bucks")]).unwrap();
-        validate_payment(&prices, &[coin(20, "sand")]).unwrap();
+        let charged = validate_payment(&prices, &[coin(20, "sand")]).unwrap();
+        assert_eq!(charged, coin(20, "sand"));
         validate_payment(&prices, &[coin(150, "bucks")]).unwrap();
-        validate_payment(&prices, &[coin(50, "sand")]).unwrap();
+        let charged = validate_payment(&prices, &[coin(50, "sand")]).unwrap();
+        assert_eq!(charged, coin(20, "sand"));
         validate_payment(&prices, &[coin(100, "bucks"), coin(20, "ujunox")]).unwrap();
         validate_payment(&prices, &[coin(20, "ujunox"), coin(100, "bucks")]).unwrap();
         validate_payment(
@@ -113,4 +143,27 @@ mod tests {
         let err = validate_payment(&prices, &[coin(99, "bucks"), coin(200, "gold")]).unwrap_err();
         assert!(matches!(err, ContractError::InsufficientPayment));
     }
+
+    #[test]
+    fn surplus_works() {
+        let charged = coin(100, "bucks");
+        assert_eq!(surplus(&[coin(100, "bucks")], &charged), vec![]);
+        assert_eq!(surplus(&[coin(150, "bucks")], &charged), coins(50, "bucks"));
+        assert_eq!(
+            surplus(&[coin(20, "ujunox"), coin(100, "bucks")], &charged),
+            coins(20, "ujunox")
+        );
+        assert_eq!(
+            surplus(
+                &[coin(20, "ujunox"), coin(120, "bucks"), coin(55, "uluna")],
+                &charged
+            ),
+            vec![coin(20, "ujunox"), coin(20, "bucks"), coin(55, "uluna")]
+        );
+        // Charges only once
+        assert_eq!(
+            surplus(&[coin(100, "bucks"), coin(100, "bucks")], &charged),
+            coins(100, "bucks")
+        );
+    }
 }
@@ -1,5 +1,16 @@
+mod beacon_cache;
+mod channels;
+mod credits;
+mod escrow;
+mod gas_limits;
 mod jobs;
+mod migrations;
+mod prices;
 mod publish_time;
+mod rate_limits;
+mod settlement;
+mod stats;
+mod tokens;
 
 pub mod contract;
 pub mod error;
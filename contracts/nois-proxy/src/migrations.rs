@@ -0,0 +1,140 @@
+//! State migrations from older versions.
+//!
+//! Every step migrates the state written by an older version into the format of
+//! the current version.
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Coin, StdResult, Storage, Timestamp, Uint128};
+use cw_storage_plus::Item;
+
+use semver::Version;
+
+use crate::error::ContractError;
+use crate::state::{
+    AccessMode, Config, GatewayChannel, OperationalMode, RateLimits, CONFIG,
+    FIRST_CHANNEL_CONNECTED, GATEWAY_CHANNEL, GATEWAY_CHANNELS,
+};
+
+pub fn parse_version(version: &str) -> Result<Version, ContractError> {
+    version.parse().map_err(|_| ContractError::InvalidVersion {
+        version: version.to_string(),
+    })
+}
+
+/// The config as stored by 0.13 and earlier, i.e. before cw2 versioning was added
+#[cw_serde]
+struct ConfigV0_13 {
+    prices: Vec<Coin>,
+    manager: Option<Addr>,
+    test_mode: bool,
+    callback_gas_limit: u64,
+    payment: Option<String>,
+    nois_beacon_price: Uint128,
+    nois_beacon_price_updated: Timestamp,
+    mode: OperationalMode,
+}
+
+const CONFIG_V0_13: Item<ConfigV0_13> = Item::new("config");
+
+/// Migrates unversioned contracts (0.13 and earlier) to the current version.
+///
+/// Adds `Config::access_mode` and `Config::rate_limits` and adds the gateway channel
+/// to `GATEWAY_CHANNELS`, which did not exist before. Since a channel was connected
+/// already, further channels need approval.
+pub fn migrate_v0_13(
+    storage: &mut dyn Storage,
+    access_mode: AccessMode,
+    rate_limits: RateLimits,
+) -> StdResult<()> {
+    let ConfigV0_13 {
+        prices,
+        manager,
+        test_mode,
+        callback_gas_limit,
+        payment,
+        nois_beacon_price,
+        nois_beacon_price_updated,
+        mode,
+    } = CONFIG_V0_13.load(storage)?;
+    let config = Config {
+        prices,
+        manager,
+        test_mode,
+        callback_gas_limit,
+        payment,
+        nois_beacon_price,
+        nois_beacon_price_updated,
+        mode,
+        access_mode,
+        rate_limits,
+    };
+    CONFIG.save(storage, &config)?;
+
+    if let Some(channel_id) = GATEWAY_CHANNEL.may_load(storage)? {
+        let channel = GatewayChannel {
+            approved: true,
+            payment: config.payment,
+            ..GatewayChannel::default()
+        };
+        GATEWAY_CHANNELS.save(storage, &channel_id, &channel)?;
+        FIRST_CHANNEL_CONNECTED.save(storage, &true)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::{coins, testing::mock_dependencies};
+
+    fn config_v0_13(payment: Option<String>) -> ConfigV0_13 {
+        ConfigV0_13 {
+            prices: coins(1_000_000, "unoisx"),
+            manager: Some(Addr::unchecked("manager")),
+            test_mode: false,
+            callback_gas_limit: 500_000,
+            payment,
+            nois_beacon_price: Uint128::zero(),
+            nois_beacon_price_updated: Timestamp::from_seconds(0),
+            mode: OperationalMode::Funded {},
+        }
+    }
+
+    #[test]
+    fn migrate_v0_13_works() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+        CONFIG_V0_13.save(s, &config_v0_13(None)).unwrap();
+
+        let rate_limits = RateLimits {
+            sender_per_block: Some(3),
+            ..RateLimits::default()
+        };
+        migrate_v0_13(s, AccessMode::Allowlist {}, rate_limits.clone()).unwrap();
+        let config = CONFIG.load(s).unwrap();
+        assert_eq!(config.prices, coins(1_000_000, "unoisx"));
+        assert_eq!(config.manager, Some(Addr::unchecked("manager")));
+        assert_eq!(config.access_mode, AccessMode::Allowlist {});
+        assert_eq!(config.rate_limits, rate_limits);
+
+        // No channel
+        assert!(GATEWAY_CHANNELS.is_empty(s));
+        assert_eq!(FIRST_CHANNEL_CONNECTED.may_load(s).unwrap(), None);
+    }
+
+    #[test]
+    fn migrate_v0_13_adds_gateway_channel() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+        CONFIG_V0_13
+            .save(s, &config_v0_13(Some("payment".to_string())))
+            .unwrap();
+        GATEWAY_CHANNEL.save(s, &"channel-1".to_string()).unwrap();
+
+        migrate_v0_13(s, AccessMode::Open {}, RateLimits::default()).unwrap();
+        let channel = GATEWAY_CHANNELS.load(s, "channel-1").unwrap();
+        assert!(channel.approved);
+        assert_eq!(channel.payment, Some("payment".to_string()));
+        assert!(FIRST_CHANNEL_CONNECTED.load(s).unwrap());
+    }
+}
@@ -1,8 +1,13 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Coin, Timestamp, Uint128};
+use cosmwasm_std::{Addr, Coin, Timestamp, Uint128};
+use cw20::Cw20ReceiveMsg;
 use nois::ProxyExecuteMsg;
 
-use crate::state::{Config, OperationalMode};
+use crate::state::{
+    AccessMode, AutoTopUp, BeaconCacheSettings, CallbackGasBounds, Config, CreditUsage,
+    CreditUsageKind, Delivery, DiscountTier, Discounts, GasTier, GatewayChannel, IbcDenom, Job,
+    OperationalMode, RateLimits, Stats, Subscription, TopUp,
+};
 
 #[cw_serde]
 pub struct InstantiateMsg {
@@ -17,6 +22,25 @@ pub struct InstantiateMsg {
     /// The amount of gas that the callback to the dapp can consume
     pub callback_gas_limit: u64,
     pub mode: OperationalMode,
+    /// Controls which addresses can request beacons. Defaults to `AccessMode::Open`.
+    pub access_mode: Option<AccessMode>,
+}
+
+#[cw_serde]
+pub struct MigrateMsg {
+    /// Sets `Config::access_mode`. When migrating from a version without this field,
+    /// this defaults to `AccessMode::Open`.
+    pub access_mode: Option<AccessMode>,
+    /// Sets `Config::rate_limits`. When migrating from a version without this field,
+    /// this defaults to no limits.
+    pub rate_limits: Option<RateLimits>,
+}
+
+/// The messages that can be embedded in a CW20 `Send` to pay with CW20 tokens
+#[cw_serde]
+pub enum ReceiveMsg {
+    GetNextRandomness { job_id: String },
+    GetRandomnessAfter { after: Timestamp, job_id: String },
 }
 
 #[cw_serde]
@@ -30,6 +54,15 @@ pub enum ExecuteMsg {
         after: Timestamp,
         job_id: String,
     },
+    /// Receives CW20 tokens as payment for the embedded [`ReceiveMsg`].
+    /// The price must be set with the denom `cw20:<token contract address>`.
+    Receive(Cw20ReceiveMsg),
+    /// Requests one beacon for multiple jobs. All jobs are sent in a single IBC packet and
+    /// paid at once, i.e. the funds must cover the price times the number of jobs.
+    GetRandomnessBatch {
+        after: Timestamp,
+        job_ids: Vec<String>,
+    },
     /// Set the config
     SetConfig {
         manager: Option<String>,
@@ -43,6 +76,125 @@ pub enum ExecuteMsg {
         /// The amount of tokens the proxy sends for each randomness request to the Nois chain
         nois_beacon_price: Option<Uint128>,
         mode: Option<OperationalMode>,
+        access_mode: Option<AccessMode>,
+        /// Limits for the number of beacon requests per block and per window
+        rate_limits: Option<RateLimits>,
+    },
+    /// Adds and removes addresses to/from the allowlist.
+    /// Removals are applied after additions.
+    UpdateAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Adds and removes addresses to/from the denylist.
+    /// Removals are applied after additions.
+    UpdateDenylist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Sets prices for a single dapp which replace `Config::prices`.
+    /// If `prices` is None, the override is removed.
+    SetPriceOverride {
+        address: String,
+        prices: Option<Vec<Coin>>,
+    },
+    /// Registers the callback gas limit of a dapp. This replaces `Config::callback_gas_limit`
+    /// for this dapp and must be within the callback gas bounds.
+    /// If `gas_limit` is None, the registration is removed.
+    SetCallbackGasLimit {
+        gas_limit: Option<u64>,
+        /// The dapp to set the limit for. Defaults to the message sender.
+        /// Only the manager can set limits for other dapps.
+        dapp: Option<String>,
+    },
+    /// Sets the range in which dapps can register their callback gas limit
+    SetCallbackGasBounds {
+        min: u64,
+        max: u64,
+        /// The gas tiers, sorted by `min_gas_limit` in ascending order
+        tiers: Vec<GasTier>,
+    },
+    /// Sets the volume discounts
+    SetDiscounts {
+        /// The length of a discount period in seconds
+        period: u64,
+        /// The discount tiers, sorted by `min_requests` in ascending order
+        tiers: Vec<DiscountTier>,
+    },
+    /// Enables the cache of delivered beacons. If `settings` is None, the cache is disabled.
+    SetBeaconCache {
+        settings: Option<BeaconCacheSettings>,
+    },
+    /// Makes the gateway channel the primary channel which is used for all outgoing packets.
+    /// This approves the channel.
+    SetPrimaryGatewayChannel {
+        channel_id: String,
+    },
+    /// Approves or revokes a backup gateway channel. Packets from channels that are not
+    /// approved are rejected.
+    ApproveGatewayChannel {
+        channel_id: String,
+        approved: bool,
+    },
+    /// Sets how the payment contract is topped up from the proxy's balance
+    SetTopUp {
+        unois_denom: IbcDenom,
+        /// If set, the payment contract is topped up automatically in `OperationalMode::Funded`
+        auto: Option<AutoTopUp>,
+    },
+    /// Sends IBCed unois from the proxy's balance to the payment contract
+    TopUpPayment {
+        amount: Uint128,
+    },
+    /// Subscribes to `count` beacons with `interval` seconds between them. The funds sent
+    /// along with this message pay for all of them. The proxy sends the requests itself and
+    /// calls back with the job IDs `<job_id_prefix>-<index>`.
+    Subscribe {
+        interval: u64,
+        count: u32,
+        job_id_prefix: String,
+    },
+    /// Cancels a subscription of the message sender and refunds the beacons not yet requested
+    Unsubscribe {
+        job_id_prefix: String,
+    },
+    /// Sends the next request of a subscription. This is done automatically when a
+    /// beacon is delivered but can be triggered by anyone in case a request failed.
+    /// If the owner is not allowed to request beacons anymore, the subscription is
+    /// cancelled and the remaining beacons are refunded.
+    ContinueSubscription {
+        owner: String,
+        job_id_prefix: String,
+    },
+    /// Deposits the funds sent along with this message as prepaid credit. Beacon requests
+    /// without funds are paid from this credit.
+    Deposit {
+        /// The address that can use the credit. Defaults to the message sender.
+        beneficiary: Option<String>,
+    },
+    /// Withdraws prepaid credit of the message sender
+    WithdrawCredit {
+        denom: String,
+        /// The amount of tokens to withdraw.
+        /// If None, withdraw all available credit of the given denom.
+        amount: Option<Uint128>,
+    },
+    /// Requests the current beacon price from the gateway. This can be called by anyone
+    /// but only once the stored price is older than `BEACON_PRICE_REFRESH_INTERVAL`.
+    RefreshBeaconPrice {},
+    /// Sends the unois owed to the gateway to the payment contract.
+    /// This can be called by anyone.
+    Settle {},
+    /// Executes a failed callback again. This can be done by the dapp itself or by
+    /// the manager on behalf of the dapp.
+    RetryCallback {
+        /// The request ID of the failed callback as returned by `QueryMsg::FailedCallbacks`
+        request_id: u64,
+        /// The amount of gas that the callback can consume
+        gas_limit: u64,
+        /// The dapp the callback is sent to. Defaults to the message sender.
+        /// Only the manager can retry callbacks of other dapps.
+        sender: Option<String>,
     },
     // Withdraw the given amount to the withdrawal address
     Withdraw {
@@ -102,6 +254,69 @@ pub enum SudoMsg {
         /// The amount of tokens the proxy sends for each randomness request to the Nois chain
         nois_beacon_price: Option<Uint128>,
         mode: Option<OperationalMode>,
+        access_mode: Option<AccessMode>,
+        /// Limits for the number of beacon requests per block and per window
+        rate_limits: Option<RateLimits>,
+    },
+    /// Sets prices for a single dapp which replace `Config::prices`.
+    /// If `prices` is None, the override is removed.
+    #[cfg(feature = "governance_owned")]
+    SetPriceOverride {
+        address: String,
+        prices: Option<Vec<Coin>>,
+    },
+    /// Sets the range in which dapps can register their callback gas limit
+    #[cfg(feature = "governance_owned")]
+    SetCallbackGasBounds {
+        min: u64,
+        max: u64,
+        /// The gas tiers, sorted by `min_gas_limit` in ascending order
+        tiers: Vec<GasTier>,
+    },
+    /// Sets the volume discounts
+    #[cfg(feature = "governance_owned")]
+    SetDiscounts {
+        /// The length of a discount period in seconds
+        period: u64,
+        /// The discount tiers, sorted by `min_requests` in ascending order
+        tiers: Vec<DiscountTier>,
+    },
+    /// Enables the cache of delivered beacons. If `settings` is None, the cache is disabled.
+    #[cfg(feature = "governance_owned")]
+    SetBeaconCache {
+        settings: Option<BeaconCacheSettings>,
+    },
+    /// Makes the gateway channel the primary channel which is used for all outgoing packets.
+    /// This approves the channel.
+    #[cfg(feature = "governance_owned")]
+    SetPrimaryGatewayChannel { channel_id: String },
+    /// Approves or revokes a backup gateway channel. Packets from channels that are not
+    /// approved are rejected.
+    #[cfg(feature = "governance_owned")]
+    ApproveGatewayChannel { channel_id: String, approved: bool },
+    /// Sets how the payment contract is topped up from the proxy's balance
+    #[cfg(feature = "governance_owned")]
+    SetTopUp {
+        unois_denom: IbcDenom,
+        /// If set, the payment contract is topped up automatically in `OperationalMode::Funded`
+        auto: Option<AutoTopUp>,
+    },
+    /// Sends IBCed unois from the proxy's balance to the payment contract
+    #[cfg(feature = "governance_owned")]
+    TopUpPayment { amount: Uint128 },
+    /// Adds and removes addresses to/from the allowlist.
+    /// Removals are applied after additions.
+    #[cfg(feature = "governance_owned")]
+    UpdateAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Adds and removes addresses to/from the denylist.
+    /// Removals are applied after additions.
+    #[cfg(feature = "governance_owned")]
+    UpdateDenylist {
+        add: Vec<String>,
+        remove: Vec<String>,
     },
 }
 
@@ -117,11 +332,107 @@ pub enum QueryMsg {
     /// Get the price in one particular denom. Returns None when denom is not accepted.
     #[returns(PriceResponse)]
     Price { denom: String },
-    /// Queries the local channel ID that is used to request beacons.
+    /// Queries the local ID of the primary channel that is used to request beacons.
     /// This channel is unset when the proxy is instantiated and will be set when
-    /// the channel is created. Once created, the value does not change anymore.
+    /// the first channel is created. It can be changed by the manager.
     #[returns(GatewayChannelResponse)]
     GatewayChannel {},
+    /// Gets all gateway channels including their health stats, sorted by channel ID
+    #[returns(GatewayChannelsResponse)]
+    GatewayChannels {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Gets the beacon price stored in the proxy and how old it is
+    #[returns(BeaconPriceResponse)]
+    BeaconPrice {},
+    /// Gets the settings of the beacon cache
+    #[returns(BeaconCacheResponse)]
+    BeaconCache {},
+    /// Gets the top-up settings of the payment contract
+    #[returns(TopUpResponse)]
+    TopUp {},
+    /// Gets the unois owed to the gateway
+    #[returns(DebtResponse)]
+    Debt {},
+    /// Gets a job by the request ID created by the proxy
+    #[returns(JobResponse)]
+    Job { request_id: u64 },
+    /// Gets all jobs of a dapp, sorted by request ID
+    #[returns(JobsResponse)]
+    JobsBySender {
+        sender: String,
+        /// The request ID after which to start
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Get the prices the given address has to pay for its next request,
+    /// including price overrides and volume discounts.
+    #[returns(PricesResponse)]
+    PriceFor { address: String },
+    /// Gets all price overrides, sorted by address
+    #[returns(PriceOverridesResponse)]
+    PriceOverrides {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Gets the usage statistics of all dapps, sorted by address
+    #[returns(DappStatsResponse)]
+    DappStats {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Gets the usage statistics summed up over all dapps
+    #[returns(GlobalStatsResponse)]
+    GlobalStats {},
+    /// Gets the volume discounts
+    #[returns(DiscountsResponse)]
+    Discounts {},
+    /// Gets the gas limit used for callbacks to the given dapp
+    #[returns(CallbackGasLimitResponse)]
+    CallbackGasLimit { address: String },
+    /// Gets the range in which dapps can register their callback gas limit
+    #[returns(CallbackGasBoundsResponse)]
+    CallbackGasBounds {},
+    /// Gets the addresses on the allowlist, sorted by address
+    #[returns(AddressListResponse)]
+    Allowlist {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Gets the addresses on the denylist, sorted by address
+    #[returns(AddressListResponse)]
+    Denylist {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Gets the subscriptions of a dapp, sorted by job ID prefix
+    #[returns(SubscriptionsResponse)]
+    Subscriptions {
+        owner: String,
+        /// The job ID prefix after which to start
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Gets the prepaid credit of an address
+    #[returns(CreditResponse)]
+    Credit { address: String },
+    /// Gets the changes of the prepaid credit of an address, sorted from old to new
+    #[returns(CreditHistoryResponse)]
+    CreditHistory {
+        address: String,
+        /// The index after which to start
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Gets the deliveries of a dapp for which the callback failed, sorted by job ID
+    #[returns(FailedCallbacksResponse)]
+    FailedCallbacks {
+        sender: String,
+        /// The request ID after which to start
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
 }
 
 #[cw_serde]
@@ -146,6 +457,202 @@ pub struct GatewayChannelResponse {
     pub channel: Option<String>,
 }
 
+#[cw_serde]
+pub struct QueriedGatewayChannel {
+    pub channel_id: String,
+    pub primary: bool,
+    pub channel: GatewayChannel,
+}
+
+#[cw_serde]
+pub struct GatewayChannelsResponse {
+    pub channels: Vec<QueriedGatewayChannel>,
+}
+
+#[cw_serde]
+pub struct QueriedDappStats {
+    pub address: String,
+    pub stats: Stats,
+}
+
+#[cw_serde]
+pub struct DappStatsResponse {
+    pub stats: Vec<QueriedDappStats>,
+}
+
+#[cw_serde]
+pub struct GlobalStatsResponse {
+    pub stats: Stats,
+}
+
+#[cw_serde]
+pub struct QueriedSubscription {
+    pub job_id_prefix: String,
+    pub subscription: Subscription,
+}
+
+#[cw_serde]
+pub struct SubscriptionsResponse {
+    pub subscriptions: Vec<QueriedSubscription>,
+}
+
+#[cw_serde]
+pub struct BeaconCacheResponse {
+    /// None if the cache is disabled
+    pub settings: Option<BeaconCacheSettings>,
+}
+
+#[cw_serde]
+pub struct TopUpResponse {
+    pub top_up: Option<TopUp>,
+    /// The number of beacon requests since the last automatic top-up
+    pub requests_since_top_up: u32,
+}
+
+#[cw_serde]
+pub struct DebtResponse {
+    /// The amount of unois not yet sent to the payment contract
+    pub debt: Uint128,
+    /// The time of the last settlement. None if there was no debt yet.
+    pub last_settled: Option<Timestamp>,
+}
+
+#[cw_serde]
+pub struct BeaconPriceResponse {
+    /// The amount of unois the proxy sends for each randomness request to the Nois chain
+    pub price: Uint128,
+    /// The time of the price info. None if the proxy never received a price.
+    pub updated: Option<Timestamp>,
+    /// The age of the price info in seconds. None if the proxy never received a price.
+    pub age: Option<u64>,
+    /// The earliest time at which `ExecuteMsg::RefreshBeaconPrice` can be executed
+    pub refreshable_after: Timestamp,
+}
+
+#[cw_serde]
+pub struct QueriedJob {
+    /// The ID of the request created by the proxy
+    pub request_id: u64,
+    /// The address of the dapp that requested the beacon
+    pub sender: Addr,
+    /// The job ID chosen by the dapp
+    pub job_id: String,
+    /// Beacon publish time must be > `after`
+    pub after: Timestamp,
+    /// The funds the dapp paid for this request
+    pub funds: Vec<Coin>,
+    /// True if the request was paid from the prepaid credit of the dapp
+    pub paid_with_credit: bool,
+    /// The randomness source identifier the gateway committed to
+    pub source_id: Option<String>,
+    /// The block height at which the request was made
+    pub request_height: u64,
+    /// The block height at which the beacon was delivered to the proxy
+    pub delivery_height: Option<u64>,
+    /// Whether or not the callback to the dapp succeeded
+    pub callback_success: Option<bool>,
+    /// The reason why the beacon will never be delivered
+    pub error: Option<String>,
+}
+
+impl QueriedJob {
+    pub fn new(request_id: u64, job: Job) -> Self {
+        Self {
+            request_id,
+            sender: job.sender,
+            job_id: job.job_id,
+            after: job.after,
+            funds: job.funds,
+            paid_with_credit: job.paid_with_credit,
+            source_id: job.source_id,
+            request_height: job.request_height,
+            delivery_height: job.delivery_height,
+            callback_success: job.callback_success,
+            error: job.error,
+        }
+    }
+}
+
+#[cw_serde]
+pub struct JobResponse {
+    /// The job when found. None/null otherwise.
+    pub job: Option<QueriedJob>,
+}
+
+#[cw_serde]
+pub struct JobsResponse {
+    pub jobs: Vec<QueriedJob>,
+}
+
+#[cw_serde]
+pub struct QueriedPriceOverride {
+    pub address: String,
+    pub prices: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct PriceOverridesResponse {
+    pub overrides: Vec<QueriedPriceOverride>,
+}
+
+#[cw_serde]
+pub struct DiscountsResponse {
+    pub discounts: Option<Discounts>,
+}
+
+#[cw_serde]
+pub struct CallbackGasLimitResponse {
+    pub gas_limit: u64,
+}
+
+#[cw_serde]
+pub struct CallbackGasBoundsResponse {
+    pub bounds: Option<CallbackGasBounds>,
+}
+
+#[cw_serde]
+pub struct AddressListResponse {
+    pub addresses: Vec<String>,
+}
+
+#[cw_serde]
+pub struct CreditResponse {
+    /// The credit in all denoms
+    pub credit: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct QueriedCreditUsage {
+    /// The position in the credit history of the address
+    pub index: u64,
+    /// The block time of the change
+    pub time: Timestamp,
+    /// The amount added or removed
+    pub amount: Coin,
+    pub kind: CreditUsageKind,
+}
+
+impl QueriedCreditUsage {
+    pub fn new(index: u64, usage: CreditUsage) -> Self {
+        Self {
+            index,
+            time: usage.time,
+            amount: usage.amount,
+            kind: usage.kind,
+        }
+    }
+}
+
+#[cw_serde]
+pub struct CreditHistoryResponse {
+    pub entries: Vec<QueriedCreditUsage>,
+}
+
+#[cw_serde]
+pub struct FailedCallbacksResponse {
+    pub deliveries: Vec<Delivery>,
+}
+
 /// This struct contains information about the origin of the beacon request. It helps the
 /// proxy to route the beacon response to the final destination.
 /// The IBC communication between proxy and gateway does not need this information. It is
@@ -156,4 +663,46 @@ pub struct RequestBeaconOrigin {
     /// to send the callback.
     pub sender: String,
     pub job_id: String,
+    /// The ID of the request created by the proxy. This is unset for requests
+    /// created before the proxy started tracking jobs.
+    pub request_id: Option<u64>,
+}
+
+/// The error callback the proxy sends to the dapp when the gateway rejects a beacon request.
+/// In this case the randomness for the job will never be delivered and the payment
+/// is refunded.
+#[cw_serde]
+pub struct NoisErrorCallback {
+    /// The ID chosen by the caller for this job
+    pub job_id: String,
+    /// The error message returned by the gateway
+    pub error: String,
+    /// The funds that are refunded to the dapp along with this callback. Requests paid
+    /// from credit are refunded to the credit.
+    pub refund: Vec<Coin>,
+}
+
+/// The callback the proxy sends to the dapp when the beacon request packet timed out.
+/// In this case the randomness for the job will never be delivered and the payment
+/// is refunded.
+#[cw_serde]
+pub struct NoisTimeoutCallback {
+    /// The ID chosen by the caller for this job
+    pub job_id: String,
+    /// The funds that are refunded to the dapp along with this callback
+    pub refund: Vec<Coin>,
+}
+
+/// This is just a helper to properly serialize the above error callbacks.
+/// Dapps that want to handle failed requests should include those variants in
+/// their ExecuteMsg enum. Dapps that do not handle them are not affected since a
+/// failing error callback is ignored by the proxy.
+#[cw_serde]
+pub enum ErrorReceiverExecuteMsg {
+    /// This is sent as `{"nois_receive_error": {"callback": {"job_id": "...", "error": "...", "refund": [...]}}}`
+    /// to the contract.
+    NoisReceiveError { callback: NoisErrorCallback },
+    /// This is sent as `{"nois_receive_timeout": {"callback": {"job_id": "...", "refund": [...]}}}`
+    /// to the contract.
+    NoisReceiveTimeout { callback: NoisTimeoutCallback },
 }
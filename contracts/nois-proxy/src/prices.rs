@@ -0,0 +1,220 @@
+use cosmwasm_std::{Addr, Coin, StdResult, Storage, Timestamp};
+
+use crate::error::ContractError;
+use crate::gas_limits::gas_price_increase_percent;
+use crate::state::{
+    DiscountTier, Discounts, PeriodUsage, DISCOUNTS, PERIOD_USAGE, PRICE_OVERRIDES,
+};
+
+/// Checks that the discount tiers are sorted and within the 0-100 % range
+pub fn validate_discounts(discounts: &Discounts) -> Result<(), ContractError> {
+    if !discounts.tiers.is_empty() && discounts.period == 0 {
+        return Err(ContractError::InvalidDiscounts);
+    }
+    let mut previous: Option<&DiscountTier> = None;
+    for tier in &discounts.tiers {
+        if tier.discount_percent > 100 {
+            return Err(ContractError::InvalidDiscounts);
+        }
+        if let Some(previous) = previous {
+            if tier.min_requests <= previous.min_requests {
+                return Err(ContractError::InvalidDiscounts);
+            }
+        }
+        previous = Some(tier);
+    }
+    Ok(())
+}
+
+/// The number of requests the address sent in the current discount period
+fn requests_in_period(
+    storage: &dyn Storage,
+    address: &Addr,
+    period: u64,
+    now: Timestamp,
+) -> StdResult<u32> {
+    if period == 0 {
+        return Ok(0);
+    }
+    let usage = PERIOD_USAGE.may_load(storage, address)?;
+    Ok(match usage {
+        Some(usage) if usage.period == now.seconds() / period => usage.requests,
+        _ => 0,
+    })
+}
+
+/// Calculates the prices the given address has to pay for its next request.
+///
+/// This is the price override of the address if set or the global prices otherwise.
+/// The discount of the highest reached tier is applied to all of them. Afterwards
+/// the price increase of the dapp's gas tier is added.
+pub fn effective_prices(
+    storage: &dyn Storage,
+    prices: &[Coin],
+    address: &Addr,
+    now: Timestamp,
+) -> StdResult<Vec<Coin>> {
+    let prices = match PRICE_OVERRIDES.may_load(storage, address)? {
+        Some(prices) => prices,
+        None => prices.to_vec(),
+    };
+
+    let discount_percent = match DISCOUNTS.may_load(storage)? {
+        Some(discounts) => {
+            let requests = requests_in_period(storage, address, discounts.period, now)?;
+            discounts
+                .tiers
+                .iter()
+                .rev()
+                .find(|tier| requests >= tier.min_requests)
+                .map(|tier| tier.discount_percent)
+                .unwrap_or_default()
+        }
+        None => 0,
+    };
+    let increase_percent = gas_price_increase_percent(storage, address)?;
+
+    Ok(prices
+        .into_iter()
+        .map(|price| Coin {
+            amount: price
+                .amount
+                .multiply_ratio(100 - discount_percent as u128, 100u128)
+                .multiply_ratio(100 + increase_percent as u128, 100u128),
+            denom: price.denom,
+        })
+        .collect())
+}
+
+/// Counts `count` requests of the given address in the current discount period
+pub fn record_requests(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    now: Timestamp,
+    count: u32,
+) -> StdResult<()> {
+    let Some(discounts) = DISCOUNTS.may_load(storage)? else {
+        return Ok(());
+    };
+    if discounts.period == 0 {
+        return Ok(());
+    }
+    let requests = requests_in_period(storage, address, discounts.period, now)?;
+    PERIOD_USAGE.save(
+        storage,
+        address,
+        &PeriodUsage {
+            period: now.seconds() / discounts.period,
+            requests: requests + count,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::{coin, coins, testing::mock_dependencies};
+
+    #[test]
+    fn validate_discounts_works() {
+        let tier = |min_requests, discount_percent| DiscountTier {
+            min_requests,
+            discount_percent,
+        };
+        validate_discounts(&Discounts {
+            period: 0,
+            tiers: vec![],
+        })
+        .unwrap();
+        validate_discounts(&Discounts {
+            period: 3600,
+            tiers: vec![tier(10, 10), tier(100, 100)],
+        })
+        .unwrap();
+
+        // Period must be set
+        let err = validate_discounts(&Discounts {
+            period: 0,
+            tiers: vec![tier(10, 10)],
+        })
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidDiscounts));
+        // Discount too large
+        let err = validate_discounts(&Discounts {
+            period: 3600,
+            tiers: vec![tier(10, 101)],
+        })
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidDiscounts));
+        // Unsorted
+        let err = validate_discounts(&Discounts {
+            period: 3600,
+            tiers: vec![tier(10, 10), tier(10, 20)],
+        })
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidDiscounts));
+    }
+
+    #[test]
+    fn effective_prices_works() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+        let dapp = Addr::unchecked("dapp");
+        let partner = Addr::unchecked("partner");
+        let prices = vec![coin(100, "bucks"), coin(20, "sand")];
+        let now = Timestamp::from_seconds(7200);
+
+        // No overrides or discounts
+        assert_eq!(effective_prices(s, &prices, &dapp, now).unwrap(), prices);
+
+        // Override
+        PRICE_OVERRIDES
+            .save(s, &partner, &coins(50, "bucks"))
+            .unwrap();
+        assert_eq!(effective_prices(s, &prices, &dapp, now).unwrap(), prices);
+        assert_eq!(
+            effective_prices(s, &prices, &partner, now).unwrap(),
+            coins(50, "bucks")
+        );
+
+        // Discounts
+        DISCOUNTS
+            .save(
+                s,
+                &Discounts {
+                    period: 3600,
+                    tiers: vec![
+                        DiscountTier {
+                            min_requests: 2,
+                            discount_percent: 10,
+                        },
+                        DiscountTier {
+                            min_requests: 3,
+                            discount_percent: 50,
+                        },
+                    ],
+                },
+            )
+            .unwrap();
+        assert_eq!(effective_prices(s, &prices, &dapp, now).unwrap(), prices);
+        record_requests(s, &dapp, now, 2).unwrap();
+        assert_eq!(
+            effective_prices(s, &prices, &dapp, now).unwrap(),
+            vec![coin(90, "bucks"), coin(18, "sand")]
+        );
+        record_requests(s, &dapp, now, 1).unwrap();
+        assert_eq!(
+            effective_prices(s, &prices, &dapp, now).unwrap(),
+            vec![coin(50, "bucks"), coin(10, "sand")]
+        );
+        // Other dapps are not affected
+        assert_eq!(
+            effective_prices(s, &prices, &partner, now).unwrap(),
+            coins(50, "bucks")
+        );
+
+        // Resets in the next period
+        let later = now.plus_seconds(3600);
+        assert_eq!(effective_prices(s, &prices, &dapp, later).unwrap(), prices);
+    }
+}
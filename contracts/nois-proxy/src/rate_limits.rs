@@ -0,0 +1,205 @@
+use cosmwasm_std::{Addr, BlockInfo, Storage};
+
+use crate::error::ContractError;
+use crate::state::{RateLimits, RequestCounter, GLOBAL_REQUEST_COUNTER, SENDER_REQUEST_COUNTERS};
+
+/// Checks that a window length is set when window limits are used
+pub fn validate_rate_limits(limits: &RateLimits) -> Result<(), ContractError> {
+    let uses_window = limits.sender_per_window.is_some() || limits.global_per_window.is_some();
+    if uses_window && limits.window == 0 {
+        return Err(ContractError::InvalidRateLimits);
+    }
+    Ok(())
+}
+
+/// Moves the counter to the given block and window
+fn advance(counter: &mut RequestCounter, block: &BlockInfo, window: u64) {
+    if counter.height != block.height {
+        counter.height = block.height;
+        counter.block_requests = 0;
+    }
+    if let Some(current) = block.time.seconds().checked_div(window) {
+        if current == counter.window + 1 {
+            counter.previous_window_requests = counter.window_requests;
+            counter.window_requests = 0;
+        } else if current != counter.window {
+            counter.previous_window_requests = 0;
+            counter.window_requests = 0;
+        }
+        counter.window = current;
+    }
+}
+
+/// The number of requests in the rolling window ending now.
+///
+/// The requests of the previous window are weighted by the share of the rolling
+/// window that overlaps with it.
+fn requests_in_window(counter: &RequestCounter, block: &BlockInfo, window: u64) -> u64 {
+    let elapsed = block.time.seconds() % window;
+    let previous = counter.previous_window_requests as u64 * (window - elapsed) / window;
+    previous + counter.window_requests as u64
+}
+
+/// Adds `count` requests to the counter or errors if this exceeds one of the limits
+fn count(
+    counter: &mut RequestCounter,
+    block: &BlockInfo,
+    window: u64,
+    per_block: Option<u32>,
+    per_window: Option<u32>,
+    count: u32,
+) -> Result<(), ContractError> {
+    advance(counter, block, window);
+    if let Some(limit) = per_block {
+        if counter.block_requests as u64 + count as u64 > limit as u64 {
+            return Err(ContractError::RateLimitExceeded { limit, window: 0 });
+        }
+    }
+    if let Some(limit) = per_window {
+        if requests_in_window(counter, block, window) + count as u64 > limit as u64 {
+            return Err(ContractError::RateLimitExceeded { limit, window });
+        }
+    }
+    counter.block_requests += count;
+    counter.window_requests += count;
+    Ok(())
+}
+
+/// Counts `count` requests of the sender and errors if this exceeds the sender's
+/// or the global rate limits
+pub fn check_rate_limits(
+    storage: &mut dyn Storage,
+    limits: &RateLimits,
+    sender: &Addr,
+    block: &BlockInfo,
+    requests: u32,
+) -> Result<(), ContractError> {
+    if *limits == RateLimits::default() {
+        return Ok(());
+    }
+
+    let mut counter = SENDER_REQUEST_COUNTERS
+        .may_load(storage, sender)?
+        .unwrap_or_default();
+    count(
+        &mut counter,
+        block,
+        limits.window,
+        limits.sender_per_block,
+        limits.sender_per_window,
+        requests,
+    )?;
+    SENDER_REQUEST_COUNTERS.save(storage, sender, &counter)?;
+
+    let mut counter = GLOBAL_REQUEST_COUNTER
+        .may_load(storage)?
+        .unwrap_or_default();
+    count(
+        &mut counter,
+        block,
+        limits.window,
+        limits.global_per_block,
+        limits.global_per_window,
+        requests,
+    )?;
+    GLOBAL_REQUEST_COUNTER.save(storage, &counter)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env};
+
+    #[test]
+    fn validate_rate_limits_works() {
+        validate_rate_limits(&RateLimits::default()).unwrap();
+        validate_rate_limits(&RateLimits {
+            sender_per_block: Some(3),
+            ..RateLimits::default()
+        })
+        .unwrap();
+
+        let err = validate_rate_limits(&RateLimits {
+            global_per_window: Some(3),
+            ..RateLimits::default()
+        })
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidRateLimits));
+    }
+
+    #[test]
+    fn check_rate_limits_works_per_block() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+        let limits = RateLimits {
+            sender_per_block: Some(2),
+            global_per_block: Some(3),
+            ..RateLimits::default()
+        };
+        let mut block = mock_env().block;
+
+        check_rate_limits(s, &limits, &alice, &block, 2).unwrap();
+        let err = check_rate_limits(s, &limits, &alice, &block, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RateLimitExceeded {
+                limit: 2,
+                window: 0
+            }
+        ));
+        check_rate_limits(s, &limits, &bob, &block, 1).unwrap();
+        let err = check_rate_limits(s, &limits, &bob, &block, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RateLimitExceeded {
+                limit: 3,
+                window: 0
+            }
+        ));
+
+        // Reset in the next block
+        block.height += 1;
+        check_rate_limits(s, &limits, &alice, &block, 2).unwrap();
+    }
+
+    #[test]
+    fn check_rate_limits_works_per_window() {
+        let mut deps = mock_dependencies();
+        let s = deps.as_mut().storage;
+        let alice = Addr::unchecked("alice");
+        let limits = RateLimits {
+            window: 100,
+            sender_per_window: Some(10),
+            ..RateLimits::default()
+        };
+        let mut block = mock_env().block;
+        block.time = block.time.minus_nanos(block.time.nanos() % 100_000_000_000);
+
+        check_rate_limits(s, &limits, &alice, &block, 10).unwrap();
+        block.height += 1;
+        block.time = block.time.plus_seconds(50);
+        let err = check_rate_limits(s, &limits, &alice, &block, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::RateLimitExceeded {
+                limit: 10,
+                window: 100
+            }
+        ));
+
+        // Half of the previous window overlaps with the rolling window
+        block.height += 1;
+        block.time = block.time.plus_seconds(100);
+        check_rate_limits(s, &limits, &alice, &block, 5).unwrap();
+        let err = check_rate_limits(s, &limits, &alice, &block, 1).unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceeded { .. }));
+
+        // The previous window is over
+        block.height += 1;
+        block.time = block.time.plus_seconds(200);
+        check_rate_limits(s, &limits, &alice, &block, 10).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+use cosmwasm_std::{StdResult, Storage, Timestamp, Uint128};
+
+use crate::state::{Settlement, GATEWAY_PREPAID, SETTLEMENT};
+
+/// Loads the settlement state. Before the first debt is accrued, the last
+/// settlement is considered to be `now`.
+pub fn load_settlement(storage: &dyn Storage, now: Timestamp) -> StdResult<Settlement> {
+    Ok(SETTLEMENT.may_load(storage)?.unwrap_or(Settlement {
+        debt: Uint128::zero(),
+        last_settled: now,
+    }))
+}
+
+/// Adds the amount to the debt and returns the updated settlement state
+pub fn accrue_debt(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    amount: Uint128,
+) -> StdResult<Settlement> {
+    let mut settlement = load_settlement(storage, now)?;
+    settlement.debt += amount;
+    SETTLEMENT.save(storage, &settlement)?;
+    Ok(settlement)
+}
+
+/// Returns true if the debt must be settled now
+pub fn is_settlement_due(
+    settlement: &Settlement,
+    now: Timestamp,
+    threshold: Uint128,
+    interval: u64,
+) -> bool {
+    !settlement.debt.is_zero()
+        && (settlement.debt >= threshold || now >= settlement.last_settled.plus_seconds(interval))
+}
+
+/// Resets the debt to zero and returns the amount that has to be sent
+pub fn take_debt(storage: &mut dyn Storage, now: Timestamp) -> StdResult<Uint128> {
+    let debt = load_settlement(storage, now)?.debt;
+    SETTLEMENT.save(
+        storage,
+        &Settlement {
+            debt: Uint128::zero(),
+            last_settled: now,
+        },
+    )?;
+    Ok(debt)
+}
+
+/// Pays as much as possible of the amount from the unois prepaid at the gateway.
+/// Returns the remaining amount that has to be paid.
+pub fn use_gateway_prepaid(storage: &mut dyn Storage, amount: Uint128) -> StdResult<Uint128> {
+    let prepaid = GATEWAY_PREPAID.may_load(storage)?.unwrap_or_default();
+    let used = prepaid.min(amount);
+    GATEWAY_PREPAID.save(storage, &(prepaid - used))?;
+    Ok(amount - used)
+}
+
+/// Drops the prepaid amount and returns it. This is used when the gateway shows that
+/// the prepaid amount did not arrive at the payment contract.
+pub fn clear_gateway_prepaid(storage: &mut dyn Storage) -> StdResult<Uint128> {
+    let prepaid = GATEWAY_PREPAID.may_load(storage)?.unwrap_or_default();
+    GATEWAY_PREPAID.remove(storage);
+    Ok(prepaid)
+}
+
+/// Stores unois which was sent to the gateway for requests that it rejected
+fn add_gateway_prepaid(storage: &mut dyn Storage, amount: Uint128) -> StdResult<()> {
+    let prepaid = GATEWAY_PREPAID.may_load(storage)?.unwrap_or_default();
+    GATEWAY_PREPAID.save(storage, &(prepaid + amount))
+}
+
+/// Reduces the unsettled debt by the gateway payment of requests the gateway did not
+/// charge for. Returns the reduction, which is at most the current debt.
+pub fn reverse_gateway_debt(storage: &mut dyn Storage, amount: Uint128) -> StdResult<Uint128> {
+    let Some(mut settlement) = SETTLEMENT.may_load(storage)? else {
+        return Ok(Uint128::zero());
+    };
+    let reversed = settlement.debt.min(amount);
+    settlement.debt -= reversed;
+    SETTLEMENT.save(storage, &settlement)?;
+    Ok(reversed)
+}
+
+/// Takes back the gateway payment of requests that the gateway rejected.
+/// Unsettled debt is reduced first. The rest was already sent and becomes prepaid.
+/// Returns the amount that became prepaid.
+pub fn return_gateway_payment(storage: &mut dyn Storage, amount: Uint128) -> StdResult<Uint128> {
+    let prepaid = amount - reverse_gateway_debt(storage, amount)?;
+    add_gateway_prepaid(storage, prepaid)?;
+    Ok(prepaid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+
+    #[test]
+    fn settlement_works() {
+        let mut storage = MockStorage::new();
+        let start = Timestamp::from_seconds(1000);
+
+        let settlement = load_settlement(&storage, start).unwrap();
+        assert_eq!(settlement.debt, Uint128::zero());
+        assert!(!is_settlement_due(
+            &settlement,
+            start.plus_seconds(5000),
+            Uint128::new(100),
+            60
+        ));
+
+        let settlement = accrue_debt(&mut storage, start, Uint128::new(40)).unwrap();
+        assert_eq!(settlement.last_settled, start);
+        assert!(!is_settlement_due(
+            &settlement,
+            start,
+            Uint128::new(100),
+            60
+        ));
+        // Interval passed
+        assert!(is_settlement_due(
+            &settlement,
+            start.plus_seconds(60),
+            Uint128::new(100),
+            60
+        ));
+
+        // Threshold reached
+        let later = start.plus_seconds(10);
+        let settlement = accrue_debt(&mut storage, later, Uint128::new(60)).unwrap();
+        assert_eq!(settlement.debt, Uint128::new(100));
+        assert_eq!(settlement.last_settled, start);
+        assert!(is_settlement_due(&settlement, later, Uint128::new(100), 60));
+
+        assert_eq!(take_debt(&mut storage, later).unwrap(), Uint128::new(100));
+        let settlement = load_settlement(&storage, start).unwrap();
+        assert_eq!(settlement.debt, Uint128::zero());
+        assert_eq!(settlement.last_settled, later);
+    }
+
+    #[test]
+    fn gateway_prepaid_works() {
+        let mut storage = MockStorage::new();
+
+        // Nothing prepaid
+        let remaining = use_gateway_prepaid(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(remaining, Uint128::new(50));
+
+        add_gateway_prepaid(&mut storage, Uint128::new(30)).unwrap();
+        add_gateway_prepaid(&mut storage, Uint128::new(40)).unwrap();
+        let remaining = use_gateway_prepaid(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(remaining, Uint128::zero());
+        let remaining = use_gateway_prepaid(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(remaining, Uint128::new(30));
+        let remaining = use_gateway_prepaid(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(remaining, Uint128::new(50));
+    }
+
+    #[test]
+    fn reverse_gateway_debt_works() {
+        let mut storage = MockStorage::new();
+        let now = Timestamp::from_seconds(1000);
+
+        // No settlement yet
+        let reversed = reverse_gateway_debt(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(reversed, Uint128::zero());
+
+        accrue_debt(&mut storage, now, Uint128::new(30)).unwrap();
+        let reversed = reverse_gateway_debt(&mut storage, Uint128::new(20)).unwrap();
+        assert_eq!(reversed, Uint128::new(20));
+        let reversed = reverse_gateway_debt(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(reversed, Uint128::new(10));
+        assert_eq!(
+            load_settlement(&storage, now).unwrap().debt,
+            Uint128::zero()
+        );
+
+        // Nothing becomes prepaid
+        let remaining = use_gateway_prepaid(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(remaining, Uint128::new(50));
+    }
+
+    #[test]
+    fn return_gateway_payment_works() {
+        let mut storage = MockStorage::new();
+        let now = Timestamp::from_seconds(1000);
+
+        // Without settlement everything was sent
+        let prepaid = return_gateway_payment(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(prepaid, Uint128::new(50));
+        use_gateway_prepaid(&mut storage, Uint128::new(50)).unwrap();
+
+        // Unsettled debt is reduced first
+        accrue_debt(&mut storage, now, Uint128::new(30)).unwrap();
+        let prepaid = return_gateway_payment(&mut storage, Uint128::new(20)).unwrap();
+        assert_eq!(prepaid, Uint128::zero());
+        assert_eq!(
+            load_settlement(&storage, now).unwrap().debt,
+            Uint128::new(10)
+        );
+        let prepaid = return_gateway_payment(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(prepaid, Uint128::new(40));
+        assert_eq!(
+            load_settlement(&storage, now).unwrap().debt,
+            Uint128::zero()
+        );
+        let remaining = use_gateway_prepaid(&mut storage, Uint128::new(50)).unwrap();
+        assert_eq!(remaining, Uint128::new(10));
+    }
+}
@@ -1,6 +1,6 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Coin, Timestamp, Uint128};
-use cw_storage_plus::Item;
+use cosmwasm_std::{Addr, Coin, Empty, HexBinary, StdResult, Storage, Timestamp, Uint128};
+use cw_storage_plus::{Deque, Item, Map};
 
 /// The denom information required to send a MsgTransfer.
 /// Ideally we could just query the ICS-20 channel ID and did not have to store it,
@@ -26,6 +26,43 @@ pub enum OperationalMode {
         /// The denom of the IBCed unois token
         unois_denom: IbcDenom,
     },
+    /// Proxy contract accrues the IBCed NOIS it owes the gateway and sends it in a single
+    /// transfer once `threshold` is reached or `interval` passed since the last settlement.
+    IbcPayBatched {
+        /// The denom of the IBCed unois token
+        unois_denom: IbcDenom,
+        /// The amount of owed unois at which the debt is settled
+        threshold: Uint128,
+        /// The time in seconds after the last settlement at which the debt is settled
+        interval: u64,
+    },
+}
+
+/// Controls which addresses can request beacons from the proxy
+#[cw_serde]
+pub enum AccessMode {
+    /// Every address can request beacons
+    Open {},
+    /// Only addresses on the allowlist can request beacons
+    Allowlist {},
+    /// All addresses except the ones on the denylist can request beacons
+    Denylist {},
+}
+
+/// Limits for the number of beacon requests. Limits that are not set are not enforced.
+#[cw_serde]
+#[derive(Default)]
+pub struct RateLimits {
+    /// Max number of requests of a single sender in one block
+    pub sender_per_block: Option<u32>,
+    /// Max number of requests of all senders in one block
+    pub global_per_block: Option<u32>,
+    /// The length of the rolling window in seconds
+    pub window: u64,
+    /// Max number of requests of a single sender in the rolling window
+    pub sender_per_window: Option<u32>,
+    /// Max number of requests of all senders in the rolling window
+    pub global_per_window: Option<u32>,
 }
 
 #[cw_serde]
@@ -47,13 +84,364 @@ pub struct Config {
     /// The time (on the Nois chain) the price info was created
     pub nois_beacon_price_updated: Timestamp,
     pub mode: OperationalMode,
+    /// Controls which addresses can request beacons
+    pub access_mode: AccessMode,
+    pub rate_limits: RateLimits,
 }
 
 pub const CONFIG: Item<Config> = Item::new("config");
 
-/// Channel to the nois-gateway contract on the Nois chain
+/// The primary channel to the nois-gateway contract on the Nois chain.
+/// All outgoing packets are sent through this channel.
 pub const GATEWAY_CHANNEL: Item<String> = Item::new("gateway_channel");
 
+/// A channel to a nois-gateway and its health stats
+#[cw_serde]
+#[derive(Default)]
+pub struct GatewayChannel {
+    /// Packets are only accepted from approved channels. The primary channel is always approved.
+    pub approved: bool,
+    /// Address of the payment contract the gateway created for this channel
+    pub payment: Option<String>,
+    /// The number of success acknowledgements received
+    pub acks: u64,
+    /// The number of error acknowledgements received
+    pub error_acks: u64,
+    /// The number of packets that timed out
+    pub timeouts: u64,
+}
+
+/// All open channels to nois-gateways, including the primary one
+pub const GATEWAY_CHANNELS: Map<&str, GatewayChannel> = Map::new("gateway_channels");
+
+/// Set once the first channel was connected. Only this channel is approved automatically.
+pub const FIRST_CHANNEL_CONNECTED: Item<bool> = Item::new("first_channel_connected");
+
+/// A beacon request of a dapp
+#[cw_serde]
+pub struct Job {
+    /// The address of the dapp that requested the beacon
+    pub sender: Addr,
+    /// The job ID chosen by the dapp
+    pub job_id: String,
+    /// Beacon publish time must be > `after`
+    pub after: Timestamp,
+    /// The funds the dapp paid for this request. They are refunded when the
+    /// request packet times out.
+    pub funds: Vec<Coin>,
+    /// True if the request was paid from the prepaid credit of the dapp
+    pub paid_with_credit: bool,
+    /// The randomness source identifier the gateway committed to. This is set once
+    /// the gateway acknowledged the request.
+    pub source_id: Option<String>,
+    /// The block height at which the request was made
+    pub request_height: u64,
+    /// The block height at which the beacon was delivered to the proxy
+    pub delivery_height: Option<u64>,
+    /// Whether or not the callback to the dapp succeeded. Unset as long as no callback
+    /// was sent.
+    pub callback_success: Option<bool>,
+    /// The reason why the beacon will never be delivered, e.g. an error returned by the
+    /// gateway or a packet timeout.
+    pub error: Option<String>,
+    /// The unois paid to the gateway's payment contract for this job, either by a transfer
+    /// or from the prepaid amount. Unset for jobs which did not pay the gateway.
+    pub gateway_payment: Option<Uint128>,
+    /// The part of `gateway_payment` that was newly sent or accrued as debt for this job,
+    /// i.e. not paid from the prepaid amount
+    pub gateway_transfer: Option<Uint128>,
+    /// The callback gas limit resolved when the request was made. The price of the
+    /// request was based on this limit. Unset for jobs created before it was stored.
+    pub callback_gas_limit: Option<u64>,
+}
+
+/// The ID of the next job to be created
+const NEXT_REQUEST_ID: Item<u64> = Item::new("next_request_id");
+
+/// A map from request ID to job. The request ID is an ID created by the proxy. It is
+/// unique in contrast to the job ID chosen by the dapp.
+pub const JOBS: Map<u64, Job> = Map::new("jobs");
+
+/// An index of all jobs by (sender, request ID)
+pub const JOBS_BY_SENDER: Map<(&Addr, u64), Empty> = Map::new("jobs_by_sender");
+
+/// Stores a new job and returns its request ID
+pub fn create_job(storage: &mut dyn Storage, job: &Job) -> StdResult<u64> {
+    let request_id = NEXT_REQUEST_ID.may_load(storage)?.unwrap_or(1);
+    NEXT_REQUEST_ID.save(storage, &(request_id + 1))?;
+    JOBS.save(storage, request_id, job)?;
+    JOBS_BY_SENDER.save(storage, (&job.sender, request_id), &Empty {})?;
+    Ok(request_id)
+}
+
+/// The jobs whose request is currently being sent. We only learn the packet sequence in the
+/// reply of the `IbcMsg::SendPacket`, so the request IDs are parked here until then.
+pub const OUTGOING_REQUEST: Item<Vec<u64>> = Item::new("outgoing_request");
+
+/// A map from (channel ID, packet sequence) to the request IDs of the jobs sent in that packet.
+/// This contains multiple request IDs for batch requests.
+/// Entries are removed when the packet is acknowledged or timed out.
+pub const PENDING_REQUESTS: Map<(&str, u64), Vec<u64>> = Map::new("pending_requests");
+
+/// A beacon delivery to a dapp, i.e. everything needed to execute the callback
+#[cw_serde]
+pub struct Delivery {
+    /// The address of the dapp that requested the beacon
+    pub sender: Addr,
+    /// The job ID chosen by the dapp
+    pub job_id: String,
+    /// The point in time when the randomness was first published
+    pub published: Timestamp,
+    /// The randomness
+    pub randomness: HexBinary,
+    /// The ID of the request created by the proxy. This is unset for requests
+    /// created before the proxy started tracking jobs.
+    pub request_id: Option<u64>,
+    /// The number of retries of the callback so far
+    pub retries: u32,
+    /// The point in time after which the callback cannot be retried anymore
+    pub expires: Timestamp,
+}
+
+/// The deliveries whose callbacks are currently being executed. We need this to map the
+/// result of a callback to its delivery in the reply.
+///
+/// This is a stack because callbacks can be nested, e.g. when a dapp requests a cached
+/// beacon from within its callback. The reply of the innermost callback comes first.
+pub const CALLBACK_DELIVERIES: Deque<Delivery> = Deque::new("callback_deliveries");
+
+/// A map from (sender, request ID) to deliveries for which the callback failed.
+/// Those can be retried using `ExecuteMsg::RetryCallback`. Deliveries without a
+/// request ID are not stored since they cannot be identified uniquely.
+pub const FAILED_DELIVERIES: Map<(&Addr, u64), Delivery> = Map::new("failed_deliveries");
+
+/// Addresses allowed to request beacons in `AccessMode::Allowlist`
+pub const ALLOWLIST: Map<&Addr, Empty> = Map::new("allowlist");
+
+/// Addresses not allowed to request beacons in `AccessMode::Denylist`
+pub const DENYLIST: Map<&Addr, Empty> = Map::new("denylist");
+
+/// Prepaid credit of dapps. A map from (address, denom) to amount.
+pub const CREDITS: Map<(&Addr, &str), Uint128> = Map::new("credits");
+
+/// The total amount per denom the proxy holds on behalf of dapps, i.e. prepaid
+/// credit and prepaid subscription beacons. This cannot be withdrawn.
+pub const ESCROW: Map<&str, Uint128> = Map::new("escrow");
+
+#[cw_serde]
+pub enum CreditUsageKind {
+    /// Funds were deposited to the account
+    Deposit { depositor: Addr },
+    /// Funds were withdrawn from the account
+    Withdrawal {},
+    /// A beacon request was paid from the account
+    Payment { request_id: u64 },
+    /// The payment of a beacon request was refunded to the account
+    Refund { request_id: u64 },
+}
+
+/// A change of the credit of a dapp
+#[cw_serde]
+pub struct CreditUsage {
+    /// The block time of the change
+    pub time: Timestamp,
+    /// The amount added or removed
+    pub amount: Coin,
+    pub kind: CreditUsageKind,
+}
+
+/// A map from (address, index) to changes of the credit
+pub const CREDIT_HISTORY: Map<(&Addr, u64), CreditUsage> = Map::new("credit_history");
+
+/// The number of entries in the credit history of an address
+pub const CREDIT_HISTORY_LEN: Map<&Addr, u64> = Map::new("credit_history_len");
+
+/// Prices that replace `Config::prices` for individual dapps
+pub const PRICE_OVERRIDES: Map<&Addr, Vec<Coin>> = Map::new("price_overrides");
+
+/// A volume discount which applies once a dapp sent a certain number of requests
+/// in the current discount period
+#[cw_serde]
+pub struct DiscountTier {
+    /// The number of requests in the current period after which the discount applies
+    pub min_requests: u32,
+    /// The discount in percent (0-100)
+    pub discount_percent: u32,
+}
+
+#[cw_serde]
+pub struct Discounts {
+    /// The length of a discount period in seconds. Periods are aligned to the
+    /// Unix epoch.
+    pub period: u64,
+    /// The discount tiers, sorted by `min_requests` in ascending order
+    pub tiers: Vec<DiscountTier>,
+}
+
+pub const DISCOUNTS: Item<Discounts> = Item::new("discounts");
+
+/// The number of requests of a dapp in a discount period
+#[cw_serde]
+pub struct PeriodUsage {
+    /// The index of the discount period
+    pub period: u64,
+    pub requests: u32,
+}
+
+pub const PERIOD_USAGE: Map<&Addr, PeriodUsage> = Map::new("period_usage");
+
+/// A price increase for dapps with a high callback gas limit
+#[cw_serde]
+pub struct GasTier {
+    /// The registered callback gas limit from which on the increase applies
+    pub min_gas_limit: u64,
+    /// The price increase in percent
+    pub price_increase_percent: u32,
+}
+
+/// The range in which dapps can register their callback gas limit
+#[cw_serde]
+pub struct CallbackGasBounds {
+    pub min: u64,
+    pub max: u64,
+    /// The gas tiers, sorted by `min_gas_limit` in ascending order
+    pub tiers: Vec<GasTier>,
+}
+
+pub const CALLBACK_GAS_BOUNDS: Item<CallbackGasBounds> = Item::new("callback_gas_bounds");
+
+/// Callback gas limits registered by dapps. They replace `Config::callback_gas_limit`.
+pub const CALLBACK_GAS_LIMITS: Map<&Addr, u64> = Map::new("callback_gas_limits");
+
+/// Automatically sends IBCed unois to the payment contract in `OperationalMode::Funded`
+#[cw_serde]
+pub struct AutoTopUp {
+    /// The number of beacon requests after which a top-up is sent
+    pub every_requests: u32,
+    /// The amount of unois sent in each top-up
+    pub amount: Uint128,
+}
+
+/// Settings for topping up the payment contract from the proxy's balance
+#[cw_serde]
+pub struct TopUp {
+    /// The denom of the IBCed unois token
+    pub unois_denom: IbcDenom,
+    pub auto: Option<AutoTopUp>,
+}
+
+pub const TOP_UP: Item<TopUp> = Item::new("top_up");
+
+/// The number of beacon requests since the last automatic top-up
+pub const REQUESTS_SINCE_TOP_UP: Item<u32> = Item::new("requests_since_top_up");
+
+/// A prepaid series of beacon requests with a fixed interval between their `after` values.
+/// The next request is sent when the beacon of the previous one is delivered.
+#[cw_serde]
+pub struct Subscription {
+    /// The time in seconds between the `after` values of two requests
+    pub interval: u64,
+    /// The number of prepaid beacons that were not yet requested
+    pub remaining: u32,
+    /// The number of beacons requested so far. This is used to create the job IDs.
+    pub requested: u32,
+    /// The price paid per beacon. It is refunded for every remaining beacon on unsubscribe.
+    pub price: Coin,
+    /// The `after` value of the next request
+    pub next_after: Timestamp,
+    /// The request ID of the beacon request currently in flight
+    pub pending_request: Option<u64>,
+}
+
+/// Subscriptions by dapp address and job ID prefix
+pub const SUBSCRIPTIONS: Map<(&Addr, &str), Subscription> = Map::new("subscriptions");
+
+/// A map from request ID to the job ID prefix of the subscription the request was sent for
+pub const SUBSCRIPTION_REQUESTS: Map<u64, String> = Map::new("subscription_requests");
+
+/// Counts the requests in the current block and window for enforcing the rate limits
+#[cw_serde]
+#[derive(Default)]
+pub struct RequestCounter {
+    /// The height of the block the last request was made in
+    pub height: u64,
+    /// The number of requests in block `height`
+    pub block_requests: u32,
+    /// The index of the current window, i.e. time divided by the window length
+    pub window: u64,
+    /// The number of requests in the current window
+    pub window_requests: u32,
+    /// The number of requests in the window before the current one
+    pub previous_window_requests: u32,
+}
+
+pub const SENDER_REQUEST_COUNTERS: Map<&Addr, RequestCounter> = Map::new("sender_request_counters");
+
+pub const GLOBAL_REQUEST_COUNTER: Item<RequestCounter> = Item::new("global_request_counter");
+
+/// Usage counters of a single dapp or of all dapps
+#[cw_serde]
+#[derive(Default)]
+pub struct Stats {
+    /// The number of beacon requests
+    pub requests: u64,
+    /// The number of delivered beacons
+    pub deliveries: u64,
+    /// The number of callbacks that failed
+    pub failed_callbacks: u64,
+    /// The total amount paid minus refunds, one entry per denom
+    pub paid: Vec<Coin>,
+}
+
+pub const DAPP_STATS: Map<&Addr, Stats> = Map::new("dapp_stats");
+
+pub const GLOBAL_STATS: Item<Stats> = Item::new("global_stats");
+
+/// Enables the cache of delivered beacons
+#[cw_serde]
+pub struct BeaconCacheSettings {
+    /// The time in seconds a delivered beacon is kept in the cache
+    pub lifetime: u64,
+    /// The discount in percent for requests answered from the cache
+    pub discount_percent: u32,
+}
+
+pub const BEACON_CACHE_SETTINGS: Item<BeaconCacheSettings> = Item::new("beacon_cache_settings");
+
+/// A beacon delivered by the gateway
+#[cw_serde]
+pub struct CachedBeacon {
+    /// A RNG specific randomness source identifier, e.g. `drand:<network id>:<round>`
+    pub source_id: String,
+    /// The point in time when the randomness was first published
+    pub published: Timestamp,
+    /// The randomness
+    pub randomness: HexBinary,
+    /// The time after which the beacon is no longer served from the cache
+    pub expires: Timestamp,
+}
+
+/// A map from drand round to the beacon delivered for it
+pub const BEACON_CACHE: Map<u64, CachedBeacon> = Map::new("beacon_cache");
+
+/// The unois owed to the gateway in `OperationalMode::IbcPayBatched`
+#[cw_serde]
+pub struct Settlement {
+    /// The amount of unois not yet sent to the payment contract
+    pub debt: Uint128,
+    /// The time of the last settlement
+    pub last_settled: Timestamp,
+}
+
+pub const SETTLEMENT: Item<Settlement> = Item::new("settlement");
+
+/// Unois that was sent to the gateway's payment contract for requests which the gateway
+/// rejected without charging. It pays for the next requests before new unois is sent.
+pub const GATEWAY_PREPAID: Item<Uint128> = Item::new("gateway_prepaid");
+
+/// The time at which the proxy last pulled the beacon price from the gateway
+pub const LAST_BEACON_PRICE_REQUEST: Item<Timestamp> = Item::new("last_beacon_price_request");
+
 /// We use this value to get publish times that are independent of the current clock
 /// in test mode. We want the following rounds to be the result. To get there we use
 /// a starting time of 1677687597000000000 - 1 nanoseconds and then increment by 30 seconds.
@@ -0,0 +1,105 @@
+use cosmwasm_std::{Addr, Coin, StdResult, Storage};
+
+use crate::state::{Stats, DAPP_STATS, GLOBAL_STATS};
+
+/// Applies the update to the stats of the given dapp and to the global stats
+fn update_stats(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    update: impl Fn(&mut Stats),
+) -> StdResult<()> {
+    let mut stats = DAPP_STATS.may_load(storage, address)?.unwrap_or_default();
+    update(&mut stats);
+    DAPP_STATS.save(storage, address, &stats)?;
+
+    let mut stats = GLOBAL_STATS.may_load(storage)?.unwrap_or_default();
+    update(&mut stats);
+    GLOBAL_STATS.save(storage, &stats)
+}
+
+pub fn count_requests(storage: &mut dyn Storage, address: &Addr, count: u32) -> StdResult<()> {
+    update_stats(storage, address, |stats| stats.requests += count as u64)
+}
+
+pub fn count_delivery(storage: &mut dyn Storage, address: &Addr) -> StdResult<()> {
+    update_stats(storage, address, |stats| stats.deliveries += 1)
+}
+
+pub fn count_failed_callback(storage: &mut dyn Storage, address: &Addr) -> StdResult<()> {
+    update_stats(storage, address, |stats| stats.failed_callbacks += 1)
+}
+
+pub fn count_payment(storage: &mut dyn Storage, address: &Addr, amount: &Coin) -> StdResult<()> {
+    update_stats(storage, address, |stats| {
+        match stats
+            .paid
+            .iter_mut()
+            .find(|paid| paid.denom == amount.denom)
+        {
+            Some(paid) => paid.amount += amount.amount,
+            None => stats.paid.push(amount.clone()),
+        }
+    })
+}
+
+pub fn count_refund(storage: &mut dyn Storage, address: &Addr, amount: &Coin) -> StdResult<()> {
+    update_stats(storage, address, |stats| {
+        if let Some(paid) = stats
+            .paid
+            .iter_mut()
+            .find(|paid| paid.denom == amount.denom)
+        {
+            paid.amount = paid.amount.saturating_sub(amount.amount);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+
+    #[test]
+    fn stats_work() {
+        let mut storage = MockStorage::new();
+        let dapp1 = Addr::unchecked("dapp1");
+        let dapp2 = Addr::unchecked("dapp2");
+
+        count_requests(&mut storage, &dapp1, 3).unwrap();
+        count_payment(&mut storage, &dapp1, &Coin::new(300, "unoisx")).unwrap();
+        count_delivery(&mut storage, &dapp1).unwrap();
+        count_failed_callback(&mut storage, &dapp1).unwrap();
+        count_requests(&mut storage, &dapp2, 1).unwrap();
+        count_payment(&mut storage, &dapp2, &Coin::new(100, "unoisx")).unwrap();
+        count_payment(&mut storage, &dapp2, &Coin::new(7, "uatom")).unwrap();
+        count_refund(&mut storage, &dapp2, &Coin::new(100, "unoisx")).unwrap();
+
+        assert_eq!(
+            DAPP_STATS.load(&storage, &dapp1).unwrap(),
+            Stats {
+                requests: 3,
+                deliveries: 1,
+                failed_callbacks: 1,
+                paid: vec![Coin::new(300, "unoisx")],
+            }
+        );
+        assert_eq!(
+            DAPP_STATS.load(&storage, &dapp2).unwrap(),
+            Stats {
+                requests: 1,
+                deliveries: 0,
+                failed_callbacks: 0,
+                paid: vec![Coin::new(0, "unoisx"), Coin::new(7, "uatom")],
+            }
+        );
+        assert_eq!(
+            GLOBAL_STATS.load(&storage).unwrap(),
+            Stats {
+                requests: 4,
+                deliveries: 1,
+                failed_callbacks: 1,
+                paid: vec![Coin::new(300, "unoisx"), Coin::new(7, "uatom")],
+            }
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Payments in CW20 tokens.
+//!
+//! CW20 tokens are represented as `Coin`s with the denom `cw20:<contract address>`.
+//! This allows using them in prices, job funds and refunds like native tokens.
+
+use cosmwasm_std::{to_binary, Addr, BankMsg, Coin, CosmosMsg, QuerierWrapper, StdResult, WasmMsg};
+use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg};
+
+const CW20_DENOM_PREFIX: &str = "cw20:";
+
+/// The denom representing tokens of the given CW20 contract
+pub fn cw20_denom(contract: &Addr) -> String {
+    format!("{CW20_DENOM_PREFIX}{contract}")
+}
+
+/// The CW20 contract address of the denom or `None` for native denoms
+pub fn cw20_contract(denom: &str) -> Option<&str> {
+    denom.strip_prefix(CW20_DENOM_PREFIX)
+}
+
+/// Creates the messages to send the amounts to the recipient. Native coins are sent
+/// in a single bank message, CW20 tokens are transferred one by one.
+pub fn send_msgs(recipient: &Addr, amounts: &[Coin]) -> StdResult<Vec<CosmosMsg>> {
+    let mut native = vec![];
+    let mut msgs = vec![];
+    for amount in amounts {
+        match cw20_contract(&amount.denom) {
+            Some(contract) => msgs.push(
+                WasmMsg::Execute {
+                    contract_addr: contract.to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: recipient.to_string(),
+                        amount: amount.amount,
+                    })?,
+                    funds: vec![],
+                }
+                .into(),
+            ),
+            None => native.push(amount.clone()),
+        }
+    }
+    if !native.is_empty() {
+        msgs.insert(
+            0,
+            BankMsg::Send {
+                to_address: recipient.to_string(),
+                amount: native,
+            }
+            .into(),
+        );
+    }
+    Ok(msgs)
+}
+
+/// Queries the balance of native or CW20 tokens
+pub fn query_balance(querier: &QuerierWrapper, address: &Addr, denom: String) -> StdResult<Coin> {
+    match cw20_contract(&denom) {
+        Some(contract) => {
+            let BalanceResponse { balance } = querier.query_wasm_smart(
+                contract,
+                &Cw20QueryMsg::Balance {
+                    address: address.to_string(),
+                },
+            )?;
+            Ok(Coin {
+                denom,
+                amount: balance,
+            })
+        }
+        None => querier.query_balance(address, denom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::{coin, Uint128};
+
+    #[test]
+    fn cw20_denom_works() {
+        let denom = cw20_denom(&Addr::unchecked("token"));
+        assert_eq!(denom, "cw20:token");
+        assert_eq!(cw20_contract(&denom), Some("token"));
+        assert_eq!(cw20_contract("unoisx"), None);
+    }
+
+    #[test]
+    fn send_msgs_works() {
+        let recipient = Addr::unchecked("dapp");
+        assert_eq!(send_msgs(&recipient, &[]).unwrap(), vec![]);
+
+        let msgs = send_msgs(
+            &recipient,
+            &[coin(1, "cw20:token"), coin(2, "unoisx"), coin(3, "uatom")],
+        )
+        .unwrap();
+        assert_eq!(
+            msgs,
+            vec![
+                BankMsg::Send {
+                    to_address: "dapp".to_string(),
+                    amount: vec![coin(2, "unoisx"), coin(3, "uatom")],
+                }
+                .into(),
+                WasmMsg::Execute {
+                    contract_addr: "token".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: "dapp".to_string(),
+                        amount: Uint128::new(1),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }
+                .into(),
+            ]
+        );
+    }
+}
@@ -126,6 +126,7 @@ fn integration_test() {
             payment_code_id: code_id_nois_payment,
             payment_initial_funds: payment_initial(),
             sink: Addr::unchecked(SINK),
+            fee_split: nois_gateway::state::default_fee_split(),
         }
     );
 
@@ -167,6 +168,7 @@ fn integration_test() {
             price: None,
             drand_addr: Some(addr_nois_drand.to_string()),
             payment_initial_funds: None,
+            fee_split: None,
         },
         &[],
     )
@@ -184,6 +186,7 @@ fn integration_test() {
             payment_code_id: code_id_nois_payment,
             payment_initial_funds: payment_initial(),
             sink: Addr::unchecked(SINK),
+            fee_split: nois_gateway::state::default_fee_split(),
         }
     );
 
@@ -206,6 +209,7 @@ fn integration_test() {
                 test_mode: false,
                 callback_gas_limit: 500_000,
                 mode: nois_proxy::state::OperationalMode::Funded {},
+                access_mode: None,
             },
             &[],
             "Nois-Proxy",
@@ -229,7 +233,9 @@ fn integration_test() {
                 payment: None,
                 nois_beacon_price: Uint128::zero(),
                 nois_beacon_price_updated: Timestamp::from_seconds(0),
-                mode: nois_proxy::state::OperationalMode::Funded {}
+                mode: nois_proxy::state::OperationalMode::Funded {},
+                access_mode: nois_proxy::state::AccessMode::Open {},
+                rate_limits: nois_proxy::state::RateLimits::default(),
             },
         }
     );
@@ -78,6 +78,7 @@ fn integration_test() {
             payment_code_id: PAYMENT,
             payment_initial_funds: payment_initial(),
             sink: Addr::unchecked(SINK),
+            fee_split: nois_gateway::state::default_fee_split(),
         }
     );
 
@@ -89,6 +90,7 @@ fn integration_test() {
         price: None,
         drand_addr: Some(DRAND.to_string()),
         payment_initial_funds: None,
+        fee_split: None,
     };
     let _resp = app
         .execute_contract(
@@ -113,6 +115,7 @@ fn integration_test() {
             payment_code_id: PAYMENT,
             payment_initial_funds: payment_initial(),
             sink: Addr::unchecked(SINK),
+            fee_split: nois_gateway::state::default_fee_split(),
         }
     );
 
@@ -135,6 +138,7 @@ fn integration_test() {
                 test_mode: false,
                 callback_gas_limit: 500_000,
                 mode: nois_proxy::state::OperationalMode::Funded {},
+                access_mode: None,
             },
             &[],
             "Nois-Proxy",
@@ -157,7 +161,9 @@ fn integration_test() {
                 payment: None,
                 nois_beacon_price: Uint128::zero(),
                 nois_beacon_price_updated: Timestamp::from_seconds(0),
-                mode: nois_proxy::state::OperationalMode::Funded {}
+                mode: nois_proxy::state::OperationalMode::Funded {},
+                access_mode: nois_proxy::state::AccessMode::Open {},
+                rate_limits: nois_proxy::state::RateLimits::default(),
             },
         }
     );
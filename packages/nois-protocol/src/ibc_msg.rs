@@ -2,6 +2,7 @@ use cosmwasm_schema::cw_serde;
 use cosmwasm_std::{from_slice, to_binary, Binary, HexBinary, Timestamp, Uint128};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::fmt::Display;
 
 /// This is the message we send over the IBC channel from nois-proxy to nois-gateway.
 #[cw_serde]
@@ -13,6 +14,16 @@ pub enum InPacket {
         /// The origin data set by the proxy in a proxy specific format.
         origin: Binary,
     },
+    /// Requests one beacon for multiple jobs with the same `after` value.
+    /// The gateway delivers the beacon to each origin separately and acknowledges
+    /// the packet with the same ack as a single `RequestBeacon`.
+    RequestBeaconBatch {
+        /// Beacon publish time must be > `after`
+        after: Timestamp,
+        /// The origin data of every job set by the proxy in a proxy specific format.
+        /// Must contain between 1 and `MAX_REQUEST_BEACON_BATCH_SIZE` elements.
+        origins: Vec<Binary>,
+    },
     /// Requests the current price per beacon. This can change over time and potentially
     /// change per channel ID.
     /// The proxy can pull the beacon price but should also expect price updates to get pushed.
@@ -109,9 +120,17 @@ impl StdAck {
         StdAck::Result(serialized)
     }
 
-    /// Creates an error ack
+    /// Creates an error ack.
+    ///
+    /// Errors starting with the prefix reserved for coded errors get an extra prefix
+    /// such that they are never mistaken for an error created by [`StdAck::error_with_code`].
     pub fn error(err: impl Into<String>) -> Self {
-        StdAck::Error(err.into())
+        let err = err.into();
+        if err.starts_with(ERROR_CODE_PREFIX) {
+            StdAck::Error(format!("error: {err}"))
+        } else {
+            StdAck::Error(err)
+        }
     }
 
     pub fn unwrap(self) -> Binary {
@@ -131,6 +150,52 @@ impl StdAck {
             StdAck::Error(err) => err,
         }
     }
+
+    /// Creates an error ack with a machine-readable code. The error string is the
+    /// reserved prefix, the code, ": " and the description.
+    pub fn error_with_code(code: AckErrorCode, err: impl Display) -> Self {
+        StdAck::Error(format!("{ERROR_CODE_PREFIX}{}: {err}", code.as_str()))
+    }
+
+    /// The code of an error ack created by [`StdAck::error_with_code`]
+    pub fn error_code(&self) -> Option<AckErrorCode> {
+        match self {
+            StdAck::Result(_) => None,
+            StdAck::Error(err) => {
+                let (code, _) = err.strip_prefix(ERROR_CODE_PREFIX)?.split_once(": ")?;
+                AckErrorCode::parse(code)
+            }
+        }
+    }
+}
+
+/// The prefix of error acks created by [`StdAck::error_with_code`].
+/// [`StdAck::error`] never creates an error string starting with it.
+const ERROR_CODE_PREFIX: &str = "nois-ack-code:";
+
+/// Error codes which allow the other side of the channel to handle failed packets
+/// without parsing the error description
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AckErrorCode {
+    /// The customer's payment contract cannot pay for the requested beacons.
+    /// The gateway did not charge anything.
+    InsufficientPaymentBalance,
+}
+
+impl AckErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AckErrorCode::InsufficientPaymentBalance => "insufficient_payment_balance",
+        }
+    }
+
+    fn parse(code: &str) -> Option<Self> {
+        match code {
+            "insufficient_payment_balance" => Some(AckErrorCode::InsufficientPaymentBalance),
+            _ => None,
+        }
+    }
 }
 
 impl From<StdAck> for Binary {
@@ -4,22 +4,29 @@ mod ibc_msg;
 use cosmwasm_std::IbcOrder;
 
 pub use checks::{check_order, check_version, ChannelError};
-pub use ibc_msg::{InPacket, InPacketAck, OutPacket, OutPacketAck, StdAck};
+pub use ibc_msg::{AckErrorCode, InPacket, InPacketAck, OutPacket, OutPacketAck, StdAck};
 
 pub const IBC_APP_VERSION: &str = "nois-v7";
 pub const APP_ORDER: IbcOrder = IbcOrder::Unordered;
 // we use this for tests to ensure it is rejected
 pub const BAD_APP_ORDER: IbcOrder = IbcOrder::Ordered;
 
-// We currently have no way for dapps to process timeouts. If we wanted that,
-// we had to introduce a different callback which then needs handling by the
-// dapp developer. For now, let's assume all requests relayed to Nois and all
-// deliveries are relayed to the consumer chain. In order to avoid unintended
-// timeouts due to relayer downtime, we set the lifetime to 100 days.
+// The proxy refunds timed out beacon requests and informs the dapp with a timeout
+// callback. But this requires handling by the dapp developer, so let's assume all
+// requests relayed to Nois and all deliveries are relayed to the consumer chain.
+// In order to avoid unintended timeouts due to relayer downtime, we set the
+// lifetime to 100 days.
 pub const REQUEST_BEACON_PACKET_LIFETIME: u64 = 100 * 24 * 3600; // seconds
 pub const DELIVER_BEACON_PACKET_LIFETIME: u64 = 100 * 24 * 3600; // seconds
 pub const BEACON_PRICE_PACKET_LIFETIME: u64 = 100 * 24 * 3600; // seconds
 pub const WELCOME_PACKET_LIFETIME: u64 = 100 * 24 * 3600; // seconds
 
+/// A beacon price pulled by the proxy is only useful while it is recent, so those packets
+/// time out early. This should not exceed the proxy's refresh interval.
+pub const PULL_BEACON_PRICE_PACKET_LIFETIME: u64 = 3600; // seconds
+
+/// The maximum number of origins in a `InPacket::RequestBeaconBatch`
+pub const MAX_REQUEST_BEACON_BATCH_SIZE: usize = 50;
+
 /// Token transfers time out earlier than other messages to avoid locking funds for too long
 pub const TRANSFER_PACKET_LIFETIME: u64 = 24 * 3600; // seconds
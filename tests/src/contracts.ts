@@ -56,6 +56,17 @@ export interface GatewayInstantiateMsg {
   readonly sink: string;
 }
 
+export type GatewayFeeDestination =
+  | { readonly burn: Record<string, never> }
+  | { readonly community_pool: Record<string, never> }
+  | { readonly relayer: Record<string, never> }
+  | { readonly address: { readonly address: string } };
+
+export interface GatewayFeeShare {
+  readonly destination: GatewayFeeDestination;
+  readonly percent: number;
+}
+
 export interface GatewayExecuteMsg {
   readonly add_verified_round?: {
     readonly round: number;
@@ -67,6 +78,18 @@ export interface GatewayExecuteMsg {
     readonly price?: null | Coin;
     readonly drand_addr?: null | string;
     readonly payment_initial_funds?: null | Coin;
+    readonly fee_split?: null | GatewayFeeShare[];
+  };
+  readonly process_jobs?: {
+    readonly round: number;
+    readonly max: number;
+  };
+  readonly broadcast_price?: {
+    readonly limit?: null | number;
+  };
+  readonly set_customer_price?: {
+    readonly channel_id: string;
+    readonly price?: null | Coin;
   };
 }
 
@@ -74,6 +97,7 @@ export interface GatewayQueriedCustomer {
   readonly channel_id: string;
   readonly payment: string;
   readonly requested_beacons: number;
+  readonly price: null | Coin;
 }
 
 export interface GatewayCustomerResponse {
@@ -91,6 +115,18 @@ export interface ProxyOperationalMode {
   readonly ibc_pay?: {
     readonly unois_denom: IbcDenom;
   };
+  readonly ibc_pay_batched?: {
+    readonly unois_denom: IbcDenom;
+    readonly threshold: string;
+    /** Interval in seconds */
+    readonly interval: number;
+  };
+}
+
+export interface ProxyAccessMode {
+  readonly open?: {};
+  readonly allowlist?: {};
+  readonly denylist?: {};
 }
 
 export interface ProxyInstantiateMsg {
@@ -99,6 +135,7 @@ export interface ProxyInstantiateMsg {
   readonly test_mode: boolean;
   readonly callback_gas_limit: number;
   readonly mode: ProxyOperationalMode;
+  readonly access_mode?: ProxyAccessMode;
 }
 
 export interface ProxyExecuteMsg {
@@ -110,6 +147,22 @@ export interface ProxyExecuteMsg {
     /** Timestamp in nanoseconds since epoch */
     readonly after: string;
   };
+  readonly get_randomness_batch?: {
+    readonly job_ids: Array<string>;
+    /** Timestamp in nanoseconds since epoch */
+    readonly after: string;
+  };
+  readonly subscribe?: {
+    /** Interval in seconds */
+    readonly interval: number;
+    readonly count: number;
+    readonly job_id_prefix: string;
+  };
+  readonly unsubscribe?: {
+    readonly job_id_prefix: string;
+  };
+  readonly refresh_beacon_price?: {};
+  readonly settle?: {};
   readonly withdraw?: {
     readonly denom: string;
     readonly amount: null | string;
@@ -329,6 +329,10 @@ test.serial("demo contract runs into out of gas in callback", async (t) => {
         key: "log",
         value: "codespace: sdk, code: 11",
       },
+      {
+        key: "request_id",
+        value: "1",
+      },
     ]);
   }
 
@@ -379,6 +383,10 @@ test.serial("demo contract runs into out of gas in callback", async (t) => {
         key: "log",
         value: "codespace: sdk, code: 11",
       },
+      {
+        key: "request_id",
+        value: "2",
+      },
     ]);
   }
 });